@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE IF EXISTS `completions`;
+ALTER TABLE `todos` DROP COLUMN `repeat_from`;
@@ -0,0 +1,10 @@
+-- Your SQL goes here
+ALTER TABLE `todos` ADD COLUMN `repeat_from` TEXT NOT NULL DEFAULT 'Due';
+
+CREATE TABLE `completions`(
+	`id` INTEGER NOT NULL PRIMARY KEY,
+	`todo_id` INTEGER NOT NULL,
+	`completed_at` TIMESTAMP NOT NULL,
+	`due` TIMESTAMP,
+	FOREIGN KEY (`todo_id`) REFERENCES `todos`(`id`)
+);
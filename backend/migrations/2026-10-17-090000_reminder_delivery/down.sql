@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX IF EXISTS `reminders_pending`;
+ALTER TABLE `reminders` DROP COLUMN `delivered_at`;
@@ -0,0 +1,4 @@
+-- Your SQL goes here
+ALTER TABLE `reminders` ADD COLUMN `delivered_at` TIMESTAMP;
+
+CREATE INDEX `reminders_pending` ON `reminders`(`reminder`) WHERE `delivered_at` IS NULL;
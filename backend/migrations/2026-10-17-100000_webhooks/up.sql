@@ -0,0 +1,24 @@
+-- Your SQL goes here
+CREATE TABLE `webhooks`(
+	`id` INTEGER NOT NULL PRIMARY KEY,
+	`user_id` INTEGER NOT NULL,
+	`url` TEXT NOT NULL,
+	`secret` TEXT NOT NULL,
+	`events` TEXT NOT NULL,
+	`active` BOOL NOT NULL,
+	`created_at` TIMESTAMP NOT NULL,
+	FOREIGN KEY (`user_id`) REFERENCES `users`(`id`)
+);
+
+CREATE TABLE `webhook_deliveries`(
+	`id` INTEGER NOT NULL PRIMARY KEY,
+	`webhook_id` INTEGER NOT NULL,
+	`delivery_id` TEXT NOT NULL,
+	`event` TEXT NOT NULL,
+	`attempt` INTEGER NOT NULL,
+	`status_code` INTEGER,
+	`error` TEXT,
+	`succeeded` BOOL NOT NULL,
+	`attempted_at` TIMESTAMP NOT NULL,
+	FOREIGN KEY (`webhook_id`) REFERENCES `webhooks`(`id`)
+);
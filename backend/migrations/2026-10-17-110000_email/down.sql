@@ -0,0 +1,8 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE `users` DROP COLUMN `last_digest_on`;
+ALTER TABLE `users` DROP COLUMN `daily_digest`;
+ALTER TABLE `users` DROP COLUMN `email_reminders`;
+ALTER TABLE `users` DROP COLUMN `email_token_expires_at`;
+ALTER TABLE `users` DROP COLUMN `email_token_hash`;
+ALTER TABLE `users` DROP COLUMN `email_verified`;
+ALTER TABLE `users` DROP COLUMN `email`;
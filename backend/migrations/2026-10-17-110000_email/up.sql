@@ -0,0 +1,8 @@
+-- Your SQL goes here
+ALTER TABLE `users` ADD COLUMN `email` TEXT;
+ALTER TABLE `users` ADD COLUMN `email_verified` BOOL NOT NULL DEFAULT 0;
+ALTER TABLE `users` ADD COLUMN `email_token_hash` TEXT;
+ALTER TABLE `users` ADD COLUMN `email_token_expires_at` TIMESTAMP;
+ALTER TABLE `users` ADD COLUMN `email_reminders` BOOL NOT NULL DEFAULT 1;
+ALTER TABLE `users` ADD COLUMN `daily_digest` BOOL NOT NULL DEFAULT 0;
+ALTER TABLE `users` ADD COLUMN `last_digest_on` DATE;
@@ -0,0 +1,18 @@
+-- Your SQL goes here
+CREATE TABLE `sessions`(
+	`id` INTEGER NOT NULL PRIMARY KEY,
+	`user_id` INTEGER NOT NULL,
+	`created_at` TIMESTAMP NOT NULL,
+	`revoked_at` TIMESTAMP,
+	FOREIGN KEY (`user_id`) REFERENCES `users`(`id`)
+);
+
+CREATE TABLE `refresh_tokens`(
+	`id` INTEGER NOT NULL PRIMARY KEY,
+	`session_id` INTEGER NOT NULL,
+	`token_hash` TEXT NOT NULL UNIQUE,
+	`created_at` TIMESTAMP NOT NULL,
+	`expires_at` TIMESTAMP NOT NULL,
+	`used_at` TIMESTAMP,
+	FOREIGN KEY (`session_id`) REFERENCES `sessions`(`id`)
+);
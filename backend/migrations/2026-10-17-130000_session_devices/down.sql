@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE `sessions` DROP COLUMN `last_seen_at`;
+ALTER TABLE `sessions` DROP COLUMN `ip`;
+ALTER TABLE `sessions` DROP COLUMN `user_agent`;
@@ -0,0 +1,5 @@
+-- Your SQL goes here
+ALTER TABLE `sessions` ADD COLUMN `user_agent` TEXT;
+ALTER TABLE `sessions` ADD COLUMN `ip` TEXT;
+ALTER TABLE `sessions` ADD COLUMN `last_seen_at` TIMESTAMP NOT NULL DEFAULT '1970-01-01 00:00:00';
+UPDATE `sessions` SET `last_seen_at` = `created_at`;
@@ -0,0 +1,12 @@
+-- Your SQL goes here
+ALTER TABLE `users` ADD COLUMN `totp_secret` TEXT;
+ALTER TABLE `users` ADD COLUMN `totp_enabled` BOOL NOT NULL DEFAULT 0;
+ALTER TABLE `users` ADD COLUMN `totp_last_step` BIGINT;
+
+CREATE TABLE `recovery_codes`(
+	`id` INTEGER NOT NULL PRIMARY KEY,
+	`user_id` INTEGER NOT NULL,
+	`code_hash` TEXT NOT NULL,
+	`used_at` TIMESTAMP,
+	FOREIGN KEY (`user_id`) REFERENCES `users`(`id`)
+);
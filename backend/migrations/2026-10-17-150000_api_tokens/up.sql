@@ -0,0 +1,12 @@
+-- Your SQL goes here
+CREATE TABLE `api_tokens`(
+	`id` INTEGER NOT NULL PRIMARY KEY,
+	`user_id` INTEGER NOT NULL,
+	`name` TEXT NOT NULL,
+	`token_hash` TEXT NOT NULL UNIQUE,
+	`scopes` TEXT NOT NULL,
+	`created_at` TIMESTAMP NOT NULL,
+	`expires_at` TIMESTAMP,
+	`last_used_at` TIMESTAMP,
+	FOREIGN KEY (`user_id`) REFERENCES `users`(`id`)
+);
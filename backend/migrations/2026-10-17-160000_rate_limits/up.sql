@@ -0,0 +1,8 @@
+-- Your SQL goes here
+CREATE TABLE `rate_limits`(
+	`key` TEXT NOT NULL PRIMARY KEY,
+	`tokens` DOUBLE NOT NULL,
+	`updated_at` TIMESTAMP NOT NULL,
+	`failures` INTEGER NOT NULL DEFAULT 0,
+	`locked_until` TIMESTAMP
+);
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE `users` DROP COLUMN `password_reset_expires_at`;
+ALTER TABLE `users` DROP COLUMN `password_reset_hash`;
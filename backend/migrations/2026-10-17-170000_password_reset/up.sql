@@ -0,0 +1,3 @@
+-- Your SQL goes here
+ALTER TABLE `users` ADD COLUMN `password_reset_hash` TEXT;
+ALTER TABLE `users` ADD COLUMN `password_reset_expires_at` TIMESTAMP;
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX IF EXISTS `users_oidc_identity`;
+ALTER TABLE `users` DROP COLUMN `oidc_subject`;
+ALTER TABLE `users` DROP COLUMN `oidc_issuer`;
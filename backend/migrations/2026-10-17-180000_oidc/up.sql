@@ -0,0 +1,5 @@
+-- Your SQL goes here
+ALTER TABLE `users` ADD COLUMN `oidc_issuer` TEXT;
+ALTER TABLE `users` ADD COLUMN `oidc_subject` TEXT;
+
+CREATE UNIQUE INDEX `users_oidc_identity` ON `users`(`oidc_issuer`, `oidc_subject`);
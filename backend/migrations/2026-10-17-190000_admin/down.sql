@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE IF EXISTS `settings`;
+ALTER TABLE `users` DROP COLUMN `disabled_at`;
+ALTER TABLE `users` DROP COLUMN `role`;
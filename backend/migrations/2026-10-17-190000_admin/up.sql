@@ -0,0 +1,12 @@
+-- Your SQL goes here
+ALTER TABLE `users` ADD COLUMN `role` TEXT NOT NULL DEFAULT 'user';
+ALTER TABLE `users` ADD COLUMN `disabled_at` TIMESTAMP;
+
+-- Existing instances get the same bootstrap as new ones: the first user
+-- registered is the admin.
+UPDATE `users` SET `role` = 'admin' WHERE `id` = (SELECT MIN(`id`) FROM `users`);
+
+CREATE TABLE `settings`(
+	`key` TEXT NOT NULL PRIMARY KEY,
+	`value` TEXT NOT NULL
+);
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE IF EXISTS `invites`;
+DROP INDEX IF EXISTS `users_username_nocase`;
+DROP TABLE IF EXISTS `username_renames`;
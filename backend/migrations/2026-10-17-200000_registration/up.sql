@@ -0,0 +1,26 @@
+-- Your SQL goes here
+-- Usernames that only differ in case can't coexist under the new index, so
+-- all but the oldest of each such group get their id appended. The old names
+-- are kept so an admin can let the owners of these accounts know.
+CREATE TABLE `username_renames`(
+	`user_id` INTEGER NOT NULL PRIMARY KEY,
+	`old_username` TEXT NOT NULL,
+	FOREIGN KEY (`user_id`) REFERENCES `users`(`id`)
+);
+INSERT INTO `username_renames` SELECT `id`, `username` FROM `users`
+WHERE `id` NOT IN (SELECT MIN(`id`) FROM `users` GROUP BY `username` COLLATE NOCASE);
+UPDATE `users` SET `username` = `username` || '-' || `id`
+WHERE `id` NOT IN (SELECT MIN(`id`) FROM `users` GROUP BY `username` COLLATE NOCASE);
+
+CREATE UNIQUE INDEX `users_username_nocase` ON `users`(`username` COLLATE NOCASE);
+
+CREATE TABLE `invites`(
+	`id` INTEGER NOT NULL PRIMARY KEY,
+	`created_by` INTEGER NOT NULL,
+	`code_hash` TEXT NOT NULL UNIQUE,
+	`max_uses` INTEGER,
+	`uses` INTEGER NOT NULL DEFAULT 0,
+	`created_at` TIMESTAMP NOT NULL,
+	`expires_at` TIMESTAMP,
+	FOREIGN KEY (`created_by`) REFERENCES `users`(`id`)
+);
@@ -0,0 +1,17 @@
+-- Your SQL goes here
+CREATE TABLE `projects`(
+	`id` INTEGER NOT NULL PRIMARY KEY,
+	`user_id` INTEGER NOT NULL,
+	`name` TEXT NOT NULL,
+	`color` TEXT,
+	`icon` TEXT,
+	`sort_order` INTEGER NOT NULL DEFAULT 0,
+	`archived` BOOL NOT NULL DEFAULT 0,
+	`created_at` TIMESTAMP NOT NULL,
+	FOREIGN KEY (`user_id`) REFERENCES `users`(`id`)
+);
+
+-- Todos without a project are in the user's Inbox.
+ALTER TABLE `todos` ADD COLUMN `project_id` INTEGER;
+
+CREATE INDEX `todos_project_id` ON `todos`(`project_id`);
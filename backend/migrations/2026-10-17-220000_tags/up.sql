@@ -0,0 +1,20 @@
+-- Your SQL goes here
+CREATE TABLE `tags`(
+	`id` INTEGER NOT NULL PRIMARY KEY,
+	`user_id` INTEGER NOT NULL,
+	`name` TEXT NOT NULL,
+	FOREIGN KEY (`user_id`) REFERENCES `users`(`id`)
+);
+
+-- Tag names are matched without regard to case.
+CREATE UNIQUE INDEX `tags_user_id_name` ON `tags`(`user_id`, `name` COLLATE NOCASE);
+
+CREATE TABLE `todo_tags`(
+	`todo_id` INTEGER NOT NULL,
+	`tag_id` INTEGER NOT NULL,
+	PRIMARY KEY (`todo_id`, `tag_id`),
+	FOREIGN KEY (`todo_id`) REFERENCES `todos`(`id`),
+	FOREIGN KEY (`tag_id`) REFERENCES `tags`(`id`)
+);
+
+CREATE INDEX `todo_tags_tag_id` ON `todo_tags`(`tag_id`);
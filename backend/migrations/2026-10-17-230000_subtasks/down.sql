@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX IF EXISTS `todos_parent_id`;
+ALTER TABLE `todos` DROP COLUMN `auto_complete`;
+ALTER TABLE `todos` DROP COLUMN `parent_id`;
@@ -0,0 +1,6 @@
+-- Your SQL goes here
+-- Top-level todos have no parent.
+ALTER TABLE `todos` ADD COLUMN `parent_id` INTEGER;
+ALTER TABLE `todos` ADD COLUMN `auto_complete` BOOL NOT NULL DEFAULT 0;
+
+CREATE INDEX `todos_parent_id` ON `todos`(`parent_id`);
@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX IF EXISTS `todos_user_id_rank`;
+ALTER TABLE `todos` DROP COLUMN `rank`;
+ALTER TABLE `todos` DROP COLUMN `status`;
+ALTER TABLE `todos` DROP COLUMN `priority`;
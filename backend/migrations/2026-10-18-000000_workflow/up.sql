@@ -0,0 +1,15 @@
+-- Your SQL goes here
+ALTER TABLE `todos` ADD COLUMN `priority` TEXT NOT NULL DEFAULT 'none';
+ALTER TABLE `todos` ADD COLUMN `status` TEXT NOT NULL DEFAULT 'todo';
+ALTER TABLE `todos` ADD COLUMN `rank` TEXT NOT NULL DEFAULT '';
+
+UPDATE `todos` SET `status` = 'done' WHERE `completed` = 1;
+
+-- Existing todos keep the order they were created in. Ranks can't end in
+-- '0', since nothing would sort between them and the rank before.
+UPDATE `todos` SET `rank` = CASE
+	WHEN `id` % 10 = 0 THEN printf('%08di', `id`)
+	ELSE printf('%08d', `id`)
+END;
+
+CREATE INDEX `todos_user_id_rank` ON `todos`(`user_id`, `rank`);
@@ -0,0 +1,17 @@
+-- Your SQL goes here
+-- A project's creator is its owner without a row here. Invitations are rows
+-- that haven't been accepted yet.
+CREATE TABLE `project_members`(
+	`project_id` INTEGER NOT NULL,
+	`user_id` INTEGER NOT NULL,
+	`role` TEXT NOT NULL,
+	`invited_by` INTEGER NOT NULL,
+	`invited_at` TIMESTAMP NOT NULL,
+	`accepted_at` TIMESTAMP,
+	PRIMARY KEY (`project_id`, `user_id`),
+	FOREIGN KEY (`project_id`) REFERENCES `projects`(`id`),
+	FOREIGN KEY (`user_id`) REFERENCES `users`(`id`),
+	FOREIGN KEY (`invited_by`) REFERENCES `users`(`id`)
+);
+
+CREATE INDEX `project_members_user_id` ON `project_members`(`user_id`);
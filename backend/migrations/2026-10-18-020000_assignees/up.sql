@@ -0,0 +1,13 @@
+-- Your SQL goes here
+CREATE TABLE `todo_assignees`(
+	`todo_id` INTEGER NOT NULL,
+	`user_id` INTEGER NOT NULL,
+	`assigned_by` INTEGER NOT NULL,
+	`assigned_at` TIMESTAMP NOT NULL,
+	PRIMARY KEY (`todo_id`, `user_id`),
+	FOREIGN KEY (`todo_id`) REFERENCES `todos`(`id`),
+	FOREIGN KEY (`user_id`) REFERENCES `users`(`id`),
+	FOREIGN KEY (`assigned_by`) REFERENCES `users`(`id`)
+);
+
+CREATE INDEX `todo_assignees_user_id` ON `todo_assignees`(`user_id`);
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE IF EXISTS `comment_mentions`;
+DROP TABLE IF EXISTS `comments`;
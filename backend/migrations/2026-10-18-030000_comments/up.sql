@@ -0,0 +1,24 @@
+-- Your SQL goes here
+CREATE TABLE `comments`(
+	`id` INTEGER NOT NULL PRIMARY KEY,
+	`todo_id` INTEGER NOT NULL,
+	`user_id` INTEGER NOT NULL,
+	`body` TEXT NOT NULL,
+	`created_at` TIMESTAMP NOT NULL,
+	`edited_at` TIMESTAMP,
+	FOREIGN KEY (`todo_id`) REFERENCES `todos`(`id`),
+	FOREIGN KEY (`user_id`) REFERENCES `users`(`id`)
+);
+
+CREATE INDEX `comments_todo_id` ON `comments`(`todo_id`, `created_at`);
+
+-- The users a comment @mentions.
+CREATE TABLE `comment_mentions`(
+	`comment_id` INTEGER NOT NULL,
+	`user_id` INTEGER NOT NULL,
+	PRIMARY KEY (`comment_id`, `user_id`),
+	FOREIGN KEY (`comment_id`) REFERENCES `comments`(`id`),
+	FOREIGN KEY (`user_id`) REFERENCES `users`(`id`)
+);
+
+CREATE INDEX `comment_mentions_user_id` ON `comment_mentions`(`user_id`);
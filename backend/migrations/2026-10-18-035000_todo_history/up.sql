@@ -0,0 +1,16 @@
+-- Your SQL goes here
+-- Who changed which field of a todo, and from what to what. Values are
+-- stored as JSON.
+CREATE TABLE `todo_history`(
+	`id` INTEGER NOT NULL PRIMARY KEY,
+	`todo_id` INTEGER NOT NULL,
+	`user_id` INTEGER NOT NULL,
+	`field` TEXT NOT NULL,
+	`old_value` TEXT,
+	`new_value` TEXT,
+	`changed_at` TIMESTAMP NOT NULL,
+	FOREIGN KEY (`todo_id`) REFERENCES `todos`(`id`),
+	FOREIGN KEY (`user_id`) REFERENCES `users`(`id`)
+);
+
+CREATE INDEX `todo_history_todo_id` ON `todo_history`(`todo_id`, `changed_at`);
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX IF EXISTS `todos_deleted_at`;
+ALTER TABLE `todos` DROP COLUMN `deleted_at`;
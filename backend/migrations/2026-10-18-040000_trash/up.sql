@@ -0,0 +1,6 @@
+-- Your SQL goes here
+-- Set when a todo is moved to the trash; trashed todos are purged once
+-- they've been there for the retention period.
+ALTER TABLE `todos` ADD COLUMN `deleted_at` TIMESTAMP;
+
+CREATE INDEX `todos_deleted_at` ON `todos`(`deleted_at`);
@@ -0,0 +1,35 @@
+-- This file should undo anything in `up.sql`
+CREATE TABLE `users_old`(
+	`id` INTEGER NOT NULL PRIMARY KEY,
+	`username` TEXT NOT NULL,
+	`password` TEXT NOT NULL,
+	`email` TEXT,
+	`email_verified` BOOL NOT NULL DEFAULT 0,
+	`email_token_hash` TEXT,
+	`email_token_expires_at` TIMESTAMP,
+	`email_reminders` BOOL NOT NULL DEFAULT 1,
+	`daily_digest` BOOL NOT NULL DEFAULT 0,
+	`last_digest_on` DATE,
+	`totp_secret` TEXT,
+	`totp_enabled` BOOL NOT NULL DEFAULT 0,
+	`totp_last_step` BIGINT,
+	`password_reset_hash` TEXT,
+	`password_reset_expires_at` TIMESTAMP,
+	`oidc_issuer` TEXT,
+	`oidc_subject` TEXT,
+	`role` TEXT NOT NULL DEFAULT 'user',
+	`disabled_at` TIMESTAMP
+);
+-- Accounts without a password get an empty one, which can't be signed in
+-- with.
+INSERT INTO `users_old` SELECT `id`, `username`, COALESCE(`password`, ''), `email`,
+	`email_verified`, `email_token_hash`, `email_token_expires_at`, `email_reminders`,
+	`daily_digest`, `last_digest_on`, `totp_secret`, `totp_enabled`, `totp_last_step`,
+	`password_reset_hash`, `password_reset_expires_at`, `oidc_issuer`, `oidc_subject`, `role`,
+	`disabled_at`
+FROM `users`;
+DROP TABLE `users`;
+ALTER TABLE `users_old` RENAME TO `users`;
+
+CREATE UNIQUE INDEX `users_oidc_identity` ON `users`(`oidc_issuer`, `oidc_subject`);
+CREATE UNIQUE INDEX `users_username_nocase` ON `users`(`username` COLLATE NOCASE);
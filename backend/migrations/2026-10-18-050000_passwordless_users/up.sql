@@ -0,0 +1,30 @@
+-- Your SQL goes here
+-- Accounts that only sign in through single sign-on have no password.
+-- SQLite can't drop a NOT NULL constraint, so the table is rebuilt.
+CREATE TABLE `users_new`(
+	`id` INTEGER NOT NULL PRIMARY KEY,
+	`username` TEXT NOT NULL,
+	`password` TEXT,
+	`email` TEXT,
+	`email_verified` BOOL NOT NULL DEFAULT 0,
+	`email_token_hash` TEXT,
+	`email_token_expires_at` TIMESTAMP,
+	`email_reminders` BOOL NOT NULL DEFAULT 1,
+	`daily_digest` BOOL NOT NULL DEFAULT 0,
+	`last_digest_on` DATE,
+	`totp_secret` TEXT,
+	`totp_enabled` BOOL NOT NULL DEFAULT 0,
+	`totp_last_step` BIGINT,
+	`password_reset_hash` TEXT,
+	`password_reset_expires_at` TIMESTAMP,
+	`oidc_issuer` TEXT,
+	`oidc_subject` TEXT,
+	`role` TEXT NOT NULL DEFAULT 'user',
+	`disabled_at` TIMESTAMP
+);
+INSERT INTO `users_new` SELECT * FROM `users`;
+DROP TABLE `users`;
+ALTER TABLE `users_new` RENAME TO `users`;
+
+CREATE UNIQUE INDEX `users_oidc_identity` ON `users`(`oidc_issuer`, `oidc_subject`);
+CREATE UNIQUE INDEX `users_username_nocase` ON `users`(`username` COLLATE NOCASE);
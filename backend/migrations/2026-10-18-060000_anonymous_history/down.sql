@@ -0,0 +1,18 @@
+-- This file should undo anything in `up.sql`
+CREATE TABLE `todo_history_old`(
+	`id` INTEGER NOT NULL PRIMARY KEY,
+	`todo_id` INTEGER NOT NULL,
+	`user_id` INTEGER NOT NULL,
+	`field` TEXT NOT NULL,
+	`old_value` TEXT,
+	`new_value` TEXT,
+	`changed_at` TIMESTAMP NOT NULL,
+	FOREIGN KEY (`todo_id`) REFERENCES `todos`(`id`),
+	FOREIGN KEY (`user_id`) REFERENCES `users`(`id`)
+);
+-- Changes without an author have no one to be attributed to and are dropped.
+INSERT INTO `todo_history_old` SELECT * FROM `todo_history` WHERE `user_id` IS NOT NULL;
+DROP TABLE `todo_history`;
+ALTER TABLE `todo_history_old` RENAME TO `todo_history`;
+
+CREATE INDEX `todo_history_todo_id` ON `todo_history`(`todo_id`, `changed_at`);
@@ -0,0 +1,18 @@
+-- Your SQL goes here
+-- Changes by users who deleted their account are kept without an author.
+CREATE TABLE `todo_history_new`(
+	`id` INTEGER NOT NULL PRIMARY KEY,
+	`todo_id` INTEGER NOT NULL,
+	`user_id` INTEGER,
+	`field` TEXT NOT NULL,
+	`old_value` TEXT,
+	`new_value` TEXT,
+	`changed_at` TIMESTAMP NOT NULL,
+	FOREIGN KEY (`todo_id`) REFERENCES `todos`(`id`),
+	FOREIGN KEY (`user_id`) REFERENCES `users`(`id`)
+);
+INSERT INTO `todo_history_new` SELECT * FROM `todo_history`;
+DROP TABLE `todo_history`;
+ALTER TABLE `todo_history_new` RENAME TO `todo_history`;
+
+CREATE INDEX `todo_history_todo_id` ON `todo_history`(`todo_id`, `changed_at`);
@@ -0,0 +1,5 @@
+-- Your SQL goes here
+-- A reminder being sent is claimed until this time. A claim that runs out
+-- without the reminder being delivered, because the server stopped
+-- mid-send, is taken up again.
+ALTER TABLE `reminders` ADD COLUMN `claimed_until` TIMESTAMP;
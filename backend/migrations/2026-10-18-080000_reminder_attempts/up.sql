@@ -0,0 +1,3 @@
+-- Your SQL goes here
+-- How many times sending the reminder has failed so far.
+ALTER TABLE `reminders` ADD COLUMN `attempts` INTEGER NOT NULL DEFAULT 0;
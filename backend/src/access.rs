@@ -0,0 +1,192 @@
+//! Who may do what with a todo. Todos in the Inbox belong to whoever created
+//! them; todos in a project are open to the project's members as far as
+//! their role allows. Every todo route asks here instead of comparing user
+//! ids itself.
+
+use diesel::prelude::*;
+use diesel::sqlite::Sqlite;
+
+use crate::{CustomError, DbTodo, api, schema};
+
+type MemberRole = api::MemberRole;
+
+/// Something a user can try to do with a todo or a project.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    View,
+    /// Commenting on todos.
+    Comment,
+    /// Changing, moving and deleting todos.
+    Edit,
+    /// Changing the project itself and who is in it.
+    Manage,
+}
+
+impl Action {
+    fn least_role(self) -> MemberRole {
+        match self {
+            Action::View => MemberRole::Viewer,
+            Action::Comment => MemberRole::Commenter,
+            Action::Edit => MemberRole::Editor,
+            Action::Manage => MemberRole::Owner,
+        }
+    }
+}
+
+/// The user's role in a project, or `None` if they aren't in it. Open
+/// invitations don't count, and only the project's creator is its owner.
+pub fn project_role(
+    conn: &mut SqliteConnection,
+    user_id: i32,
+    project_id: i32,
+) -> QueryResult<Option<MemberRole>> {
+    let owner_id = schema::projects::table
+        .find(project_id)
+        .select(schema::projects::user_id)
+        .first::<i32>(conn)
+        .optional()?;
+    match owner_id {
+        None => Ok(None),
+        Some(owner_id) if owner_id == user_id => Ok(Some(MemberRole::Owner)),
+        Some(_) => Ok(schema::project_members::table
+            .filter(schema::project_members::project_id.eq(project_id))
+            .filter(schema::project_members::user_id.eq(user_id))
+            .filter(schema::project_members::accepted_at.is_not_null())
+            .select(schema::project_members::role)
+            .first::<String>(conn)
+            .optional()?
+            .as_deref()
+            .and_then(MemberRole::parse)
+            .map(|role| role.min(MemberRole::Editor))),
+    }
+}
+
+/// The user's role for a todo, or `None` if they can't see it.
+pub fn todo_role(
+    conn: &mut SqliteConnection,
+    user_id: i32,
+    todo: &DbTodo,
+) -> QueryResult<Option<MemberRole>> {
+    match todo.project_id {
+        Some(project_id) => project_role(conn, user_id, project_id),
+        None => Ok((todo.user_id == user_id).then_some(MemberRole::Owner)),
+    }
+}
+
+fn check(role: Option<MemberRole>, action: Action) -> Result<MemberRole, CustomError> {
+    match role {
+        // Things the user can't see at all don't exist as far as they know.
+        None => Err(CustomError::NotFound),
+        Some(role) if role >= action.least_role() => Ok(role),
+        Some(_) => Err(CustomError::Forbidden),
+    }
+}
+
+/// Whether `user_id` may do `action` to `todo`, whether it's in the trash or
+/// not.
+pub fn allows(
+    conn: &mut SqliteConnection,
+    user_id: i32,
+    todo: &DbTodo,
+    action: Action,
+) -> QueryResult<bool> {
+    Ok(todo_role(conn, user_id, todo)?.is_some_and(|role| role >= action.least_role()))
+}
+
+fn authorize_todo(
+    conn: &mut SqliteConnection,
+    user_id: i32,
+    todo_id: i32,
+    action: Action,
+    trashed: bool,
+) -> Result<DbTodo, CustomError> {
+    let todo = schema::todos::table
+        .find(todo_id)
+        .first::<DbTodo>(conn)
+        .optional()?
+        .filter(|todo| todo.deleted_at.is_some() == trashed)
+        .ok_or(CustomError::NotFound)?;
+    check(todo_role(conn, user_id, &todo)?, action)?;
+    Ok(todo)
+}
+
+/// Whether `user_id` may do `action` to todo `todo_id`. Returns the todo if
+/// so, `NotFound` if they can't see it and `Forbidden` if they can see it but
+/// not do that. Todos in the trash count as not found.
+pub fn authorize(
+    conn: &mut SqliteConnection,
+    user_id: i32,
+    todo_id: i32,
+    action: Action,
+) -> Result<DbTodo, CustomError> {
+    authorize_todo(conn, user_id, todo_id, action, false)
+}
+
+/// Like `authorize`, for a todo in the trash.
+pub fn authorize_trashed(
+    conn: &mut SqliteConnection,
+    user_id: i32,
+    todo_id: i32,
+    action: Action,
+) -> Result<DbTodo, CustomError> {
+    authorize_todo(conn, user_id, todo_id, action, true)
+}
+
+/// Like `authorize`, for a project. Returns the user's role in it.
+pub fn authorize_project(
+    conn: &mut SqliteConnection,
+    user_id: i32,
+    project_id: i32,
+    action: Action,
+) -> Result<MemberRole, CustomError> {
+    check(project_role(conn, user_id, project_id)?, action)
+}
+
+/// The projects the user owns or has joined.
+pub fn project_ids(conn: &mut SqliteConnection, user_id: i32) -> QueryResult<Vec<i32>> {
+    let mut ids = schema::projects::table
+        .filter(schema::projects::user_id.eq(user_id))
+        .select(schema::projects::id)
+        .load::<i32>(conn)?;
+    ids.extend(
+        schema::project_members::table
+            .filter(schema::project_members::user_id.eq(user_id))
+            .filter(schema::project_members::accepted_at.is_not_null())
+            .select(schema::project_members::project_id)
+            .load::<i32>(conn)?,
+    );
+    Ok(ids)
+}
+
+/// Every todo the user can reach, in the trash or not: their own Inbox and
+/// the todos of the projects they're in.
+pub fn reachable_todos(
+    conn: &mut SqliteConnection,
+    user_id: i32,
+) -> QueryResult<schema::todos::BoxedQuery<'static, Sqlite>> {
+    let project_ids = project_ids(conn, user_id)?;
+    Ok(schema::todos::table
+        .filter(
+            schema::todos::project_id
+                .is_null()
+                .and(schema::todos::user_id.eq(user_id))
+                .or(schema::todos::project_id.eq_any(project_ids)),
+        )
+        .into_boxed())
+}
+
+/// Every todo the user can see, leaving out the trash.
+pub fn visible_todos(
+    conn: &mut SqliteConnection,
+    user_id: i32,
+) -> QueryResult<schema::todos::BoxedQuery<'static, Sqlite>> {
+    Ok(reachable_todos(conn, user_id)?.filter(schema::todos::deleted_at.is_null()))
+}
+
+/// The todos in the trash the user can see.
+pub fn trashed_todos(
+    conn: &mut SqliteConnection,
+    user_id: i32,
+) -> QueryResult<schema::todos::BoxedQuery<'static, Sqlite>> {
+    Ok(reachable_todos(conn, user_id)?.filter(schema::todos::deleted_at.is_not_null()))
+}
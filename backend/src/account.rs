@@ -0,0 +1,413 @@
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel::prelude::*;
+use diesel::sql_types::Text;
+use rocket::State;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+
+use crate::ratelimit::{Account, RateLimiter};
+use crate::security::PasswordConfig;
+use crate::{
+    AppConfig, AuthenticatedUser, CustomError, DbPool, DbTodo, DbUser, api, auth, mailer, schema,
+    security, subtasks, tags,
+};
+
+type PasswordChange = api::PasswordChange;
+type PasswordResetRequest = api::PasswordResetRequest;
+type PasswordReset = api::PasswordReset;
+type AccountDeletion = api::AccountDeletion;
+
+define_sql_function!(fn lower(x: Text) -> Text);
+
+const RESET_TOKEN_TTL: Duration = Duration::hours(1);
+/// How recently an account without a password must have signed in for that
+/// to stand in for the password.
+const SSO_CONFIRMATION_WINDOW: Duration = Duration::minutes(10);
+
+/// Whether the account holder confirmed a sensitive change: with their
+/// password, or for accounts that only sign in through single sign-on, by a
+/// session opened in the last few minutes.
+fn confirmed(
+    conn: &mut SqliteConnection,
+    auth_user: &AuthenticatedUser,
+    user: &DbUser,
+    password: Option<&String>,
+) -> Result<bool, CustomError> {
+    match (&user.password, password) {
+        (Some(db_password), Some(password)) => {
+            Ok(security::verify_password(password, db_password)?)
+        }
+        (Some(_), None) => Ok(false),
+        (None, _) => {
+            // Access tokens don't count; they aren't a sign-in.
+            let Some(session_id) = auth_user.session_id else {
+                return Ok(false);
+            };
+            let created_at = schema::sessions::table
+                .find(session_id)
+                .select(schema::sessions::created_at)
+                .first::<NaiveDateTime>(conn)?;
+            Ok(Utc::now().naive_utc() - created_at < SSO_CONFIRMATION_WINDOW)
+        }
+    }
+}
+
+/// What to tell someone whose change wasn't `confirmed`.
+fn not_confirmed(user: &DbUser, message: &str) -> CustomError {
+    match user.password {
+        Some(_) => CustomError::InvalidInput(message.to_string()),
+        None => CustomError::InvalidInput(String::from(
+            "Sign in again with single sign-on to confirm this.",
+        )),
+    }
+}
+
+/// Checks `new_password` against the policy and stores it for `user`.
+fn set_password(
+    conn: &mut SqliteConnection,
+    password_config: &PasswordConfig,
+    user: &DbUser,
+    new_password: &str,
+) -> Result<(), CustomError> {
+    password_config
+        .check(new_password, &user.username)
+        .map_err(CustomError::InvalidInput)?;
+
+    // A new password also invalidates any reset link still in someone's inbox.
+    diesel::update(schema::users::table.find(user.id))
+        .set((
+            schema::users::password.eq(security::hash_password(new_password, password_config)?),
+            schema::users::password_reset_hash.eq(None::<String>),
+            schema::users::password_reset_expires_at.eq(None::<NaiveDateTime>),
+        ))
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Stores a new single-use reset token for the user, replacing any earlier
+/// one, and returns the link to the reset page with it.
+pub fn issue_reset_link(
+    conn: &mut SqliteConnection,
+    app_config: &AppConfig,
+    user_id: i32,
+    ttl: Duration,
+) -> Result<String, CustomError> {
+    let token = security::generate_token(32);
+    diesel::update(schema::users::table.find(user_id))
+        .set((
+            schema::users::password_reset_hash.eq(security::hash_token(&token)),
+            schema::users::password_reset_expires_at.eq((Utc::now() + ttl).naive_utc()),
+        ))
+        .execute(conn)?;
+
+    Ok(format!(
+        "{}/reset-password?token={}",
+        app_config.public_url, token
+    ))
+}
+
+/// Changes the password of the signed-in user, or sets one for an account
+/// that only used single sign-on so far. Every other session is logged out,
+/// since one of them may be why the password is being changed.
+#[put("/users/me/password", data = "<password_json>")]
+pub fn change_password(
+    pool: &State<DbPool>,
+    password_config: &State<PasswordConfig>,
+    auth_user: AuthenticatedUser,
+    password_json: Json<PasswordChange>,
+) -> Result<Status, CustomError> {
+    let change = password_json.into_inner();
+    let mut conn = pool.get().expect("Failed to get DB connection from pool");
+
+    conn.transaction(|conn| {
+        let db_user = schema::users::table
+            .find(auth_user.user_id)
+            .first::<DbUser>(conn)?;
+        if !confirmed(conn, &auth_user, &db_user, change.current_password.as_ref())? {
+            return Err(not_confirmed(
+                &db_user,
+                "The current password is incorrect.",
+            ));
+        }
+
+        set_password(conn, password_config, &db_user, &change.new_password)?;
+        auth::revoke_user_sessions(conn, db_user.id, auth_user.session_id)?;
+        Ok(Status::NoContent)
+    })
+}
+
+/// Emails a single-use reset link to the account's verified address. The
+/// response is the same whether or not the account exists, so it can't be
+/// used to find out who has one.
+#[post("/users/password/forgot", data = "<request_json>")]
+pub fn forgot_password(
+    pool: &State<DbPool>,
+    app_config: &State<AppConfig>,
+    mailer: &State<mailer::Mailer>,
+    rate_limiter: &State<RateLimiter>,
+    client: auth::ClientInfo,
+    request_json: Json<PasswordResetRequest>,
+) -> Result<Status, CustomError> {
+    let login = request_json.into_inner().login.trim().to_string();
+    rate_limiter.check(client.ip.as_deref(), Some(Account::Username(&login)))?;
+    let mut conn = pool.get().expect("Failed to get DB connection from pool");
+
+    let db_user = schema::users::table
+        .filter(
+            lower(schema::users::username)
+                .eq(login.to_ascii_lowercase())
+                .or(schema::users::email
+                    .eq(&login)
+                    .and(schema::users::email_verified.eq(true))),
+        )
+        .first::<DbUser>(&mut conn)
+        .optional()?;
+    let Some((db_user, email)) = db_user.and_then(|user| {
+        let email = user.email.clone().filter(|_| user.email_verified)?;
+        Some((user, email))
+    }) else {
+        return Ok(Status::NoContent);
+    };
+
+    let link = issue_reset_link(&mut conn, app_config, db_user.id, RESET_TOKEN_TTL)?;
+    mailer.send_in_background(
+        email,
+        mailer::templates::password_reset(&db_user.username, &link),
+    );
+    Ok(Status::NoContent)
+}
+
+/// Sets a new password with a token from a reset email and logs out every
+/// session of the account.
+#[post("/users/password/reset", data = "<reset_json>")]
+pub fn reset_password(
+    pool: &State<DbPool>,
+    password_config: &State<PasswordConfig>,
+    rate_limiter: &State<RateLimiter>,
+    client: auth::ClientInfo,
+    reset_json: Json<PasswordReset>,
+) -> Result<Status, CustomError> {
+    let reset = reset_json.into_inner();
+    rate_limiter.check(client.ip.as_deref(), None)?;
+    let mut conn = pool.get().expect("Failed to get DB connection from pool");
+
+    conn.transaction(|conn| {
+        let db_user = schema::users::table
+            .filter(schema::users::password_reset_hash.eq(security::hash_token(&reset.token)))
+            .filter(schema::users::password_reset_expires_at.gt(Utc::now().naive_utc()))
+            .first::<DbUser>(conn)
+            .optional()?
+            .ok_or(CustomError::NotFound)?;
+
+        set_password(conn, password_config, &db_user, &reset.new_password)?;
+        auth::revoke_user_sessions(conn, db_user.id, None)?;
+        Ok(Status::NoContent)
+    })
+}
+
+/// Deletes a user together with everything that belongs to them. What they
+/// added to other people's projects stays there.
+pub fn delete_user(conn: &mut SqliteConnection, user_id: i32) -> Result<(), CustomError> {
+    let project_ids = schema::projects::table
+        .filter(schema::projects::user_id.eq(user_id))
+        .select(schema::projects::id)
+        .load::<i32>(conn)?;
+
+    // Todos in other people's projects are handed over to the project's
+    // owner, tags and all.
+    let handed_over = schema::todos::table
+        .filter(schema::todos::user_id.eq(user_id))
+        .filter(schema::todos::project_id.is_not_null())
+        .filter(schema::todos::project_id.ne_all(&project_ids))
+        .load::<DbTodo>(conn)?;
+    for todo in handed_over {
+        let owner_id = schema::projects::table
+            .filter(schema::projects::id.nullable().eq(todo.project_id))
+            .select(schema::projects::user_id)
+            .first::<i32>(conn)?;
+        let tags = tags::names_of(conn, &todo)?;
+        let todo = diesel::update(schema::todos::table.find(todo.id))
+            .set(schema::todos::user_id.eq(owner_id))
+            .get_result::<DbTodo>(conn)?;
+        tags::set_todo_tags(conn, &todo, &tags)?;
+    }
+    // So are the invitations the user sent to them.
+    let invited_to = schema::project_members::table
+        .filter(schema::project_members::invited_by.eq(user_id))
+        .filter(schema::project_members::project_id.ne_all(&project_ids))
+        .select(schema::project_members::project_id)
+        .distinct()
+        .load::<i32>(conn)?;
+    for project_id in invited_to {
+        let owner_id = schema::projects::table
+            .find(project_id)
+            .select(schema::projects::user_id)
+            .first::<i32>(conn)?;
+        diesel::update(
+            schema::project_members::table
+                .filter(schema::project_members::project_id.eq(project_id))
+                .filter(schema::project_members::invited_by.eq(user_id)),
+        )
+        .set(schema::project_members::invited_by.eq(owner_id))
+        .execute(conn)?;
+    }
+
+    // What's left of the user's todos is in their Inbox and their own
+    // projects.
+    let todo_ids = schema::todos::table
+        .filter(schema::todos::user_id.eq(user_id))
+        .select(schema::todos::id);
+    diesel::delete(schema::reminders::table.filter(schema::reminders::todo_id.eq_any(todo_ids)))
+        .execute(conn)?;
+    diesel::delete(
+        schema::completions::table.filter(schema::completions::todo_id.eq_any(todo_ids)),
+    )
+    .execute(conn)?;
+    let tag_ids = schema::tags::table
+        .filter(schema::tags::user_id.eq(user_id))
+        .select(schema::tags::id);
+    diesel::delete(
+        schema::todo_tags::table.filter(
+            schema::todo_tags::todo_id
+                .eq_any(todo_ids)
+                .or(schema::todo_tags::tag_id.eq_any(tag_ids)),
+        ),
+    )
+    .execute(conn)?;
+    diesel::delete(
+        schema::todo_assignees::table.filter(
+            schema::todo_assignees::todo_id
+                .eq_any(todo_ids)
+                .or(schema::todo_assignees::user_id.eq(user_id)),
+        ),
+    )
+    .execute(conn)?;
+    let comment_ids = schema::comments::table
+        .filter(
+            schema::comments::todo_id
+                .eq_any(todo_ids)
+                .or(schema::comments::user_id.eq(user_id)),
+        )
+        .select(schema::comments::id);
+    diesel::delete(
+        schema::comment_mentions::table.filter(
+            schema::comment_mentions::comment_id
+                .eq_any(comment_ids)
+                .or(schema::comment_mentions::user_id.eq(user_id)),
+        ),
+    )
+    .execute(conn)?;
+    diesel::delete(
+        schema::comments::table.filter(
+            schema::comments::todo_id
+                .eq_any(todo_ids)
+                .or(schema::comments::user_id.eq(user_id)),
+        ),
+    )
+    .execute(conn)?;
+    diesel::delete(
+        schema::todo_history::table.filter(schema::todo_history::todo_id.eq_any(todo_ids)),
+    )
+    .execute(conn)?;
+    // What the user changed on other people's todos stays in their history,
+    // just without an author.
+    diesel::update(schema::todo_history::table.filter(schema::todo_history::user_id.eq(user_id)))
+        .set(schema::todo_history::user_id.eq(None::<i32>))
+        .execute(conn)?;
+    // Other members' subtasks of the user's todos become top-level todos.
+    let parent_ids = todo_ids.load::<i32>(conn)?;
+    diesel::update(
+        schema::todos::table
+            .filter(schema::todos::user_id.ne(user_id))
+            .filter(schema::todos::parent_id.eq_any(&parent_ids)),
+    )
+    .set(schema::todos::parent_id.eq(None::<i32>))
+    .execute(conn)?;
+    diesel::delete(schema::todos::table.filter(schema::todos::user_id.eq(user_id)))
+        .execute(conn)?;
+    diesel::delete(schema::tags::table.filter(schema::tags::user_id.eq(user_id))).execute(conn)?;
+    // Other members' todos in the user's projects go back to their Inbox.
+    let moved =
+        diesel::update(schema::todos::table.filter(schema::todos::project_id.eq_any(&project_ids)))
+            .set(schema::todos::project_id.eq(None::<i32>))
+            .returning((schema::todos::id, schema::todos::user_id))
+            .load::<(i32, i32)>(conn)?;
+    // Only their creators can see them there.
+    for &(todo_id, creator_id) in &moved {
+        diesel::delete(
+            schema::todo_assignees::table
+                .filter(schema::todo_assignees::todo_id.eq(todo_id))
+                .filter(schema::todo_assignees::user_id.ne(creator_id)),
+        )
+        .execute(conn)?;
+    }
+    let moved_ids: Vec<i32> = moved.iter().map(|&(todo_id, _)| todo_id).collect();
+    subtasks::detach_strays(conn, &moved_ids)?;
+    diesel::delete(
+        schema::project_members::table.filter(
+            schema::project_members::project_id
+                .eq_any(&project_ids)
+                .or(schema::project_members::user_id.eq(user_id)),
+        ),
+    )
+    .execute(conn)?;
+    diesel::delete(schema::projects::table.filter(schema::projects::user_id.eq(user_id)))
+        .execute(conn)?;
+
+    let webhook_ids = schema::webhooks::table
+        .filter(schema::webhooks::user_id.eq(user_id))
+        .select(schema::webhooks::id);
+    diesel::delete(
+        schema::webhook_deliveries::table
+            .filter(schema::webhook_deliveries::webhook_id.eq_any(webhook_ids)),
+    )
+    .execute(conn)?;
+    diesel::delete(schema::webhooks::table.filter(schema::webhooks::user_id.eq(user_id)))
+        .execute(conn)?;
+
+    let session_ids = schema::sessions::table
+        .filter(schema::sessions::user_id.eq(user_id))
+        .select(schema::sessions::id);
+    diesel::delete(
+        schema::refresh_tokens::table
+            .filter(schema::refresh_tokens::session_id.eq_any(session_ids)),
+    )
+    .execute(conn)?;
+    diesel::delete(schema::sessions::table.filter(schema::sessions::user_id.eq(user_id)))
+        .execute(conn)?;
+    diesel::delete(
+        schema::recovery_codes::table.filter(schema::recovery_codes::user_id.eq(user_id)),
+    )
+    .execute(conn)?;
+    diesel::delete(schema::api_tokens::table.filter(schema::api_tokens::user_id.eq(user_id)))
+        .execute(conn)?;
+    diesel::delete(schema::invites::table.filter(schema::invites::created_by.eq(user_id)))
+        .execute(conn)?;
+    diesel::delete(schema::username_renames::table.find(user_id)).execute(conn)?;
+
+    diesel::delete(schema::users::table.find(user_id)).execute(conn)?;
+    Ok(())
+}
+
+/// Deletes the signed-in user's account with everything that belongs to it.
+#[delete("/users/me", data = "<deletion_json>")]
+pub fn delete_account(
+    pool: &State<DbPool>,
+    auth_user: AuthenticatedUser,
+    deletion_json: Json<AccountDeletion>,
+) -> Result<Status, CustomError> {
+    let password = deletion_json.into_inner().password;
+    let mut conn = pool.get().expect("Failed to get DB connection from pool");
+
+    conn.transaction(|conn| {
+        let db_user = schema::users::table
+            .find(auth_user.user_id)
+            .first::<DbUser>(conn)?;
+        if !confirmed(conn, &auth_user, &db_user, password.as_ref())? {
+            return Err(not_confirmed(&db_user, "The password is incorrect."));
+        }
+
+        delete_user(conn, db_user.id)?;
+        Ok(Status::NoContent)
+    })
+}
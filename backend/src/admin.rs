@@ -0,0 +1,430 @@
+//! Instance administration: the admin role, its request guard, the
+//! `/admin` routes and the `make-admin` command line bootstrap.
+
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
+use diesel::dsl::count_star;
+use diesel::prelude::*;
+use diesel::sql_types::Text;
+use rocket::State;
+use rocket::http::Status;
+use rocket::outcome::try_outcome;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::serde::json::Json;
+use std::collections::HashMap;
+
+use crate::{
+    AppConfig, AuthenticatedUser, CustomError, DbPool, DbUser, PasswordConfig, RegistrationConfig,
+    account, api, auth, mailer, models, registration, schema, security,
+};
+
+type DbInvite = models::Invite;
+type AdminUser = api::AdminUser;
+type ApiManagedUser = api::ManagedUser;
+type ManagedUserUpdate = api::ManagedUserUpdate;
+type ForcedPasswordReset = api::ForcedPasswordReset;
+type ApiInvite = api::Invite;
+type RegistrationSettings = api::RegistrationSettings;
+type Role = api::Role;
+
+/// Reset links handed out by an admin may have to go through another channel
+/// first, so they last longer than self-service ones.
+const FORCED_RESET_TTL: Duration = Duration::hours(24);
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 200;
+
+define_sql_function!(fn lower(x: Text) -> Text);
+
+// --- Guard ---
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AdminUser {
+    type Error = CustomError;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let auth_user = try_outcome!(req.guard::<AuthenticatedUser>().await);
+        let Some(pool) = req.rocket().state::<DbPool>() else {
+            eprintln!("Missing database pool in Rocket state!");
+            return Outcome::Error((Status::InternalServerError, CustomError::MissingConfig));
+        };
+
+        let mut conn = pool.get().expect("Failed to get DB connection from pool");
+        match role_of(&mut conn, auth_user.user_id) {
+            Ok(Role::Admin) => Outcome::Success(AdminUser { user: auth_user }),
+            Ok(Role::User) => Outcome::Error((Status::Forbidden, CustomError::Forbidden)),
+            Err(e) => Outcome::Error((Status::InternalServerError, CustomError::from(e))),
+        }
+    }
+}
+
+pub fn role_of(conn: &mut SqliteConnection, user_id: i32) -> QueryResult<Role> {
+    let role = schema::users::table
+        .find(user_id)
+        .select(schema::users::role)
+        .first::<String>(conn)?;
+    Ok(Role::parse(&role).unwrap_or_default())
+}
+
+/// The role a newly created account gets: the very first one administers the
+/// instance.
+pub fn initial_role(conn: &mut SqliteConnection) -> QueryResult<Role> {
+    let any_user = schema::users::table
+        .select(schema::users::id)
+        .first::<i32>(conn)
+        .optional()?;
+    Ok(if any_user.is_some() {
+        Role::User
+    } else {
+        Role::Admin
+    })
+}
+
+// --- Command line ---
+
+/// Runs a maintenance command given on the command line instead of starting
+/// the server, and returns the process exit code.
+pub fn run_command(pool: &DbPool, args: &[String]) -> i32 {
+    match args {
+        [command, username] if command == "make-admin" => {
+            let mut conn = pool.get().expect("Failed to get DB connection from pool");
+            let updated = diesel::update(
+                schema::users::table
+                    .filter(lower(schema::users::username).eq(username.to_ascii_lowercase())),
+            )
+            .set(schema::users::role.eq(Role::Admin.as_str()))
+            .execute(&mut conn);
+            match updated {
+                Ok(1) => {
+                    println!("{} is now an admin.", username);
+                    0
+                }
+                Ok(_) => {
+                    eprintln!("No user named {}.", username);
+                    1
+                }
+                Err(e) => {
+                    eprintln!("Failed to update {}: {:?}", username, e);
+                    1
+                }
+            }
+        }
+        _ => {
+            eprintln!("Usage: TooDoo make-admin <username>");
+            2
+        }
+    }
+}
+
+// --- Helpers ---
+
+/// Total and still open todos per user, leaving out the trash.
+fn todo_counts(
+    conn: &mut SqliteConnection,
+    user_ids: &[i32],
+) -> QueryResult<HashMap<i32, (i64, i64)>> {
+    let totals = schema::todos::table
+        .filter(schema::todos::user_id.eq_any(user_ids))
+        .filter(schema::todos::deleted_at.is_null())
+        .group_by(schema::todos::user_id)
+        .select((schema::todos::user_id, count_star()))
+        .load::<(i32, i64)>(conn)?;
+    let open = schema::todos::table
+        .filter(schema::todos::user_id.eq_any(user_ids))
+        .filter(schema::todos::deleted_at.is_null())
+        .filter(schema::todos::completed.eq(false))
+        .group_by(schema::todos::user_id)
+        .select((schema::todos::user_id, count_star()))
+        .load::<(i32, i64)>(conn)?;
+
+    let mut counts: HashMap<i32, (i64, i64)> = HashMap::new();
+    for (user_id, total) in totals {
+        counts.entry(user_id).or_default().0 = total;
+    }
+    for (user_id, open) in open {
+        counts.entry(user_id).or_default().1 = open;
+    }
+    Ok(counts)
+}
+
+/// The usernames accounts had before they were renamed to make usernames
+/// case-insensitive.
+fn old_usernames(
+    conn: &mut SqliteConnection,
+    user_ids: &[i32],
+) -> QueryResult<HashMap<i32, String>> {
+    Ok(schema::username_renames::table
+        .filter(schema::username_renames::user_id.eq_any(user_ids))
+        .select((
+            schema::username_renames::user_id,
+            schema::username_renames::old_username,
+        ))
+        .load::<(i32, String)>(conn)?
+        .into_iter()
+        .collect())
+}
+
+fn to_managed_user(
+    user: DbUser,
+    counts: &HashMap<i32, (i64, i64)>,
+    old_usernames: &HashMap<i32, String>,
+) -> ApiManagedUser {
+    let (todo_count, open_todo_count) = counts.get(&user.id).copied().unwrap_or_default();
+    ApiManagedUser {
+        id: user.id,
+        role: Role::parse(&user.role).unwrap_or_default(),
+        username: user.username,
+        email: user.email,
+        email_verified: user.email_verified,
+        disabled_at: user
+            .disabled_at
+            .map(|d| DateTime::<Utc>::from_naive_utc_and_offset(d, Utc)),
+        totp_enabled: user.totp_enabled,
+        sso_linked: user.oidc_subject.is_some(),
+        todo_count,
+        open_todo_count,
+        renamed_from: old_usernames.get(&user.id).cloned(),
+    }
+}
+
+fn load_managed_user(
+    conn: &mut SqliteConnection,
+    user_id: i32,
+) -> Result<ApiManagedUser, CustomError> {
+    let user = schema::users::table.find(user_id).first::<DbUser>(conn)?;
+    let counts = todo_counts(conn, &[user.id])?;
+    let old_usernames = old_usernames(conn, &[user.id])?;
+    Ok(to_managed_user(user, &counts, &old_usernames))
+}
+
+/// Admins can't lock themselves out, which also keeps at least one admin
+/// around.
+fn not_self(admin: &AdminUser, user_id: i32) -> Result<(), CustomError> {
+    if admin.user.user_id == user_id {
+        return Err(CustomError::InvalidInput(String::from(
+            "You can't disable, demote or delete your own account.",
+        )));
+    }
+    Ok(())
+}
+
+// --- Routes ---
+
+/// Lists accounts, optionally only those whose username or email contains `q`.
+#[get("/admin/users?<q>&<offset>&<limit>")]
+pub fn list_users(
+    pool: &State<DbPool>,
+    _admin: AdminUser,
+    q: Option<&str>,
+    offset: Option<i64>,
+    limit: Option<i64>,
+) -> Result<Json<Vec<ApiManagedUser>>, CustomError> {
+    let mut conn = pool.get().expect("Failed to get DB connection from pool");
+
+    let mut query = schema::users::table.into_boxed();
+    if let Some(q) = q.map(str::trim).filter(|q| !q.is_empty()) {
+        // `%` and `_` in the search are meant literally.
+        let escaped = q
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_");
+        let pattern = format!("%{}%", escaped);
+        query = query.filter(
+            schema::users::username
+                .like(pattern.clone())
+                .escape('\\')
+                .or(schema::users::email.like(pattern).escape('\\')),
+        );
+    }
+    let users = query
+        .order(schema::users::id)
+        .offset(offset.unwrap_or(0).max(0))
+        .limit(limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE))
+        .load::<DbUser>(&mut conn)?;
+
+    let user_ids: Vec<i32> = users.iter().map(|user| user.id).collect();
+    let counts = todo_counts(&mut conn, &user_ids)?;
+    let old_usernames = old_usernames(&mut conn, &user_ids)?;
+    Ok(Json(
+        users
+            .into_iter()
+            .map(|user| to_managed_user(user, &counts, &old_usernames))
+            .collect(),
+    ))
+}
+
+#[get("/admin/users/<id>")]
+pub fn get_user(
+    pool: &State<DbPool>,
+    _admin: AdminUser,
+    id: i32,
+) -> Result<Json<ApiManagedUser>, CustomError> {
+    let mut conn = pool.get().expect("Failed to get DB connection from pool");
+    Ok(Json(load_managed_user(&mut conn, id)?))
+}
+
+/// Changes an account's role or disables it. Disabling logs the account out
+/// everywhere; its access tokens stop working until it is enabled again.
+#[put("/admin/users/<id>", data = "<update_json>")]
+pub fn update_user(
+    pool: &State<DbPool>,
+    admin: AdminUser,
+    id: i32,
+    update_json: Json<ManagedUserUpdate>,
+) -> Result<Json<ApiManagedUser>, CustomError> {
+    let update = update_json.into_inner();
+    if update.role == Some(Role::User) || update.disabled == Some(true) {
+        not_self(&admin, id)?;
+    }
+    let mut conn = pool.get().expect("Failed to get DB connection from pool");
+
+    conn.transaction(|conn| {
+        let user = schema::users::table.find(id).first::<DbUser>(conn)?;
+
+        if let Some(role) = update.role {
+            diesel::update(schema::users::table.find(user.id))
+                .set(schema::users::role.eq(role.as_str()))
+                .execute(conn)?;
+        }
+        match update.disabled {
+            Some(true) if user.disabled_at.is_none() => {
+                diesel::update(schema::users::table.find(user.id))
+                    .set(schema::users::disabled_at.eq(Utc::now().naive_utc()))
+                    .execute(conn)?;
+                auth::revoke_user_sessions(conn, user.id, None)?;
+            }
+            Some(false) => {
+                diesel::update(schema::users::table.find(user.id))
+                    .set(schema::users::disabled_at.eq(None::<NaiveDateTime>))
+                    .execute(conn)?;
+            }
+            _ => {}
+        }
+
+        Ok(Json(load_managed_user(conn, user.id)?))
+    })
+}
+
+#[delete("/admin/users/<id>")]
+pub fn delete_user(pool: &State<DbPool>, admin: AdminUser, id: i32) -> Result<Status, CustomError> {
+    not_self(&admin, id)?;
+    let mut conn = pool.get().expect("Failed to get DB connection from pool");
+
+    conn.transaction(|conn| {
+        let user = schema::users::table.find(id).first::<DbUser>(conn)?;
+        account::delete_user(conn, user.id)?;
+        Ok(Status::NoContent)
+    })
+}
+
+/// Makes the current password unusable, logs the account out everywhere and
+/// issues a reset link. The link is emailed when the account has a verified
+/// address and returned to the admin otherwise. Single sign-on accounts have
+/// no password to reset, so they are only logged out.
+#[post("/admin/users/<id>/password-reset")]
+pub fn force_password_reset(
+    pool: &State<DbPool>,
+    app_config: &State<AppConfig>,
+    password_config: &State<PasswordConfig>,
+    mailer: &State<mailer::Mailer>,
+    _admin: AdminUser,
+    id: i32,
+) -> Result<Json<ForcedPasswordReset>, CustomError> {
+    let mut conn = pool.get().expect("Failed to get DB connection from pool");
+
+    let (user, link) = conn.transaction(|conn| {
+        let user = schema::users::table.find(id).first::<DbUser>(conn)?;
+        auth::revoke_user_sessions(conn, user.id, None)?;
+        if user.password.is_none() {
+            return Ok::<_, CustomError>((user, None));
+        }
+        let unusable = security::hash_password(&security::generate_token(32), password_config)?;
+        diesel::update(schema::users::table.find(user.id))
+            .set(schema::users::password.eq(unusable))
+            .execute(conn)?;
+        let link = account::issue_reset_link(conn, app_config, user.id, FORCED_RESET_TTL)?;
+        Ok((user, Some(link)))
+    })?;
+
+    let Some(link) = link else {
+        return Ok(Json(ForcedPasswordReset {
+            emailed: false,
+            reset_link: None,
+        }));
+    };
+
+    match user.email.filter(|_| user.email_verified) {
+        Some(email) => {
+            mailer.send_in_background(
+                email,
+                mailer::templates::password_reset(&user.username, &link),
+            );
+            Ok(Json(ForcedPasswordReset {
+                emailed: true,
+                reset_link: None,
+            }))
+        }
+        None => Ok(Json(ForcedPasswordReset {
+            emailed: false,
+            reset_link: Some(link),
+        })),
+    }
+}
+
+#[delete("/admin/users/<id>/sessions")]
+pub fn revoke_user_sessions(
+    pool: &State<DbPool>,
+    _admin: AdminUser,
+    id: i32,
+) -> Result<Status, CustomError> {
+    let mut conn = pool.get().expect("Failed to get DB connection from pool");
+
+    conn.transaction(|conn| {
+        let user = schema::users::table.find(id).first::<DbUser>(conn)?;
+        auth::revoke_user_sessions(conn, user.id, None)?;
+        Ok(Status::NoContent)
+    })
+}
+
+#[get("/admin/registration")]
+pub fn get_registration(
+    pool: &State<DbPool>,
+    config: &State<RegistrationConfig>,
+    _admin: AdminUser,
+) -> Result<Json<RegistrationSettings>, CustomError> {
+    let mut conn = pool.get().expect("Failed to get DB connection from pool");
+    Ok(Json(RegistrationSettings {
+        mode: registration::mode(&mut conn, config)?,
+    }))
+}
+
+#[put("/admin/registration", data = "<settings_json>")]
+pub fn update_registration(
+    pool: &State<DbPool>,
+    _admin: AdminUser,
+    settings_json: Json<RegistrationSettings>,
+) -> Result<Json<RegistrationSettings>, CustomError> {
+    let settings = settings_json.into_inner();
+    let mut conn = pool.get().expect("Failed to get DB connection from pool");
+
+    registration::set_mode(&mut conn, settings.mode)?;
+    Ok(Json(settings))
+}
+
+/// Every invite on the instance, whoever minted it.
+#[get("/admin/invites")]
+pub fn list_invites(
+    pool: &State<DbPool>,
+    _admin: AdminUser,
+) -> Result<Json<Vec<ApiInvite>>, CustomError> {
+    let mut conn = pool.get().expect("Failed to get DB connection from pool");
+
+    let invites = schema::invites::table
+        .order(schema::invites::created_at.desc())
+        .load::<DbInvite>(&mut conn)?;
+
+    Ok(Json(
+        invites
+            .into_iter()
+            .map(|invite| registration::to_api_invite(invite, None))
+            .collect(),
+    ))
+}
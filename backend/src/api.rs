@@ -1,41 +1,93 @@
 use chrono::{DateTime, Utc};
-use serde::{Deserialize, Serialize};
+use rocket::serde::json::Value;
+use serde::{Deserialize, Deserializer, Serialize, Serializer, de};
+use std::fmt;
+use std::str::FromStr;
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+use crate::recurrence::{ParseRuleError, RRule};
+
+/// How a todo repeats. `Never` and the legacy names `Daily`, `Weekly`,
+/// `Monthly` and `Yearly` are still accepted and returned as-is; anything else
+/// is a full RFC 5545 RRULE, optionally followed by `EXDATE` lines.
+#[derive(Debug, Clone, Default, PartialEq)]
 pub enum RepeatRule {
+    #[default]
     Never,
-    Daily,
-    Weekly,
-    Monthly,
-    Yearly,
+    Rule(RRule),
 }
 
-impl Default for RepeatRule {
-    fn default() -> Self {
-        RepeatRule::Never
+impl RepeatRule {
+    pub fn rrule(&self) -> Option<&RRule> {
+        match self {
+            RepeatRule::Never => None,
+            RepeatRule::Rule(rule) => Some(rule),
+        }
     }
 }
 
-impl ToString for RepeatRule {
-    fn to_string(&self) -> String {
+impl fmt::Display for RepeatRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            RepeatRule::Never => String::from("Never"),
-            RepeatRule::Daily => String::from("Daily"),
-            RepeatRule::Weekly => String::from("Weekly"),
-            RepeatRule::Monthly => String::from("Monthly"),
-            RepeatRule::Yearly => String::from("Yearly"),
+            RepeatRule::Never => f.write_str("Never"),
+            RepeatRule::Rule(rule) => match rule.shorthand() {
+                Some(name) => f.write_str(name),
+                None => write!(f, "{}", rule),
+            },
+        }
+    }
+}
+
+impl FromStr for RepeatRule {
+    type Err = ParseRuleError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.is_empty() || s.eq_ignore_ascii_case("never") {
+            return Ok(RepeatRule::Never);
         }
+        match RRule::from_shorthand(s) {
+            Some(rule) => Ok(RepeatRule::Rule(rule)),
+            None => s.parse().map(RepeatRule::Rule),
+        }
+    }
+}
+
+impl Serialize for RepeatRule {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for RepeatRule {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        value.parse().map_err(de::Error::custom)
     }
 }
 
-impl From<String> for RepeatRule {
+/// What the next occurrence of a repeating todo is computed from when it is
+/// completed: its current due date, or the moment it was actually completed.
+#[derive(Serialize, Deserialize, Default, Debug, Clone, Copy, PartialEq)]
+pub enum RepeatFrom {
+    #[default]
+    Due,
+    Completion,
+}
+
+impl fmt::Display for RepeatFrom {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            RepeatFrom::Due => "Due",
+            RepeatFrom::Completion => "Completion",
+        })
+    }
+}
+
+impl From<String> for RepeatFrom {
     fn from(value: String) -> Self {
         match value.as_str() {
-            "Daily" => RepeatRule::Daily,
-            "Weekly" => RepeatRule::Weekly,
-            "Monthly" => RepeatRule::Monthly,
-            "Yearly" => RepeatRule::Yearly,
-            _ => RepeatRule::Never,
+            "Completion" => RepeatFrom::Completion,
+            _ => RepeatFrom::Due,
         }
     }
 }
@@ -48,7 +100,52 @@ pub struct Todo {
     pub due: Option<DateTime<Utc>>,
     pub reminder: Vec<DateTime<Utc>>,
     pub repeat: RepeatRule,
+    #[serde(default)]
+    pub repeat_from: RepeatFrom,
     pub completed: bool,
+    /// `None` for todos in the Inbox.
+    #[serde(default)]
+    pub project_id: Option<i32>,
+    /// Tag names. Tags that don't exist yet are created.
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// The ids of the users responsible for the todo. Everyone assigned has
+    /// to be able to see it.
+    #[serde(default)]
+    pub assignees: Vec<i32>,
+    /// `None` for top-level todos.
+    #[serde(default)]
+    pub parent_id: Option<i32>,
+    /// Whether to complete the todo once all of its subtasks are.
+    #[serde(default)]
+    pub auto_complete: bool,
+    #[serde(default)]
+    pub priority: Priority,
+    /// Kept in step with `completed`, which is set for done and cancelled
+    /// todos.
+    #[serde(default)]
+    pub status: TodoStatus,
+    /// Where the todo sits in the user's own order. Sorts as a plain string;
+    /// ignored on input, where `POST /todos/reorder` moves todos instead.
+    #[serde(default)]
+    pub rank: String,
+    /// When the todo was moved to the trash. Only set on todos in the trash,
+    /// and ignored on input.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<DateTime<Utc>>,
+    /// How many of the todo's subtasks, at any depth, are done. Only set on
+    /// todos that have subtasks, and ignored on input.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub progress: Option<Progress>,
+    /// The todo's subtasks, when the list was asked for nested.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub subtasks: Vec<Todo>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Progress {
+    pub completed: usize,
+    pub total: usize,
 }
 
 #[derive(Deserialize, Debug, Clone)]
@@ -58,6 +155,349 @@ pub struct NewTodo {
     pub due: Option<DateTime<Utc>>,
     pub reminder: Vec<DateTime<Utc>>,
     pub repeat: RepeatRule,
+    #[serde(default)]
+    pub repeat_from: RepeatFrom,
+    #[serde(default)]
+    pub project_id: Option<i32>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub assignees: Vec<i32>,
+    #[serde(default)]
+    pub parent_id: Option<i32>,
+    #[serde(default)]
+    pub auto_complete: bool,
+    #[serde(default)]
+    pub priority: Priority,
+    /// Todos can't be created done or cancelled.
+    #[serde(default)]
+    pub status: TodoStatus,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    #[default]
+    None,
+    Low,
+    Medium,
+    High,
+    Urgent,
+}
+
+impl Priority {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Priority::None => "none",
+            Priority::Low => "low",
+            Priority::Medium => "medium",
+            Priority::High => "high",
+            Priority::Urgent => "urgent",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "none" => Some(Priority::None),
+            "low" => Some(Priority::Low),
+            "medium" => Some(Priority::Medium),
+            "high" => Some(Priority::High),
+            "urgent" => Some(Priority::Urgent),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum TodoStatus {
+    #[default]
+    Todo,
+    InProgress,
+    Blocked,
+    Done,
+    Cancelled,
+}
+
+impl TodoStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TodoStatus::Todo => "todo",
+            TodoStatus::InProgress => "in-progress",
+            TodoStatus::Blocked => "blocked",
+            TodoStatus::Done => "done",
+            TodoStatus::Cancelled => "cancelled",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "todo" => Some(TodoStatus::Todo),
+            "in-progress" => Some(TodoStatus::InProgress),
+            "blocked" => Some(TodoStatus::Blocked),
+            "done" => Some(TodoStatus::Done),
+            "cancelled" => Some(TodoStatus::Cancelled),
+            _ => None,
+        }
+    }
+
+    /// Whether a todo with this status counts as `completed`.
+    pub fn is_closed(&self) -> bool {
+        matches!(self, TodoStatus::Done | TodoStatus::Cancelled)
+    }
+}
+
+/// Moves a todo in the user's own order.
+#[derive(Deserialize, Debug, Clone)]
+pub struct Reorder {
+    pub id: i32,
+    /// The todo to put it right after, or `None` to put it first.
+    #[serde(default)]
+    pub after: Option<i32>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Project {
+    pub id: i32,
+    pub name: String,
+    pub color: Option<String>,
+    pub icon: Option<String>,
+    pub sort_order: i32,
+    pub archived: bool,
+    pub created_at: DateTime<Utc>,
+    /// The creator, who owns the project for good.
+    pub owner_id: i32,
+    /// What the signed-in user may do in the project.
+    pub role: MemberRole,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct NewProject {
+    pub name: String,
+    /// A hex color such as `#3f51b5`.
+    #[serde(default)]
+    pub color: Option<String>,
+    #[serde(default)]
+    pub icon: Option<String>,
+    /// Defaults to after the user's other projects when creating, and to
+    /// unchanged when updating.
+    #[serde(default)]
+    pub sort_order: Option<i32>,
+    #[serde(default)]
+    pub archived: Option<bool>,
+}
+
+/// What a member of a shared project may do, each role allowing what the
+/// ones after it do.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum MemberRole {
+    Viewer,
+    Commenter,
+    Editor,
+    Owner,
+}
+
+impl MemberRole {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MemberRole::Viewer => "viewer",
+            MemberRole::Commenter => "commenter",
+            MemberRole::Editor => "editor",
+            MemberRole::Owner => "owner",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "viewer" => Some(MemberRole::Viewer),
+            "commenter" => Some(MemberRole::Commenter),
+            "editor" => Some(MemberRole::Editor),
+            "owner" => Some(MemberRole::Owner),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Member {
+    pub user_id: i32,
+    pub username: String,
+    pub role: MemberRole,
+    /// `false` while the invitation is still open.
+    pub accepted: bool,
+    /// `None` for the project's creator.
+    pub invited_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct NewMember {
+    pub username: String,
+    pub role: MemberRole,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct MemberUpdate {
+    pub role: MemberRole,
+}
+
+/// An open invitation to someone else's project.
+#[derive(Serialize, Debug, Clone)]
+pub struct Invitation {
+    pub project_id: i32,
+    pub project_name: String,
+    pub role: MemberRole,
+    pub invited_by: String,
+    pub invited_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Comment {
+    pub id: i32,
+    pub todo_id: i32,
+    pub author_id: i32,
+    pub author: String,
+    /// Markdown, as written. Clients render it.
+    pub body: String,
+    /// The usernames the comment @mentions.
+    pub mentions: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    /// `None` until the author first edits it.
+    pub edited_at: Option<DateTime<Utc>>,
+}
+
+/// A new comment, or the new text of an existing one.
+#[derive(Deserialize, Debug, Clone)]
+pub struct NewComment {
+    pub body: String,
+}
+
+/// A change to one field of a todo. Values look the way the field does on
+/// `Todo`, and are `None` where there wasn't one.
+#[derive(Serialize, Debug, Clone)]
+pub struct HistoryEntry {
+    pub id: i32,
+    pub todo_id: i32,
+    pub todo_title: String,
+    /// `None` when the user who made the change has deleted their account.
+    pub user_id: Option<i32>,
+    pub username: Option<String>,
+    /// A field of `Todo`, or `created` for the todo being added.
+    pub field: String,
+    pub old_value: Option<Value>,
+    pub new_value: Option<Value>,
+    pub changed_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Tag {
+    pub id: i32,
+    pub name: String,
+    /// How many todos carry the tag.
+    pub todos: i64,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct TagRename {
+    pub name: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct TagMerge {
+    /// The tag that takes over the todos. The merged tag is deleted.
+    pub into: i32,
+}
+
+/// Things that can happen to a user's todos, as named in webhook payloads.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum EventKind {
+    #[serde(rename = "todo.created")]
+    TodoCreated,
+    #[serde(rename = "todo.updated")]
+    TodoUpdated,
+    #[serde(rename = "todo.completed")]
+    TodoCompleted,
+    #[serde(rename = "todo.deleted")]
+    TodoDeleted,
+    /// Sent to the user who was assigned, not the one who assigned them.
+    #[serde(rename = "todo.assigned")]
+    TodoAssigned,
+    /// Sent to each user @mentioned in a comment on the todo.
+    #[serde(rename = "todo.mentioned")]
+    TodoMentioned,
+    #[serde(rename = "reminder.due")]
+    ReminderDue,
+}
+
+impl EventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EventKind::TodoCreated => "todo.created",
+            EventKind::TodoUpdated => "todo.updated",
+            EventKind::TodoCompleted => "todo.completed",
+            EventKind::TodoDeleted => "todo.deleted",
+            EventKind::TodoAssigned => "todo.assigned",
+            EventKind::TodoMentioned => "todo.mentioned",
+            EventKind::ReminderDue => "reminder.due",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "todo.created" => Some(EventKind::TodoCreated),
+            "todo.updated" => Some(EventKind::TodoUpdated),
+            "todo.completed" => Some(EventKind::TodoCompleted),
+            "todo.deleted" => Some(EventKind::TodoDeleted),
+            "todo.assigned" => Some(EventKind::TodoAssigned),
+            "todo.mentioned" => Some(EventKind::TodoMentioned),
+            "reminder.due" => Some(EventKind::ReminderDue),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Webhook {
+    pub id: i32,
+    pub url: String,
+    /// Subscribed events; empty means all of them.
+    pub events: Vec<EventKind>,
+    pub active: bool,
+    pub created_at: DateTime<Utc>,
+    /// Only returned when the webhook is created.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct NewWebhook {
+    pub url: String,
+    #[serde(default)]
+    pub events: Vec<EventKind>,
+    #[serde(default)]
+    pub active: Option<bool>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct WebhookDelivery {
+    pub id: i32,
+    pub delivery_id: String,
+    pub event: String,
+    pub attempt: i32,
+    pub status_code: Option<i32>,
+    pub error: Option<String>,
+    pub succeeded: bool,
+    pub attempted_at: DateTime<Utc>,
+}
+
+/// Body POSTed to webhook subscribers.
+#[derive(Serialize, Debug, Clone)]
+pub struct WebhookPayload {
+    pub event: EventKind,
+    pub occurred_at: DateTime<Utc>,
+    pub todo: Todo,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remind_at: Option<DateTime<Utc>>,
 }
 
 #[derive(Serialize, Debug, Clone)]
@@ -66,24 +506,335 @@ pub struct User {
     pub username: String,
 }
 
+/// The signed-in user's own account details.
+#[derive(Serialize, Debug, Clone)]
+pub struct Profile {
+    pub id: i32,
+    pub username: String,
+    pub email: Option<String>,
+    pub email_verified: bool,
+    pub email_reminders: bool,
+    pub daily_digest: bool,
+    pub totp_enabled: bool,
+    /// `false` for accounts that only sign in through single sign-on.
+    pub has_password: bool,
+    pub role: Role,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct PasswordChange {
+    /// Left out by accounts that don't have a password yet.
+    #[serde(default)]
+    pub current_password: Option<String>,
+    pub new_password: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct PasswordResetRequest {
+    /// Username or verified email address of the account.
+    pub login: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct PasswordReset {
+    pub token: String,
+    pub new_password: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct AccountDeletion {
+    /// Left out by accounts that don't have a password.
+    #[serde(default)]
+    pub password: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct EmailUpdate {
+    pub email: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct NotificationSettings {
+    pub email_reminders: bool,
+    pub daily_digest: bool,
+}
+
 #[derive(Deserialize, Debug, Clone)]
 pub struct NewUser {
     pub username: String,
     pub password: String,
+    /// Required to register while registration is invite-only.
+    #[serde(default)]
+    pub invite_code: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct SessionToken {
+    /// Short-lived access token for the `Authorization` header.
     pub token: String,
+    /// Single-use token for `/users/refresh`; every refresh returns a new one.
+    pub refresh_token: String,
+}
+
+/// Returned by `login` instead of a session when the account has two-factor
+/// authentication enabled; `mfa_token` is exchanged at `/users/login/mfa`.
+#[derive(Serialize, Debug)]
+pub struct MfaChallenge {
+    pub mfa_required: bool,
+    pub mfa_token: String,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(untagged)]
+pub enum LoginResponse {
+    Session(SessionToken),
+    MfaRequired(MfaChallenge),
+}
+
+#[derive(Deserialize, Debug)]
+pub struct MfaLogin {
+    pub mfa_token: String,
+    /// A code from the authenticator app or an unused recovery code.
+    pub code: String,
+}
+
+/// Claims of the short-lived token proving the password step of a two-step
+/// login. It has no `sid`, so it can never pass as an access token.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MfaClaims {
+    pub sub: i32,
+    pub exp: usize,
+    pub mfa_pending: bool,
+}
+
+#[derive(Serialize, Debug)]
+pub struct TotpEnrollment {
+    pub secret: String,
+    pub otpauth_uri: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct MfaCode {
+    pub code: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct RecoveryCodes {
+    pub recovery_codes: Vec<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+}
+
+/// A device or browser the user is signed in on.
+#[derive(Serialize, Debug)]
+pub struct Session {
+    pub id: i32,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+    /// Whether this is the session making the request.
+    pub current: bool,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Claims {
     pub sub: i32,
     pub exp: usize,
+    /// The session the token was issued for, so it dies with the session.
+    pub sid: i32,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct AuthenticatedUser {
     pub user_id: i32,
+    /// The login session behind the request, or `None` when it was made with
+    /// a personal access token.
+    pub session_id: Option<i32>,
+}
+
+/// An [`AuthenticatedUser`] whose account has the admin role.
+#[derive(Debug)]
+pub struct AdminUser {
+    pub user: AuthenticatedUser,
+}
+
+/// What a personal access token is allowed to do.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Scope {
+    #[serde(rename = "todos:read")]
+    TodosRead,
+    #[serde(rename = "todos:write")]
+    TodosWrite,
+    #[serde(rename = "webhooks:read")]
+    WebhooksRead,
+    #[serde(rename = "webhooks:write")]
+    WebhooksWrite,
+    #[serde(rename = "profile:read")]
+    ProfileRead,
+}
+
+impl Scope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::TodosRead => "todos:read",
+            Scope::TodosWrite => "todos:write",
+            Scope::WebhooksRead => "webhooks:read",
+            Scope::WebhooksWrite => "webhooks:write",
+            Scope::ProfileRead => "profile:read",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "todos:read" => Some(Scope::TodosRead),
+            "todos:write" => Some(Scope::TodosWrite),
+            "webhooks:read" => Some(Scope::WebhooksRead),
+            "webhooks:write" => Some(Scope::WebhooksWrite),
+            "profile:read" => Some(Scope::ProfileRead),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ApiToken {
+    pub id: i32,
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    /// Only returned when the token is created.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct NewApiToken {
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    #[serde(default)]
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    #[default]
+    User,
+    Admin,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::User => "user",
+            Role::Admin => "admin",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "user" => Some(Role::User),
+            "admin" => Some(Role::Admin),
+            _ => None,
+        }
+    }
+}
+
+/// An account as seen by an admin.
+#[derive(Serialize, Debug, Clone)]
+pub struct ManagedUser {
+    pub id: i32,
+    pub username: String,
+    pub email: Option<String>,
+    pub email_verified: bool,
+    pub role: Role,
+    pub disabled_at: Option<DateTime<Utc>>,
+    pub totp_enabled: bool,
+    /// Whether the account is linked to the single sign-on provider.
+    pub sso_linked: bool,
+    pub todo_count: i64,
+    pub open_todo_count: i64,
+    /// The username the account had before usernames became
+    /// case-insensitive, when it had to be renamed then.
+    pub renamed_from: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ManagedUserUpdate {
+    #[serde(default)]
+    pub role: Option<Role>,
+    #[serde(default)]
+    pub disabled: Option<bool>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ForcedPasswordReset {
+    /// Whether the reset link was emailed to the user.
+    pub emailed: bool,
+    /// The reset link, for the admin to pass on when it couldn't be emailed.
+    /// Neither is set for single sign-on accounts, which have no password.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reset_link: Option<String>,
+}
+
+/// Who may create an account.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum RegistrationMode {
+    Open,
+    /// Only with an invite code from an existing user.
+    InviteOnly,
+    Closed,
+}
+
+impl RegistrationMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RegistrationMode::Open => "open",
+            RegistrationMode::InviteOnly => "invite-only",
+            RegistrationMode::Closed => "closed",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "open" => Some(RegistrationMode::Open),
+            "invite-only" => Some(RegistrationMode::InviteOnly),
+            "closed" => Some(RegistrationMode::Closed),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RegistrationSettings {
+    pub mode: RegistrationMode,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Invite {
+    pub id: i32,
+    pub created_by: i32,
+    pub max_uses: Option<i32>,
+    pub uses: i32,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    /// Only returned when the invite is created.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct NewInvite {
+    /// How many accounts the code can create; unlimited when absent.
+    #[serde(default)]
+    pub max_uses: Option<i32>,
+    #[serde(default)]
+    pub expires_at: Option<DateTime<Utc>>,
 }
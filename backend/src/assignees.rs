@@ -0,0 +1,181 @@
+//! Assigning todos to the people responsible for them. Anyone who can see a
+//! todo can be assigned to it, and each assignment or unassignment is kept
+//! in the todo's history.
+
+use chrono::Utc;
+use diesel::prelude::*;
+use diesel::sqlite::Sqlite;
+use std::str::FromStr;
+
+use crate::{CustomError, access, history, models, schema};
+
+type DbTodo = models::Todo;
+type DbTodoAssignee = models::TodoAssignee;
+
+/// Which todos `GET /todos?assigned_to=` wants.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AssigneeFilter {
+    /// `me`: the signed-in user's.
+    Me,
+    /// `none`: todos nobody is assigned to.
+    Nobody,
+    /// A user id.
+    User(i32),
+}
+
+impl FromStr for AssigneeFilter {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "me" => Ok(AssigneeFilter::Me),
+            "none" => Ok(AssigneeFilter::Nobody),
+            _ => value
+                .parse()
+                .map(AssigneeFilter::User)
+                .map_err(|_| String::from("The assignee must be `me`, `none` or a user id.")),
+        }
+    }
+}
+
+/// Narrows a todo query down to the todos the filter asks for.
+pub fn filter(
+    query: schema::todos::BoxedQuery<'static, Sqlite>,
+    filter: AssigneeFilter,
+    user_id: i32,
+) -> schema::todos::BoxedQuery<'static, Sqlite> {
+    let assigned = schema::todo_assignees::table.select(schema::todo_assignees::todo_id);
+    match filter {
+        AssigneeFilter::Me => query.filter(
+            schema::todos::id.eq_any(assigned.filter(schema::todo_assignees::user_id.eq(user_id))),
+        ),
+        AssigneeFilter::User(id) => query.filter(
+            schema::todos::id.eq_any(assigned.filter(schema::todo_assignees::user_id.eq(id))),
+        ),
+        AssigneeFilter::Nobody => query.filter(schema::todos::id.ne_all(assigned)),
+    }
+}
+
+/// The assignees of each todo, in the same order as `todos`.
+pub fn ids_for(conn: &mut SqliteConnection, todos: &[DbTodo]) -> QueryResult<Vec<Vec<i32>>> {
+    let assignees = DbTodoAssignee::belonging_to(todos)
+        .order(schema::todo_assignees::user_id.asc())
+        .load::<DbTodoAssignee>(conn)?;
+
+    Ok(assignees
+        .grouped_by(todos)
+        .into_iter()
+        .map(|assignees| assignees.into_iter().map(|a| a.user_id).collect())
+        .collect())
+}
+
+pub fn ids_of(conn: &mut SqliteConnection, todo: &DbTodo) -> QueryResult<Vec<i32>> {
+    Ok(ids_for(conn, std::slice::from_ref(todo))?
+        .pop()
+        .unwrap_or_default())
+}
+
+/// Replaces the assignees of a todo on behalf of `user_id`. Everyone in
+/// `wanted` has to be able to see the todo. Returns the assignees afterwards
+/// and the ones that are new.
+pub fn set_assignees(
+    conn: &mut SqliteConnection,
+    user_id: i32,
+    todo: &DbTodo,
+    wanted: &[i32],
+) -> Result<(Vec<i32>, Vec<i32>), CustomError> {
+    let mut wanted = wanted.to_vec();
+    wanted.sort_unstable();
+    wanted.dedup();
+    for &assignee in &wanted {
+        if access::todo_role(conn, assignee, todo)?.is_none() {
+            return Err(CustomError::InvalidInput(String::from(
+                "Todos can only be assigned to people who can see them.",
+            )));
+        }
+    }
+
+    let current = ids_of(conn, todo)?;
+    if current == wanted {
+        return Ok((wanted, Vec::new()));
+    }
+    diesel::delete(
+        schema::todo_assignees::table
+            .filter(schema::todo_assignees::todo_id.eq(todo.id))
+            .filter(schema::todo_assignees::user_id.ne_all(&wanted)),
+    )
+    .execute(conn)?;
+    let added: Vec<i32> = wanted
+        .iter()
+        .copied()
+        .filter(|assignee| !current.contains(assignee))
+        .collect();
+    let rows: Vec<DbTodoAssignee> = added
+        .iter()
+        .map(|&assignee| DbTodoAssignee {
+            todo_id: todo.id,
+            user_id: assignee,
+            assigned_by: user_id,
+            assigned_at: Utc::now().naive_utc(),
+        })
+        .collect();
+    diesel::insert_into(schema::todo_assignees::table)
+        .values(&rows)
+        .execute(conn)?;
+
+    history::record(conn, todo.id, user_id, "assignees", &current, &wanted)?;
+    Ok((wanted, added))
+}
+
+/// Unassigns everyone who can no longer see a todo, for when it moves out of
+/// a project.
+pub fn unassign_hidden(
+    conn: &mut SqliteConnection,
+    user_id: i32,
+    todo: &DbTodo,
+) -> QueryResult<()> {
+    let before = ids_of(conn, todo)?;
+    let mut after = Vec::new();
+    for &assignee in &before {
+        if access::todo_role(conn, assignee, todo)?.is_some() {
+            after.push(assignee);
+        }
+    }
+    diesel::delete(
+        schema::todo_assignees::table
+            .filter(schema::todo_assignees::todo_id.eq(todo.id))
+            .filter(schema::todo_assignees::user_id.ne_all(&after)),
+    )
+    .execute(conn)?;
+    history::record(conn, todo.id, user_id, "assignees", &before, &after)
+}
+
+/// Unassigns someone from every todo of a project, for when they leave it.
+pub fn unassign_from_project(
+    conn: &mut SqliteConnection,
+    user_id: i32,
+    project_id: i32,
+    removed_id: i32,
+) -> QueryResult<()> {
+    let todos = schema::todos::table
+        .filter(schema::todos::project_id.eq(project_id))
+        .filter(
+            schema::todos::id.eq_any(
+                schema::todo_assignees::table
+                    .filter(schema::todo_assignees::user_id.eq(removed_id))
+                    .select(schema::todo_assignees::todo_id),
+            ),
+        )
+        .load::<DbTodo>(conn)?;
+    for todo in todos {
+        let before = ids_of(conn, &todo)?;
+        diesel::delete(schema::todo_assignees::table.find((todo.id, removed_id))).execute(conn)?;
+        let after: Vec<i32> = before
+            .iter()
+            .copied()
+            .filter(|&id| id != removed_id)
+            .collect();
+        history::record(conn, todo.id, user_id, "assignees", &before, &after)?;
+    }
+    Ok(())
+}
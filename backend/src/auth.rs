@@ -0,0 +1,403 @@
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
+use diesel::prelude::*;
+use rocket::State;
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::serde::json::Json;
+
+use crate::{
+    ApiClaims, ApiSessionToken, AppConfig, AuthenticatedUser, CustomError, DbPool, api, models,
+    schema, security, tokens,
+};
+
+type DbSession = models::Session;
+type DbInsertableSession = models::InsertableSession;
+type DbRefreshToken = models::RefreshToken;
+type DbInsertableRefreshToken = models::InsertableRefreshToken;
+type RefreshRequest = api::RefreshRequest;
+type ApiSession = api::Session;
+
+/// `last_seen_at` is only written when it is at least this stale, so busy
+/// clients don't turn every request into a database write.
+pub const LAST_SEEN_RESOLUTION: Duration = Duration::minutes(1);
+/// Longest user agent we keep; anything beyond is cut off.
+const MAX_USER_AGENT_LEN: usize = 512;
+
+// --- Guard ---
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AuthenticatedUser {
+    type Error = CustomError;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        // Step 1: Get the AppConfig and pool state.
+        // If either is missing, it's a server configuration error.
+        let (Some(app_config), Some(pool)) = (
+            req.rocket().state::<AppConfig>(),
+            req.rocket().state::<DbPool>(),
+        ) else {
+            eprintln!("Missing AppConfig or database pool in Rocket state!");
+            return Outcome::Error((Status::InternalServerError, CustomError::MissingConfig));
+        };
+
+        // Step 2: Extract and validate the token from the header.
+        let Some(token_str) = req
+            .headers()
+            .get_one("Authorization")
+            .and_then(|header| header.strip_prefix("Bearer "))
+        else {
+            return Outcome::Error((Status::Unauthorized, CustomError::MissingAuthToken));
+        };
+
+        // Personal access tokens only get into the routes their scopes cover.
+        if token_str.starts_with(tokens::TOKEN_PREFIX) {
+            return authenticate_api_token(req, pool, token_str);
+        }
+
+        // Step 3: Decode the token.
+        let claims = match app_config.keys.decode::<ApiClaims>(token_str) {
+            Ok(claims) => claims,
+            // Token is present but invalid (expired, bad signature, etc.)
+            Err(e) => return Outcome::Error((Status::Unauthorized, CustomError::from(e))),
+        };
+
+        // Step 4: A valid signature isn't enough once the session is revoked.
+        let mut conn = pool.get().expect("Failed to get DB connection from pool");
+        match touch_session(&mut conn, claims.sid, claims.sub, Utc::now().naive_utc()) {
+            Ok(true) => Outcome::Success(AuthenticatedUser {
+                user_id: claims.sub,
+                session_id: Some(claims.sid),
+            }),
+            Ok(false) => Outcome::Error((Status::Unauthorized, CustomError::SessionRevoked)),
+            Err(e) => Outcome::Error((Status::InternalServerError, CustomError::from(e))),
+        }
+    }
+}
+
+fn authenticate_api_token(
+    req: &Request<'_>,
+    pool: &DbPool,
+    token: &str,
+) -> Outcome<AuthenticatedUser, CustomError> {
+    let Some(scope) = req
+        .route()
+        .and_then(|route| route.name.as_deref())
+        .and_then(tokens::required_scope)
+    else {
+        return Outcome::Error((
+            Status::Forbidden,
+            CustomError::InsufficientScope(String::from(
+                "This endpoint can't be used with an access token.",
+            )),
+        ));
+    };
+
+    let mut conn = pool.get().expect("Failed to get DB connection from pool");
+    match tokens::authenticate(&mut conn, token, Utc::now().naive_utc()) {
+        Ok(Some((user_id, scopes))) if scopes.contains(&scope) => {
+            Outcome::Success(AuthenticatedUser {
+                user_id,
+                session_id: None,
+            })
+        }
+        Ok(Some(_)) => Outcome::Error((
+            Status::Forbidden,
+            CustomError::InsufficientScope(format!(
+                "This access token is missing the {} scope.",
+                scope.as_str()
+            )),
+        )),
+        Ok(None) => Outcome::Error((Status::Unauthorized, CustomError::InvalidApiToken)),
+        Err(e) => Outcome::Error((Status::InternalServerError, CustomError::from(e))),
+    }
+}
+
+/// Checks that the session is still active and records that it was just used.
+fn touch_session(
+    conn: &mut SqliteConnection,
+    session_id: i32,
+    user_id: i32,
+    now: NaiveDateTime,
+) -> QueryResult<bool> {
+    let active = schema::sessions::table
+        .filter(schema::sessions::id.eq(session_id))
+        .filter(schema::sessions::user_id.eq(user_id))
+        .filter(schema::sessions::revoked_at.is_null());
+
+    let Some(last_seen_at) = active
+        .select(schema::sessions::last_seen_at)
+        .first::<NaiveDateTime>(conn)
+        .optional()?
+    else {
+        return Ok(false);
+    };
+
+    if now - last_seen_at >= LAST_SEEN_RESOLUTION {
+        diesel::update(active)
+            .set(schema::sessions::last_seen_at.eq(now))
+            .execute(conn)?;
+    }
+    Ok(true)
+}
+
+/// Where a login is coming from, recorded on the session it opens.
+pub struct ClientInfo {
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ClientInfo {
+    type Error = std::convert::Infallible;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let user_agent = req
+            .headers()
+            .get_one("User-Agent")
+            .map(|agent| agent.chars().take(MAX_USER_AGENT_LEN).collect::<String>());
+
+        let trust_proxy = req
+            .rocket()
+            .state::<AppConfig>()
+            .is_some_and(|config| config.trust_proxy);
+        let ip = if trust_proxy {
+            req.client_ip()
+        } else {
+            req.remote().map(|remote| remote.ip())
+        };
+
+        Outcome::Success(ClientInfo {
+            user_agent,
+            ip: ip.map(|ip| ip.to_string()),
+        })
+    }
+}
+
+// --- Sessions ---
+
+/// Opens a new session for a user who just proved who they are and returns
+/// its first token pair.
+pub fn start_session(
+    conn: &mut SqliteConnection,
+    app_config: &AppConfig,
+    user_id: i32,
+    client: ClientInfo,
+) -> Result<ApiSessionToken, CustomError> {
+    // Every way of logging in ends up here, so this is where disabled
+    // accounts are turned away.
+    let disabled_at = schema::users::table
+        .find(user_id)
+        .select(schema::users::disabled_at)
+        .first::<Option<NaiveDateTime>>(conn)?;
+    if disabled_at.is_some() {
+        return Err(CustomError::AccountDisabled);
+    }
+
+    let now = Utc::now().naive_utc();
+    let session = diesel::insert_into(schema::sessions::table)
+        .values(&DbInsertableSession {
+            user_id,
+            created_at: now,
+            user_agent: client.user_agent,
+            ip: client.ip,
+            last_seen_at: now,
+        })
+        .get_result::<DbSession>(conn)?;
+
+    issue_tokens(conn, app_config, &session)
+}
+
+/// Mints an access token and a fresh refresh token for `session`. Only the
+/// hash of the refresh token is stored.
+fn issue_tokens(
+    conn: &mut SqliteConnection,
+    app_config: &AppConfig,
+    session: &DbSession,
+) -> Result<ApiSessionToken, CustomError> {
+    let now = Utc::now();
+    let refresh_token = security::generate_token(32);
+    diesel::insert_into(schema::refresh_tokens::table)
+        .values(&DbInsertableRefreshToken {
+            session_id: session.id,
+            token_hash: security::hash_token(&refresh_token),
+            created_at: now.naive_utc(),
+            expires_at: (now + app_config.refresh_token_ttl).naive_utc(),
+        })
+        .execute(conn)?;
+
+    let token = app_config.keys.encode(&ApiClaims {
+        sub: session.user_id,
+        exp: (now + app_config.access_token_ttl).timestamp() as usize,
+        sid: session.id,
+    })?;
+
+    Ok(ApiSessionToken {
+        token,
+        refresh_token,
+    })
+}
+
+/// Ends a session: access tokens for it stop being accepted and none of its
+/// refresh tokens can be used any more.
+pub fn revoke_session(conn: &mut SqliteConnection, session_id: i32) -> QueryResult<usize> {
+    diesel::update(
+        schema::sessions::table
+            .filter(schema::sessions::id.eq(session_id))
+            .filter(schema::sessions::revoked_at.is_null()),
+    )
+    .set(schema::sessions::revoked_at.eq(Utc::now().naive_utc()))
+    .execute(conn)
+}
+
+/// Ends all of a user's sessions except `keep`, e.g. after a password change.
+pub fn revoke_user_sessions(
+    conn: &mut SqliteConnection,
+    user_id: i32,
+    keep: Option<i32>,
+) -> QueryResult<usize> {
+    diesel::update(
+        schema::sessions::table
+            .filter(schema::sessions::user_id.eq(user_id))
+            .filter(schema::sessions::id.ne(keep.unwrap_or(-1)))
+            .filter(schema::sessions::revoked_at.is_null()),
+    )
+    .set(schema::sessions::revoked_at.eq(Utc::now().naive_utc()))
+    .execute(conn)
+}
+
+/// What happened to a presented refresh token.
+enum Rotation {
+    Rotated(ApiSessionToken),
+    Rejected,
+}
+
+fn rotate(
+    conn: &mut SqliteConnection,
+    app_config: &AppConfig,
+    presented: &str,
+    now: NaiveDateTime,
+) -> Result<Rotation, CustomError> {
+    let Some((token, session)) = schema::refresh_tokens::table
+        .inner_join(schema::sessions::table)
+        .filter(schema::refresh_tokens::token_hash.eq(security::hash_token(presented)))
+        .first::<(DbRefreshToken, DbSession)>(conn)
+        .optional()?
+    else {
+        return Ok(Rotation::Rejected);
+    };
+
+    if session.revoked_at.is_some() || token.expires_at <= now {
+        return Ok(Rotation::Rejected);
+    }
+
+    // Refresh tokens are single use. Marking this one used only succeeds once,
+    // so a second presentation means it leaked: whoever holds the family may
+    // be an attacker, and the whole session is shut down.
+    let claimed = diesel::update(
+        schema::refresh_tokens::table
+            .filter(schema::refresh_tokens::id.eq(token.id))
+            .filter(schema::refresh_tokens::used_at.is_null()),
+    )
+    .set(schema::refresh_tokens::used_at.eq(now))
+    .execute(conn)?
+        == 1;
+    if !claimed {
+        eprintln!(
+            "Refresh token reuse detected for session {}, revoking it",
+            session.id
+        );
+        revoke_session(conn, session.id)?;
+        return Ok(Rotation::Rejected);
+    }
+
+    Ok(Rotation::Rotated(issue_tokens(conn, app_config, &session)?))
+}
+
+// --- Routes ---
+
+#[post("/users/refresh", data = "<refresh_json>")]
+pub fn refresh(
+    pool: &State<DbPool>,
+    app_config: &State<AppConfig>,
+    refresh_json: Json<RefreshRequest>,
+) -> Result<Json<ApiSessionToken>, CustomError> {
+    let request = refresh_json.into_inner();
+    let mut conn = pool.get().expect("Failed to get DB connection from pool");
+
+    // The rejection is returned after the transaction commits, so a revocation
+    // caused by token reuse isn't rolled back along with it.
+    let rotation = conn.transaction(|conn| {
+        rotate(
+            conn,
+            app_config,
+            &request.refresh_token,
+            Utc::now().naive_utc(),
+        )
+    })?;
+
+    match rotation {
+        Rotation::Rotated(tokens) => Ok(Json(tokens)),
+        Rotation::Rejected => Err(CustomError::InvalidRefreshToken),
+    }
+}
+
+#[post("/users/logout")]
+pub fn logout(pool: &State<DbPool>, auth_user: AuthenticatedUser) -> Result<Status, CustomError> {
+    let mut conn = pool.get().expect("Failed to get DB connection from pool");
+
+    if let Some(session_id) = auth_user.session_id {
+        revoke_session(&mut conn, session_id)?;
+    }
+    Ok(Status::NoContent)
+}
+
+#[get("/users/sessions")]
+pub fn list_sessions(
+    pool: &State<DbPool>,
+    auth_user: AuthenticatedUser,
+) -> Result<Json<Vec<ApiSession>>, CustomError> {
+    let mut conn = pool.get().expect("Failed to get DB connection from pool");
+
+    let sessions = schema::sessions::table
+        .filter(schema::sessions::user_id.eq(auth_user.user_id))
+        .filter(schema::sessions::revoked_at.is_null())
+        .order(schema::sessions::last_seen_at.desc())
+        .load::<DbSession>(&mut conn)?;
+
+    Ok(Json(
+        sessions
+            .into_iter()
+            .map(|session| ApiSession {
+                id: session.id,
+                user_agent: session.user_agent,
+                ip: session.ip,
+                created_at: DateTime::<Utc>::from_naive_utc_and_offset(session.created_at, Utc),
+                last_seen_at: DateTime::<Utc>::from_naive_utc_and_offset(session.last_seen_at, Utc),
+                current: Some(session.id) == auth_user.session_id,
+            })
+            .collect(),
+    ))
+}
+
+#[delete("/users/sessions/<id>")]
+pub fn delete_session(
+    pool: &State<DbPool>,
+    auth_user: AuthenticatedUser,
+    id: i32,
+) -> Result<Status, CustomError> {
+    let mut conn = pool.get().expect("Failed to get DB connection from pool");
+
+    let revoked = diesel::update(
+        schema::sessions::table
+            .filter(schema::sessions::id.eq(id))
+            .filter(schema::sessions::user_id.eq(auth_user.user_id))
+            .filter(schema::sessions::revoked_at.is_null()),
+    )
+    .set(schema::sessions::revoked_at.eq(Utc::now().naive_utc()))
+    .execute(&mut conn)?;
+
+    if revoked == 0 {
+        return Err(CustomError::NotFound);
+    }
+    Ok(Status::NoContent)
+}
@@ -0,0 +1,311 @@
+//! Comments on todos. Anyone who may comment on a todo can add to its
+//! thread; only a comment's author can change or delete it. `@username`
+//! mentions are recorded and the people mentioned are told about them.
+
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use diesel::sql_types::Text;
+use rocket::State;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+
+use crate::access::{self, Action};
+use crate::registration::is_username_char;
+use crate::{
+    AuthenticatedUser, CustomError, DbPool, DbTodo, EventKind, Events, TodoEvent, api,
+    load_api_todo, models, schema,
+};
+
+type DbComment = models::Comment;
+type DbInsertableComment = models::InsertableComment;
+type DbCommentMention = models::CommentMention;
+type ApiComment = api::Comment;
+type ApiTodo = api::Todo;
+type NewComment = api::NewComment;
+
+const MAX_BODY_LEN: usize = 10_000;
+
+define_sql_function!(fn lower(x: Text) -> Text);
+
+fn check_body(body: &str) -> Result<(), CustomError> {
+    if body.trim().is_empty() || body.chars().count() > MAX_BODY_LEN {
+        return Err(CustomError::InvalidInput(format!(
+            "Comments must be between 1 and {} characters long.",
+            MAX_BODY_LEN
+        )));
+    }
+    Ok(())
+}
+
+/// The usernames `@mentioned` in a comment, lowercased and without
+/// duplicates. An `@` inside a word, as in an email address, isn't a
+/// mention.
+fn mentioned_names(body: &str) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    let mut previous = None;
+    let mut chars = body.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '@' && !previous.is_some_and(is_username_char) {
+            let mut name = String::new();
+            while let Some(&next) = chars.peek().filter(|&&next| is_username_char(next)) {
+                name.push(next.to_ascii_lowercase());
+                chars.next();
+            }
+            // "Thanks @bob." mentions bob.
+            let name = name.trim_end_matches('.');
+            if !name.is_empty() && !names.iter().any(|seen| seen == name) {
+                names.push(name.to_string());
+            }
+            previous = name.chars().last();
+            continue;
+        }
+        previous = Some(c);
+    }
+    names
+}
+
+/// Records who a comment mentions: the named users that can see the todo,
+/// other than the author. Returns the ones that weren't mentioned before.
+fn set_mentions(
+    conn: &mut SqliteConnection,
+    comment: &DbComment,
+    todo: &DbTodo,
+) -> QueryResult<Vec<i32>> {
+    let candidates = schema::users::table
+        .filter(lower(schema::users::username).eq_any(mentioned_names(&comment.body)))
+        .filter(schema::users::id.ne(comment.user_id))
+        .select(schema::users::id)
+        .load::<i32>(conn)?;
+    let mut wanted = Vec::new();
+    for user_id in candidates {
+        if access::todo_role(conn, user_id, todo)?.is_some() {
+            wanted.push(user_id);
+        }
+    }
+
+    let existing = schema::comment_mentions::table
+        .filter(schema::comment_mentions::comment_id.eq(comment.id))
+        .select(schema::comment_mentions::user_id)
+        .load::<i32>(conn)?;
+    diesel::delete(
+        schema::comment_mentions::table
+            .filter(schema::comment_mentions::comment_id.eq(comment.id))
+            .filter(schema::comment_mentions::user_id.ne_all(&wanted)),
+    )
+    .execute(conn)?;
+    let added: Vec<i32> = wanted
+        .into_iter()
+        .filter(|user_id| !existing.contains(user_id))
+        .collect();
+    let rows: Vec<DbCommentMention> = added
+        .iter()
+        .map(|&user_id| DbCommentMention {
+            comment_id: comment.id,
+            user_id,
+        })
+        .collect();
+    diesel::insert_into(schema::comment_mentions::table)
+        .values(&rows)
+        .execute(conn)?;
+    Ok(added)
+}
+
+fn to_api_comments(
+    conn: &mut SqliteConnection,
+    comments: Vec<DbComment>,
+) -> QueryResult<Vec<ApiComment>> {
+    let mentions = DbCommentMention::belonging_to(&comments)
+        .inner_join(schema::users::table)
+        .select((DbCommentMention::as_select(), schema::users::username))
+        .order(lower(schema::users::username).asc())
+        .load::<(DbCommentMention, String)>(conn)?
+        .grouped_by(&comments);
+
+    let mut api_comments = Vec::new();
+    for (comment, mentions) in comments.into_iter().zip(mentions) {
+        let author = schema::users::table
+            .find(comment.user_id)
+            .select(schema::users::username)
+            .first::<String>(conn)?;
+        api_comments.push(ApiComment {
+            id: comment.id,
+            todo_id: comment.todo_id,
+            author_id: comment.user_id,
+            author,
+            body: comment.body,
+            mentions: mentions.into_iter().map(|(_, username)| username).collect(),
+            created_at: DateTime::<Utc>::from_naive_utc_and_offset(comment.created_at, Utc),
+            edited_at: comment
+                .edited_at
+                .map(|edited_at| DateTime::<Utc>::from_naive_utc_and_offset(edited_at, Utc)),
+        });
+    }
+    Ok(api_comments)
+}
+
+fn to_api_comment(conn: &mut SqliteConnection, comment: DbComment) -> QueryResult<ApiComment> {
+    Ok(to_api_comments(conn, vec![comment])?.remove(0))
+}
+
+/// Finds one of the user's own comments on a todo they may comment on.
+fn find_own(
+    conn: &mut SqliteConnection,
+    user_id: i32,
+    todo_id: i32,
+    comment_id: i32,
+) -> Result<(DbTodo, DbComment), CustomError> {
+    let todo = access::authorize(conn, user_id, todo_id, Action::Comment)?;
+    let comment = schema::comments::table
+        .filter(schema::comments::todo_id.eq(todo_id))
+        .filter(schema::comments::id.eq(comment_id))
+        .first::<DbComment>(conn)
+        .optional()?
+        .ok_or(CustomError::NotFound)?;
+    if comment.user_id != user_id {
+        return Err(CustomError::Forbidden);
+    }
+    Ok((todo, comment))
+}
+
+fn emit_mentioned(events: &Events, mentioned: Vec<i32>, todo: Option<ApiTodo>) {
+    if let Some(todo) = todo {
+        for user_id in mentioned {
+            events.emit(TodoEvent::new(
+                EventKind::TodoMentioned,
+                user_id,
+                todo.clone(),
+            ));
+        }
+    }
+}
+
+/// Deletes the comments on the given todos, with their mentions.
+pub fn delete_for_todos(conn: &mut SqliteConnection, todo_ids: &[i32]) -> QueryResult<()> {
+    let comment_ids = schema::comments::table
+        .filter(schema::comments::todo_id.eq_any(todo_ids))
+        .select(schema::comments::id);
+    diesel::delete(
+        schema::comment_mentions::table
+            .filter(schema::comment_mentions::comment_id.eq_any(comment_ids)),
+    )
+    .execute(conn)?;
+    diesel::delete(schema::comments::table.filter(schema::comments::todo_id.eq_any(todo_ids)))
+        .execute(conn)?;
+    Ok(())
+}
+
+// --- Routes ---
+
+/// Lists a todo's comments, oldest first.
+#[get("/todos/<id>/comments")]
+pub fn list_comments(
+    pool: &State<DbPool>,
+    auth_user: AuthenticatedUser,
+    id: i32,
+) -> Result<Json<Vec<ApiComment>>, CustomError> {
+    let mut conn = pool.get().expect("Failed to get DB connection from pool");
+
+    conn.transaction(|conn| {
+        access::authorize(conn, auth_user.user_id, id, Action::View)?;
+        let comments = schema::comments::table
+            .filter(schema::comments::todo_id.eq(id))
+            .order((
+                schema::comments::created_at.asc(),
+                schema::comments::id.asc(),
+            ))
+            .load::<DbComment>(conn)?;
+        Ok(Json(to_api_comments(conn, comments)?))
+    })
+}
+
+#[post("/todos/<id>/comments", data = "<comment_json>")]
+pub fn add_comment(
+    pool: &State<DbPool>,
+    events: &State<Events>,
+    auth_user: AuthenticatedUser,
+    id: i32,
+    comment_json: Json<NewComment>,
+) -> Result<Json<ApiComment>, CustomError> {
+    let new_comment = comment_json.into_inner();
+    check_body(&new_comment.body)?;
+    let mut conn = pool.get().expect("Failed to get DB connection from pool");
+
+    let (comment, mentioned, todo) = conn.transaction(|conn| {
+        let todo = access::authorize(conn, auth_user.user_id, id, Action::Comment)?;
+        let comment = diesel::insert_into(schema::comments::table)
+            .values(&DbInsertableComment {
+                todo_id: id,
+                user_id: auth_user.user_id,
+                body: new_comment.body,
+                created_at: Utc::now().naive_utc(),
+            })
+            .get_result::<DbComment>(conn)?;
+        let mentioned = set_mentions(conn, &comment, &todo)?;
+        let todo = if mentioned.is_empty() {
+            None
+        } else {
+            Some(load_api_todo(conn, todo)?)
+        };
+        Ok::<_, CustomError>((to_api_comment(conn, comment)?, mentioned, todo))
+    })?;
+
+    emit_mentioned(events, mentioned, todo);
+    Ok(Json(comment))
+}
+
+/// Changes the text of one of the user's comments. People newly mentioned
+/// are told; ones mentioned before aren't told again.
+#[put("/todos/<id>/comments/<comment_id>", data = "<comment_json>")]
+pub fn update_comment(
+    pool: &State<DbPool>,
+    events: &State<Events>,
+    auth_user: AuthenticatedUser,
+    id: i32,
+    comment_id: i32,
+    comment_json: Json<NewComment>,
+) -> Result<Json<ApiComment>, CustomError> {
+    let body = comment_json.into_inner().body;
+    check_body(&body)?;
+    let mut conn = pool.get().expect("Failed to get DB connection from pool");
+
+    let (comment, mentioned, todo) = conn.transaction(|conn| {
+        let (todo, comment) = find_own(conn, auth_user.user_id, id, comment_id)?;
+        let comment = diesel::update(schema::comments::table.find(comment.id))
+            .set((
+                schema::comments::body.eq(body),
+                schema::comments::edited_at.eq(Utc::now().naive_utc()),
+            ))
+            .get_result::<DbComment>(conn)?;
+        let mentioned = set_mentions(conn, &comment, &todo)?;
+        let todo = if mentioned.is_empty() {
+            None
+        } else {
+            Some(load_api_todo(conn, todo)?)
+        };
+        Ok::<_, CustomError>((to_api_comment(conn, comment)?, mentioned, todo))
+    })?;
+
+    emit_mentioned(events, mentioned, todo);
+    Ok(Json(comment))
+}
+
+#[delete("/todos/<id>/comments/<comment_id>")]
+pub fn delete_comment(
+    pool: &State<DbPool>,
+    auth_user: AuthenticatedUser,
+    id: i32,
+    comment_id: i32,
+) -> Result<Status, CustomError> {
+    let mut conn = pool.get().expect("Failed to get DB connection from pool");
+
+    conn.transaction(|conn| {
+        let (_, comment) = find_own(conn, auth_user.user_id, id, comment_id)?;
+        diesel::delete(
+            schema::comment_mentions::table
+                .filter(schema::comment_mentions::comment_id.eq(comment.id)),
+        )
+        .execute(conn)?;
+        diesel::delete(schema::comments::table.find(comment.id)).execute(conn)?;
+        Ok(Status::NoContent)
+    })
+}
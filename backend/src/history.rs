@@ -0,0 +1,240 @@
+//! A log of changes to todos: who changed which field, when, and from what
+//! to what. Values are stored as JSON so any field fits in the same columns.
+//! Entries are written in the same transaction as the change they describe.
+
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use diesel::sqlite::Sqlite;
+use rocket::State;
+use rocket::serde::Serialize;
+use rocket::serde::json::{self, Json};
+
+use crate::access::{self, Action};
+use crate::{AuthenticatedUser, CustomError, DbPool, api, models, schema};
+
+type DbHistoryEntry = models::HistoryEntry;
+type DbInsertableHistoryEntry = models::InsertableHistoryEntry;
+type ApiHistoryEntry = api::HistoryEntry;
+type ApiTodo = api::Todo;
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 200;
+
+/// The JSON for a value, or `None` if it's null.
+fn encode<T: Serialize>(value: &T) -> Option<String> {
+    json::to_string(value).ok().filter(|value| value != "null")
+}
+
+/// Records that `user_id` changed `field` of a todo from `old` to `new`.
+/// Nothing is written if the two are the same.
+pub fn record<T: Serialize>(
+    conn: &mut SqliteConnection,
+    todo_id: i32,
+    user_id: i32,
+    field: &str,
+    old: &T,
+    new: &T,
+) -> QueryResult<()> {
+    let (old_value, new_value) = (encode(old), encode(new));
+    if old_value == new_value {
+        return Ok(());
+    }
+    diesel::insert_into(schema::todo_history::table)
+        .values(&DbInsertableHistoryEntry {
+            todo_id,
+            user_id,
+            field: field.to_string(),
+            old_value,
+            new_value,
+            changed_at: Utc::now().naive_utc(),
+        })
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Records that `user_id` added a todo.
+pub fn record_created(
+    conn: &mut SqliteConnection,
+    todo_id: i32,
+    user_id: i32,
+    title: &str,
+) -> QueryResult<()> {
+    record(conn, todo_id, user_id, "created", &None, &Some(title))
+}
+
+/// Records every field that differs between two versions of a todo.
+/// Assignees are left out; `assignees` records those as it changes them.
+pub fn record_changes(
+    conn: &mut SqliteConnection,
+    user_id: i32,
+    before: &ApiTodo,
+    after: &ApiTodo,
+) -> QueryResult<()> {
+    let id = after.id;
+    record(conn, id, user_id, "title", &before.title, &after.title)?;
+    record(
+        conn,
+        id,
+        user_id,
+        "description",
+        &before.description,
+        &after.description,
+    )?;
+    record(conn, id, user_id, "due", &before.due, &after.due)?;
+    record(conn, id, user_id, "repeat", &before.repeat, &after.repeat)?;
+    record(
+        conn,
+        id,
+        user_id,
+        "repeat_from",
+        &before.repeat_from,
+        &after.repeat_from,
+    )?;
+    record(
+        conn,
+        id,
+        user_id,
+        "completed",
+        &before.completed,
+        &after.completed,
+    )?;
+    record(conn, id, user_id, "status", &before.status, &after.status)?;
+    record(
+        conn,
+        id,
+        user_id,
+        "priority",
+        &before.priority,
+        &after.priority,
+    )?;
+    record(
+        conn,
+        id,
+        user_id,
+        "project_id",
+        &before.project_id,
+        &after.project_id,
+    )?;
+    record(
+        conn,
+        id,
+        user_id,
+        "parent_id",
+        &before.parent_id,
+        &after.parent_id,
+    )?;
+    record(
+        conn,
+        id,
+        user_id,
+        "auto_complete",
+        &before.auto_complete,
+        &after.auto_complete,
+    )?;
+
+    // Reminders and tags are sets; only what's in them counts, not the order.
+    let (mut old_reminders, mut new_reminders) = (before.reminder.clone(), after.reminder.clone());
+    old_reminders.sort();
+    new_reminders.sort();
+    record(
+        conn,
+        id,
+        user_id,
+        "reminder",
+        &old_reminders,
+        &new_reminders,
+    )?;
+    let (mut old_tags, mut new_tags) = (before.tags.clone(), after.tags.clone());
+    old_tags.sort_by_key(|tag| tag.to_ascii_lowercase());
+    new_tags.sort_by_key(|tag| tag.to_ascii_lowercase());
+    record(conn, id, user_id, "tags", &old_tags, &new_tags)
+}
+
+fn decode(value: Option<String>) -> Option<json::Value> {
+    value.and_then(|value| json::from_str(&value).ok())
+}
+
+fn to_api_entry(
+    entry: DbHistoryEntry,
+    todo_title: String,
+    username: Option<String>,
+) -> ApiHistoryEntry {
+    ApiHistoryEntry {
+        id: entry.id,
+        todo_id: entry.todo_id,
+        todo_title,
+        user_id: entry.user_id,
+        username,
+        field: entry.field,
+        old_value: decode(entry.old_value),
+        new_value: decode(entry.new_value),
+        changed_at: DateTime::<Utc>::from_naive_utc_and_offset(entry.changed_at, Utc),
+    }
+}
+
+/// Loads a page of history entries for the given todos, newest first.
+fn load_entries(
+    conn: &mut SqliteConnection,
+    todos: schema::todos::BoxedQuery<'static, Sqlite>,
+    offset: Option<i64>,
+    limit: Option<i64>,
+) -> QueryResult<Vec<ApiHistoryEntry>> {
+    let entries = schema::todo_history::table
+        .inner_join(schema::todos::table)
+        .left_join(schema::users::table)
+        .filter(schema::todo_history::todo_id.eq_any(todos.select(schema::todos::id)))
+        .order((
+            schema::todo_history::changed_at.desc(),
+            schema::todo_history::id.desc(),
+        ))
+        .offset(offset.unwrap_or(0).max(0))
+        .limit(limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE))
+        .select((
+            DbHistoryEntry::as_select(),
+            schema::todos::title,
+            schema::users::username.nullable(),
+        ))
+        .load::<(DbHistoryEntry, String, Option<String>)>(conn)?;
+
+    Ok(entries
+        .into_iter()
+        .map(|(entry, todo_title, username)| to_api_entry(entry, todo_title, username))
+        .collect())
+}
+
+// --- Routes ---
+
+/// Lists the changes made to a todo, newest first.
+#[get("/todos/<id>/history?<offset>&<limit>")]
+pub fn todo_history(
+    pool: &State<DbPool>,
+    auth_user: AuthenticatedUser,
+    id: i32,
+    offset: Option<i64>,
+    limit: Option<i64>,
+) -> Result<Json<Vec<ApiHistoryEntry>>, CustomError> {
+    let mut conn = pool.get().expect("Failed to get DB connection from pool");
+
+    conn.transaction(|conn| {
+        access::authorize(conn, auth_user.user_id, id, Action::View)?;
+        let todo = schema::todos::table.find(id).into_boxed();
+        Ok(Json(load_entries(conn, todo, offset, limit)?))
+    })
+}
+
+/// Lists the changes made to every todo the user can see, in the trash or
+/// not, by anyone, newest first.
+#[get("/activity?<offset>&<limit>")]
+pub fn list_activity(
+    pool: &State<DbPool>,
+    auth_user: AuthenticatedUser,
+    offset: Option<i64>,
+    limit: Option<i64>,
+) -> Result<Json<Vec<ApiHistoryEntry>>, CustomError> {
+    let mut conn = pool.get().expect("Failed to get DB connection from pool");
+
+    conn.transaction(|conn| {
+        let todos = access::reachable_todos(conn, auth_user.user_id)?;
+        Ok(Json(load_entries(conn, todos, offset, limit)?))
+    })
+}
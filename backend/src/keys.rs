@@ -0,0 +1,369 @@
+//! The keys access tokens are signed with.
+//!
+//! The first key of the ring that hasn't been retired signs new tokens;
+//! every key in it is accepted when verifying until it's retired, so a new
+//! key can be rolled out while tokens signed with the previous one are still
+//! in circulation. Asymmetric keys are published at `/.well-known/jwks.json`
+//! for other services to verify our tokens with.
+
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use chrono::{DateTime, Utc};
+use jsonwebtoken::jwk::{
+    AlgorithmParameters, CommonParameters, Jwk, JwkSet, KeyAlgorithm, OctetKeyPairParameters,
+    OctetKeyPairType, PublicKeyUse, RSAKeyParameters, RSAKeyType,
+};
+use jsonwebtoken::{
+    Algorithm, DecodingKey, EncodingKey, Header, Validation, decode, decode_header, encode,
+};
+use ring::signature::{Ed25519KeyPair, KeyPair};
+use rocket::State;
+use rocket::serde::json::Json;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use sha2::{Digest, Sha256};
+use std::{env, fs};
+
+use crate::AppConfig;
+
+pub struct SigningKey {
+    pub kid: String,
+    pub algorithm: Algorithm,
+    /// After this the key is no longer accepted, ending its grace period.
+    pub not_after: Option<DateTime<Utc>>,
+    encoding: EncodingKey,
+    decoding: DecodingKey,
+    /// The public half for the JWKS; `None` for shared secrets.
+    public_jwk: Option<Jwk>,
+}
+
+impl SigningKey {
+    /// An HS256 key from a shared secret.
+    pub fn hmac(kid: String, secret: &[u8]) -> Self {
+        SigningKey {
+            kid,
+            algorithm: Algorithm::HS256,
+            not_after: None,
+            encoding: EncodingKey::from_secret(secret),
+            decoding: DecodingKey::from_secret(secret),
+            public_jwk: None,
+        }
+    }
+
+    /// An RS256 key from a PKCS#1 or PKCS#8 PEM private key.
+    pub fn rsa(kid: String, pem: &[u8]) -> Result<Self, String> {
+        let der = pem::parse(pem).map_err(|e| e.to_string())?;
+        let key_pair = match der.tag() {
+            "RSA PRIVATE KEY" => ring::rsa::KeyPair::from_der(der.contents()),
+            _ => ring::rsa::KeyPair::from_pkcs8(der.contents()),
+        }
+        .map_err(|e| e.to_string())?;
+        let public = ring::rsa::PublicKeyComponents::<Vec<u8>>::from(key_pair.public());
+        let n = URL_SAFE_NO_PAD.encode(&public.n);
+        let e = URL_SAFE_NO_PAD.encode(&public.e);
+
+        Ok(SigningKey {
+            algorithm: Algorithm::RS256,
+            not_after: None,
+            encoding: EncodingKey::from_rsa_pem(pem).map_err(|e| e.to_string())?,
+            decoding: DecodingKey::from_rsa_raw_components(&public.n, &public.e),
+            public_jwk: Some(Jwk {
+                common: common_parameters(&kid, KeyAlgorithm::RS256),
+                algorithm: AlgorithmParameters::RSA(RSAKeyParameters {
+                    key_type: RSAKeyType::RSA,
+                    n,
+                    e,
+                }),
+            }),
+            kid,
+        })
+    }
+
+    /// An EdDSA key from a PKCS#8 PEM Ed25519 private key.
+    pub fn ed25519(kid: String, pem: &[u8]) -> Result<Self, String> {
+        let der = pem::parse(pem).map_err(|e| e.to_string())?;
+        let key_pair = Ed25519KeyPair::from_pkcs8_maybe_unchecked(der.contents())
+            .map_err(|e| e.to_string())?;
+        let x = URL_SAFE_NO_PAD.encode(key_pair.public_key().as_ref());
+
+        Ok(SigningKey {
+            algorithm: Algorithm::EdDSA,
+            not_after: None,
+            encoding: EncodingKey::from_ed_pem(pem).map_err(|e| e.to_string())?,
+            decoding: DecodingKey::from_ed_components(&x).map_err(|e| e.to_string())?,
+            public_jwk: Some(Jwk {
+                common: common_parameters(&kid, KeyAlgorithm::EdDSA),
+                algorithm: AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                    key_type: OctetKeyPairType::OctetKeyPair,
+                    curve: jsonwebtoken::jwk::EllipticCurve::Ed25519,
+                    x,
+                }),
+            }),
+            kid,
+        })
+    }
+
+    fn accepted_at(&self, now: DateTime<Utc>) -> bool {
+        self.not_after.is_none_or(|not_after| now < not_after)
+    }
+}
+
+fn common_parameters(kid: &str, algorithm: KeyAlgorithm) -> CommonParameters {
+    CommonParameters {
+        public_key_use: Some(PublicKeyUse::Signature),
+        key_id: Some(kid.to_string()),
+        key_algorithm: Some(algorithm),
+        ..Default::default()
+    }
+}
+
+pub struct Keyring {
+    /// Newest first. The first key still accepted signs; all of them verify.
+    keys: Vec<SigningKey>,
+    /// Key for tokens issued before tokens carried a `kid`.
+    legacy: Option<usize>,
+}
+
+impl Keyring {
+    /// Reads the keyring from `JWT_KEYS` and `JWT_SECRET`.
+    ///
+    /// `JWT_KEYS` lists keys separated by `;`, each as
+    /// `<kid> <HS256|RS256|EdDSA> <path> [<not after, RFC 3339>]`, where the
+    /// file holds the shared secret or the PEM private key. The first key
+    /// that hasn't passed its `not after` signs new tokens; to rotate, put
+    /// the new key first and give the old one a `not after` at least one
+    /// access token lifetime away.
+    ///
+    /// `JWT_SECRET` is still honoured: it verifies tokens issued without a
+    /// `kid` and, if `JWT_KEYS` is unset, signs new ones as an HS256 key.
+    pub fn from_env() -> Self {
+        let mut keys = Vec::new();
+        if let Ok(specs) = env::var("JWT_KEYS") {
+            for spec in specs.split(';').map(str::trim).filter(|s| !s.is_empty()) {
+                keys.push(
+                    parse_key_spec(spec)
+                        .unwrap_or_else(|e| panic!("Invalid JWT_KEYS entry \"{}\": {}", spec, e)),
+                );
+            }
+        }
+
+        let mut legacy = None;
+        if let Ok(secret) = env::var("JWT_SECRET") {
+            // Derived, so the kid stays the same across restarts without
+            // giving anything away about the secret.
+            let kid = format!("hs256-{}", &hex::encode(Sha256::digest(&secret))[..8]);
+            legacy = Some(keys.len());
+            keys.push(SigningKey::hmac(kid, secret.as_bytes()));
+        }
+
+        if keys.is_empty() {
+            panic!("JWT_KEYS or JWT_SECRET must be set");
+        }
+        let keyring = Keyring { keys, legacy };
+        if keyring.signing_key(Utc::now()).is_none() {
+            panic!("Every key in JWT_KEYS is past its not after date");
+        }
+        keyring
+    }
+
+    /// The newest key that hasn't been retired yet.
+    fn signing_key(&self, now: DateTime<Utc>) -> Option<&SigningKey> {
+        self.keys.iter().find(|key| key.accepted_at(now))
+    }
+
+    /// Signs `claims` with the current key, naming it in the `kid` header.
+    pub fn encode<T: Serialize>(&self, claims: &T) -> Result<String, jsonwebtoken::errors::Error> {
+        // Only possible if the last key retires while the server is running.
+        let key = self
+            .signing_key(Utc::now())
+            .ok_or(jsonwebtoken::errors::ErrorKind::InvalidKeyFormat)?;
+        let mut header = Header::new(key.algorithm);
+        header.kid = Some(key.kid.clone());
+        encode(&header, claims, &key.encoding)
+    }
+
+    /// Verifies a token against the key named by its `kid` and decodes its
+    /// claims. Expired tokens and unknown or retired keys are rejected.
+    pub fn decode<T: DeserializeOwned>(
+        &self,
+        token: &str,
+    ) -> Result<T, jsonwebtoken::errors::Error> {
+        let header = decode_header(token)?;
+        let key = match &header.kid {
+            Some(kid) => self.keys.iter().find(|key| &key.kid == kid),
+            None => self.legacy.map(|index| &self.keys[index]),
+        }
+        .filter(|key| key.accepted_at(Utc::now()))
+        .ok_or(jsonwebtoken::errors::ErrorKind::InvalidSignature)?;
+
+        // Pinning the algorithm to the key's stops a token from picking a
+        // weaker one through its header.
+        let validation = Validation::new(key.algorithm);
+        Ok(decode::<T>(token, &key.decoding, &validation)?.claims)
+    }
+
+    /// The public keys of the ring that are still accepted.
+    pub fn jwks(&self) -> JwkSet {
+        let now = Utc::now();
+        JwkSet {
+            keys: self
+                .keys
+                .iter()
+                .filter(|key| key.accepted_at(now))
+                .filter_map(|key| key.public_jwk.clone())
+                .collect(),
+        }
+    }
+}
+
+fn parse_key_spec(spec: &str) -> Result<SigningKey, String> {
+    let fields: Vec<&str> = spec.split_whitespace().collect();
+    let [kid, algorithm, path, rest @ ..] = fields.as_slice() else {
+        return Err(String::from(
+            "expected <kid> <algorithm> <path> [<not after>]",
+        ));
+    };
+    let contents = fs::read(path).map_err(|e| format!("failed to read {}: {}", path, e))?;
+
+    let mut key = match *algorithm {
+        "HS256" => SigningKey::hmac(kid.to_string(), trim_secret(&contents)),
+        "RS256" => SigningKey::rsa(kid.to_string(), &contents)?,
+        "EdDSA" => SigningKey::ed25519(kid.to_string(), &contents)?,
+        other => return Err(format!("unsupported algorithm {}", other)),
+    };
+    key.not_after = match rest {
+        [] => None,
+        [not_after] => Some(
+            DateTime::parse_from_rfc3339(not_after)
+                .map_err(|e| format!("invalid not after date: {}", e))?
+                .with_timezone(&Utc),
+        ),
+        _ => return Err(String::from("too many fields")),
+    };
+    Ok(key)
+}
+
+/// Secrets are usually written with a trailing newline, which isn't meant to
+/// be part of them.
+fn trim_secret(contents: &[u8]) -> &[u8] {
+    contents.trim_ascii_end()
+}
+
+#[get("/.well-known/jwks.json")]
+pub fn jwks(app_config: &State<AppConfig>) -> Json<JwkSet> {
+    Json(app_config.keys.jwks())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+    use serde::Deserialize;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Claims {
+        sub: String,
+        exp: i64,
+    }
+
+    fn claims() -> Claims {
+        Claims {
+            sub: String::from("1"),
+            exp: (Utc::now() + Duration::minutes(5)).timestamp(),
+        }
+    }
+
+    fn key(kid: &str, not_after: Option<DateTime<Utc>>) -> SigningKey {
+        let mut key = SigningKey::hmac(kid.to_string(), format!("secret of {}", kid).as_bytes());
+        key.not_after = not_after;
+        key
+    }
+
+    fn kid_of(token: &str) -> Option<String> {
+        decode_header(token).unwrap().kid
+    }
+
+    #[test]
+    fn signs_with_the_first_key_still_accepted() {
+        let retired = Utc::now() - Duration::minutes(1);
+        let keyring = Keyring {
+            keys: vec![
+                key("old", Some(retired)),
+                key("new", None),
+                key("older", None),
+            ],
+            legacy: None,
+        };
+        let claims = claims();
+        let token = keyring.encode(&claims).unwrap();
+        assert_eq!(kid_of(&token).as_deref(), Some("new"));
+        assert_eq!(keyring.decode::<Claims>(&token).unwrap(), claims);
+    }
+
+    #[test]
+    fn verifies_with_the_key_named_by_kid() {
+        let new = Keyring {
+            keys: vec![key("new", None), key("old", None)],
+            legacy: None,
+        };
+        let old = Keyring {
+            keys: vec![key("old", None)],
+            legacy: None,
+        };
+        let token = old.encode(&claims()).unwrap();
+        assert!(new.decode::<Claims>(&token).is_ok());
+
+        // Same kid, different secret: the signature doesn't check out.
+        let impostor = Keyring {
+            keys: vec![SigningKey::hmac(String::from("old"), b"something else")],
+            legacy: None,
+        };
+        assert!(
+            new.decode::<Claims>(&impostor.encode(&claims()).unwrap())
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_and_retired_kids() {
+        let other = Keyring {
+            keys: vec![key("other", None)],
+            legacy: None,
+        };
+        let token = other.encode(&claims()).unwrap();
+
+        let without = Keyring {
+            keys: vec![key("new", None)],
+            legacy: None,
+        };
+        assert!(without.decode::<Claims>(&token).is_err());
+
+        let retired = Keyring {
+            keys: vec![
+                key("new", None),
+                key("other", Some(Utc::now() - Duration::minutes(1))),
+            ],
+            legacy: None,
+        };
+        assert!(retired.decode::<Claims>(&token).is_err());
+    }
+
+    #[test]
+    fn tokens_without_a_kid_use_the_legacy_key() {
+        let legacy = key("legacy", None);
+        let token = encode(&Header::default(), &claims(), &legacy.encoding).unwrap();
+        assert_eq!(kid_of(&token), None);
+
+        let with_legacy = Keyring {
+            keys: vec![key("new", None), legacy],
+            legacy: Some(1),
+        };
+        assert!(with_legacy.decode::<Claims>(&token).is_ok());
+
+        let without_legacy = Keyring {
+            keys: vec![key("new", None), key("legacy", None)],
+            legacy: None,
+        };
+        assert!(without_legacy.decode::<Claims>(&token).is_err());
+    }
+}
@@ -0,0 +1,544 @@
+use chrono::{DateTime, NaiveDate, Timelike, Utc};
+use diesel::prelude::*;
+use lettre::message::{Mailbox, MultiPart};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::tokio;
+use rocket::{Orbit, Rocket, Shutdown};
+use std::env;
+use std::time::Duration;
+
+use crate::api::{EventKind, Todo};
+use crate::notifier::{DueReminder, Notifier, NotifyError, TodoEvent};
+use crate::scheduler::with_conn;
+use crate::{DbPool, DbReminder, DbTodo, DbUser, assignees, schema, tags, to_api_todo};
+
+/// How often the digest task checks whether anyone is due a digest.
+const DIGEST_CHECK_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+// --- Configuration ---
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SmtpSecurity {
+    /// Plain SMTP, e.g. for a local sink such as MailHog.
+    None,
+    StartTls,
+    Tls,
+}
+
+#[derive(Debug, Clone)]
+pub struct SmtpConfig {
+    pub host: String,
+    pub port: u16,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub from: String,
+    pub security: SmtpSecurity,
+}
+
+impl SmtpConfig {
+    /// Reads `SMTP_HOST`, `SMTP_PORT`, `SMTP_USERNAME`, `SMTP_PASSWORD`,
+    /// `SMTP_FROM` and `SMTP_SECURITY` (`none`, `starttls` or `tls`). Returns
+    /// `None` when `SMTP_HOST` is unset, which disables sending.
+    pub fn from_env() -> Option<Self> {
+        let host = env::var("SMTP_HOST").ok()?;
+        let security = match env::var("SMTP_SECURITY").as_deref() {
+            Ok("none") => SmtpSecurity::None,
+            Ok("tls") => SmtpSecurity::Tls,
+            Ok("starttls") | Err(_) => SmtpSecurity::StartTls,
+            Ok(other) => panic!("SMTP_SECURITY must be none, starttls or tls, not {}", other),
+        };
+        let default_port = match security {
+            SmtpSecurity::None => 25,
+            SmtpSecurity::StartTls => 587,
+            SmtpSecurity::Tls => 465,
+        };
+
+        Some(SmtpConfig {
+            host,
+            port: env::var("SMTP_PORT")
+                .map(|p| p.parse().expect("SMTP_PORT must be a port number"))
+                .unwrap_or(default_port),
+            username: env::var("SMTP_USERNAME").ok(),
+            password: env::var("SMTP_PASSWORD").ok(),
+            from: env::var("SMTP_FROM").expect("SMTP_FROM must be set when SMTP_HOST is"),
+            security,
+        })
+    }
+}
+
+// --- Sending ---
+
+/// A rendered email with both a plaintext and an HTML body.
+pub struct Email {
+    pub subject: String,
+    pub text: String,
+    pub html: String,
+}
+
+/// Sends email over SMTP. Without SMTP configuration only the recipient and
+/// subject are logged, since bodies carry live verification and reset links.
+/// `MAIL_LOG_BODIES=1` logs the bodies too, so those flows can be followed
+/// locally.
+#[derive(Clone)]
+pub struct Mailer {
+    transport: Option<AsyncSmtpTransport<Tokio1Executor>>,
+    from: Option<Mailbox>,
+    log_bodies: bool,
+}
+
+impl Mailer {
+    pub fn new(config: Option<SmtpConfig>) -> Self {
+        let Some(config) = config else {
+            return Mailer {
+                transport: None,
+                from: None,
+                log_bodies: env::var("MAIL_LOG_BODIES").is_ok_and(|v| v == "1" || v == "true"),
+            };
+        };
+
+        let builder = match config.security {
+            SmtpSecurity::None => {
+                AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&config.host)
+            }
+            SmtpSecurity::StartTls => {
+                AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.host)
+                    .expect("Failed to configure SMTP STARTTLS.")
+            }
+            SmtpSecurity::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&config.host)
+                .expect("Failed to configure SMTP TLS."),
+        };
+        let mut builder = builder.port(config.port);
+        if let (Some(username), Some(password)) = (config.username, config.password) {
+            builder = builder.credentials(Credentials::new(username, password));
+        }
+
+        Mailer {
+            transport: Some(builder.build()),
+            from: Some(
+                config
+                    .from
+                    .parse()
+                    .expect("SMTP_FROM must be a valid address"),
+            ),
+            log_bodies: false,
+        }
+    }
+
+    pub async fn send(&self, to: &str, email: Email) -> Result<(), NotifyError> {
+        let (Some(transport), Some(from)) = (&self.transport, &self.from) else {
+            if self.log_bodies {
+                eprintln!("Email to {}: {}\n{}", to, email.subject, email.text);
+            } else {
+                eprintln!(
+                    "Email to {}: {} (SMTP is not configured)",
+                    to, email.subject
+                );
+            }
+            return Ok(());
+        };
+
+        let to: Mailbox = to
+            .parse()
+            .map_err(|e| NotifyError(format!("Invalid recipient {}: {}", to, e)))?;
+        let message = Message::builder()
+            .from(from.clone())
+            .to(to)
+            .subject(email.subject)
+            .multipart(MultiPart::alternative_plain_html(email.text, email.html))
+            .map_err(|e| NotifyError(format!("Failed to build email: {}", e)))?;
+
+        transport
+            .send(message)
+            .await
+            .map(|_| ())
+            .map_err(|e| NotifyError(format!("Failed to send email: {}", e)))
+    }
+
+    /// For request handlers, which shouldn't wait on the SMTP server.
+    pub fn send_in_background(&self, to: String, email: Email) {
+        let mailer = self.clone();
+        tokio::spawn(async move {
+            if let Err(e) = mailer.send(&to, email).await {
+                eprintln!("{}", e);
+            }
+        });
+    }
+}
+
+pub fn is_valid_address(address: &str) -> bool {
+    address.parse::<lettre::Address>().is_ok()
+}
+
+// --- Reminder Emails ---
+
+/// Emails due reminders, new assignments and mentions to users with a
+/// verified address who haven't turned reminder emails off.
+pub struct EmailNotifier {
+    pool: DbPool,
+    mailer: Mailer,
+}
+
+impl EmailNotifier {
+    pub fn new(pool: DbPool, mailer: Mailer) -> Self {
+        EmailNotifier { pool, mailer }
+    }
+
+    async fn load_user(&self, user_id: i32) -> Result<DbUser, NotifyError> {
+        with_conn(&self.pool, move |conn| {
+            schema::users::table.find(user_id).first::<DbUser>(conn)
+        })
+        .await
+        .ok_or_else(|| NotifyError(format!("Could not load user {}", user_id)))
+    }
+}
+
+#[rocket::async_trait]
+impl Notifier for EmailNotifier {
+    async fn reminder_due(&self, reminder: &DueReminder) -> Result<(), NotifyError> {
+        let user = self.load_user(reminder.user_id).await?;
+
+        match user.email {
+            Some(email) if user.email_verified && user.email_reminders => {
+                self.mailer
+                    .send(&email, templates::reminder(&user.username, reminder))
+                    .await
+            }
+            _ => Ok(()),
+        }
+    }
+
+    async fn todo_event(&self, event: &TodoEvent) -> Result<(), NotifyError> {
+        let template = match event.kind {
+            EventKind::TodoAssigned => templates::assignment,
+            EventKind::TodoMentioned => templates::mention,
+            _ => return Ok(()),
+        };
+        let user = self.load_user(event.user_id).await?;
+
+        match user.email {
+            Some(email) if user.email_verified && user.email_reminders => {
+                self.mailer
+                    .send(&email, template(&user.username, &event.todo))
+                    .await
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+// --- Daily Digest ---
+
+/// Fairing that emails each opted-in user a daily list of what is due today
+/// and what is overdue, once `digest_hour` (UTC) has passed. `last_digest_on`
+/// claims the day before the mail goes out, so restarts never send a second
+/// one; a digest that fails to send gives the claim back and is retried on
+/// the next check.
+pub struct DailyDigest {
+    mailer: Mailer,
+    digest_hour: u32,
+}
+
+impl DailyDigest {
+    pub fn new(mailer: Mailer, digest_hour: u32) -> Self {
+        DailyDigest {
+            mailer,
+            digest_hour,
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for DailyDigest {
+    fn info(&self) -> Info {
+        Info {
+            name: "Daily Digest",
+            kind: Kind::Liftoff,
+        }
+    }
+
+    async fn on_liftoff(&self, rocket: &Rocket<Orbit>) {
+        let Some(pool) = rocket.state::<DbPool>().cloned() else {
+            eprintln!("Missing database pool in Rocket state, digests will not be sent!");
+            return;
+        };
+
+        tokio::spawn(run_digests(
+            pool,
+            self.mailer.clone(),
+            self.digest_hour,
+            rocket.shutdown(),
+        ));
+    }
+}
+
+async fn run_digests(pool: DbPool, mailer: Mailer, digest_hour: u32, shutdown: Shutdown) {
+    loop {
+        let now = Utc::now();
+        if now.hour() >= digest_hour {
+            send_digests(&pool, &mailer, now.date_naive()).await;
+        }
+
+        tokio::select! {
+            _ = shutdown.clone() => break,
+            _ = tokio::time::sleep(DIGEST_CHECK_INTERVAL) => {}
+        }
+    }
+}
+
+async fn send_digests(pool: &DbPool, mailer: &Mailer, today: NaiveDate) {
+    let Some(digests) = with_conn(pool, move |conn| claim_digests(conn, today)).await else {
+        return;
+    };
+
+    for (user, due_today, overdue) in digests {
+        let Some(email) = user.email else {
+            continue;
+        };
+        if due_today.is_empty() && overdue.is_empty() {
+            continue;
+        }
+        if let Err(e) = mailer
+            .send(
+                &email,
+                templates::digest(&user.username, &due_today, &overdue),
+            )
+            .await
+        {
+            eprintln!("Failed to send digest to user {}: {}", user.id, e);
+            let (user_id, previous) = (user.id, user.last_digest_on);
+            with_conn(pool, move |conn| {
+                release_digest(conn, user_id, previous, today)
+            })
+            .await;
+        }
+    }
+}
+
+type Digest = (DbUser, Vec<Todo>, Vec<Todo>);
+
+/// Marks today's digest as sent for every user who still needs one and
+/// returns their open todos that are due today and overdue.
+fn claim_digests(conn: &mut SqliteConnection, today: NaiveDate) -> QueryResult<Vec<Digest>> {
+    conn.transaction(|conn| {
+        let users = schema::users::table
+            .filter(schema::users::daily_digest.eq(true))
+            .filter(schema::users::email_verified.eq(true))
+            .filter(schema::users::email.is_not_null())
+            .filter(
+                schema::users::last_digest_on
+                    .is_null()
+                    .or(schema::users::last_digest_on.lt(today)),
+            )
+            .load::<DbUser>(conn)?;
+
+        let start_of_day = today.and_time(Default::default());
+        let end_of_day = start_of_day + chrono::Duration::days(1);
+        let mut digests = Vec::new();
+
+        for user in users {
+            diesel::update(schema::users::table.find(user.id))
+                .set(schema::users::last_digest_on.eq(today))
+                .execute(conn)?;
+
+            let todos = schema::todos::table
+                .filter(schema::todos::user_id.eq(user.id))
+                .filter(schema::todos::completed.eq(false))
+                .filter(schema::todos::deleted_at.is_null())
+                .filter(schema::todos::due.lt(end_of_day))
+                .order(schema::todos::due.asc())
+                .load::<DbTodo>(conn)?;
+            let reminders = DbReminder::belonging_to(&todos)
+                .load::<DbReminder>(conn)?
+                .grouped_by(&todos);
+            let tags = tags::names_for(conn, &todos)?;
+            let assignees = assignees::ids_for(conn, &todos)?;
+
+            let (due_today, overdue): (Vec<Todo>, Vec<Todo>) = todos
+                .into_iter()
+                .zip(reminders)
+                .zip(tags)
+                .zip(assignees)
+                .map(|(((todo, reminders), tags), assignees)| {
+                    to_api_todo(todo, reminders, tags, assignees)
+                })
+                .partition(|todo| todo.due.is_some_and(|due| due.naive_utc() >= start_of_day));
+            digests.push((user, due_today, overdue));
+        }
+        Ok(digests)
+    })
+}
+
+/// Gives a claimed digest day back after the mail couldn't be sent, unless
+/// the user has been claimed again in the meantime.
+fn release_digest(
+    conn: &mut SqliteConnection,
+    user_id: i32,
+    previous: Option<NaiveDate>,
+    today: NaiveDate,
+) -> QueryResult<usize> {
+    diesel::update(
+        schema::users::table
+            .find(user_id)
+            .filter(schema::users::last_digest_on.eq(today)),
+    )
+    .set(schema::users::last_digest_on.eq(previous))
+    .execute(conn)
+}
+
+// --- Templates ---
+
+pub mod templates {
+    use super::*;
+
+    pub fn verification(username: &str, link: &str) -> Email {
+        Email {
+            subject: String::from("Confirm your TooDoo email address"),
+            text: format!(
+                "Hi {},\n\nplease confirm this email address for your TooDoo account by opening:\n\n{}\n\nThe link is valid for 24 hours. If you didn't ask for this, you can ignore this email.\n",
+                username, link
+            ),
+            html: layout(&format!(
+                "<p>Hi {},</p><p>please confirm this email address for your TooDoo account:</p><p><a href=\"{}\">Confirm email address</a></p><p>The link is valid for 24 hours. If you didn't ask for this, you can ignore this email.</p>",
+                escape(username),
+                escape(link)
+            )),
+        }
+    }
+
+    pub fn password_reset(username: &str, link: &str) -> Email {
+        Email {
+            subject: String::from("Reset your TooDoo password"),
+            text: format!(
+                "Hi {},\n\nsomeone asked to reset the password of your TooDoo account. To choose a new one, open:\n\n{}\n\nThe link is valid for 1 hour and can be used once. If you didn't ask for this, you can ignore this email; your password stays the same.\n",
+                username, link
+            ),
+            html: layout(&format!(
+                "<p>Hi {},</p><p>someone asked to reset the password of your TooDoo account.</p><p><a href=\"{}\">Choose a new password</a></p><p>The link is valid for 1 hour and can be used once. If you didn't ask for this, you can ignore this email; your password stays the same.</p>",
+                escape(username),
+                escape(link)
+            )),
+        }
+    }
+
+    pub fn reminder(username: &str, reminder: &DueReminder) -> Email {
+        let todo = &reminder.todo;
+        let due = todo
+            .due
+            .map(|due| format!("Due: {}\n", format_time(due)))
+            .unwrap_or_default();
+        Email {
+            subject: format!("Reminder: {}", todo.title),
+            text: format!(
+                "Hi {},\n\nthis is your reminder for \"{}\".\n{}\n{}\n",
+                username, todo.title, due, todo.description
+            ),
+            html: layout(&format!(
+                "<p>Hi {},</p><p>this is your reminder for <strong>{}</strong>.</p>{}<p>{}</p>",
+                escape(username),
+                escape(&todo.title),
+                todo.due
+                    .map(|due| format!("<p>Due: {}</p>", format_time(due)))
+                    .unwrap_or_default(),
+                escape(&todo.description)
+            )),
+        }
+    }
+
+    pub fn assignment(username: &str, todo: &Todo) -> Email {
+        let due = todo
+            .due
+            .map(|due| format!("Due: {}\n", format_time(due)))
+            .unwrap_or_default();
+        Email {
+            subject: format!("Assigned to you: {}", todo.title),
+            text: format!(
+                "Hi {},\n\nyou've been assigned to \"{}\".\n{}\n{}\n",
+                username, todo.title, due, todo.description
+            ),
+            html: layout(&format!(
+                "<p>Hi {},</p><p>you've been assigned to <strong>{}</strong>.</p>{}<p>{}</p>",
+                escape(username),
+                escape(&todo.title),
+                todo.due
+                    .map(|due| format!("<p>Due: {}</p>", format_time(due)))
+                    .unwrap_or_default(),
+                escape(&todo.description)
+            )),
+        }
+    }
+
+    pub fn mention(username: &str, todo: &Todo) -> Email {
+        Email {
+            subject: format!("You were mentioned on: {}", todo.title),
+            text: format!(
+                "Hi {},\n\nsomeone mentioned you in a comment on \"{}\".\n",
+                username, todo.title
+            ),
+            html: layout(&format!(
+                "<p>Hi {},</p><p>someone mentioned you in a comment on <strong>{}</strong>.</p>",
+                escape(username),
+                escape(&todo.title)
+            )),
+        }
+    }
+
+    pub fn digest(username: &str, due_today: &[Todo], overdue: &[Todo]) -> Email {
+        let mut text = format!(
+            "Hi {},\n\nhere is your TooDoo summary for today.\n",
+            username
+        );
+        let mut html = format!(
+            "<p>Hi {},</p><p>here is your TooDoo summary for today.</p>",
+            escape(username)
+        );
+        for (heading, todos) in [("Due today", due_today), ("Overdue", overdue)] {
+            if todos.is_empty() {
+                continue;
+            }
+            text.push_str(&format!("\n{}:\n", heading));
+            html.push_str(&format!("<h3>{}</h3><ul>", heading));
+            for todo in todos {
+                let due = todo.due.map(format_time).unwrap_or_default();
+                text.push_str(&format!("  - {} ({})\n", todo.title, due));
+                html.push_str(&format!(
+                    "<li>{} <small>({})</small></li>",
+                    escape(&todo.title),
+                    due
+                ));
+            }
+            html.push_str("</ul>");
+        }
+
+        Email {
+            subject: format!(
+                "TooDoo: {} due today, {} overdue",
+                due_today.len(),
+                overdue.len()
+            ),
+            text,
+            html: layout(&html),
+        }
+    }
+
+    fn layout(body: &str) -> String {
+        format!(
+            "<!DOCTYPE html><html><body style=\"font-family: sans-serif; line-height: 1.5;\">{}<p style=\"color: #888;\">TooDoo</p></body></html>",
+            body
+        )
+    }
+
+    fn format_time(time: DateTime<Utc>) -> String {
+        time.format("%Y-%m-%d %H:%M UTC").to_string()
+    }
+
+    fn escape(value: &str) -> String {
+        value
+            .replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+            .replace('"', "&quot;")
+            .replace('\'', "&#39;")
+    }
+}
@@ -1,30 +1,57 @@
 #[macro_use]
 extern crate rocket;
 
-use chrono::{DateTime, Utc};
-use jsonwebtoken::{DecodingKey, Header, Validation, decode};
+use chrono::{DateTime, NaiveDateTime, Utc};
 use rocket::{
     Request, State,
     http::{ContentType, Status},
-    request::{FromRequest, Outcome},
     response::{self, Responder, Response},
     serde::json::{Json, json},
 };
 use rocket_cors::CorsOptions;
 use std::env;
 use std::io::Cursor;
+use std::sync::Arc;
+use std::time::Duration;
 
 use diesel::BelongingToDsl;
 use diesel::r2d2::{self, ConnectionManager, Pool};
-use diesel::{prelude::*, result::Error as DieselError};
+use diesel::sql_types::Text;
+use diesel::{prelude::*, result::DatabaseErrorKind, result::Error as DieselError};
 use dotenvy::dotenv;
 
+mod access;
+mod account;
+mod admin;
 mod api;
+mod assignees;
+mod auth;
+mod comments;
+mod history;
+mod keys;
+mod mailer;
+mod members;
+mod mfa;
 mod models;
+mod notifier;
+mod oidc;
+mod projects;
+mod ratelimit;
+mod recurrence;
+mod registration;
+mod scheduler;
 mod schema;
 mod security;
+mod subtasks;
+mod tags;
+mod tokens;
+mod totp;
+mod trash;
+mod webhooks;
+mod workflow;
 
 // --- Model and Type Definitions ---
+type DbInsertableCompletion = models::InsertableCompletion;
 type DbInsertableReminder = models::InsertableReminder;
 type DbReminder = models::Reminder;
 type DbTodo = models::Todo;
@@ -32,14 +59,32 @@ type DbInsertableTodo = models::InsertableTodo;
 type DbInsertableUser = models::InsertableUser;
 type DbUser = models::User;
 type DbPool = Pool<ConnectionManager<SqliteConnection>>;
+type ReminderWakeup = scheduler::ReminderWakeup;
+type Events = notifier::Events;
+type TodoEvent = notifier::TodoEvent;
+type EventKind = api::EventKind;
 type ApiUser = api::User;
+type ApiProfile = api::Profile;
+type EmailUpdate = api::EmailUpdate;
+type NotificationSettings = api::NotificationSettings;
 type NewUser = api::NewUser;
 type ApiTodo = api::Todo;
 type NewTodo = api::NewTodo;
 type ApiSessionToken = api::SessionToken;
+type ApiLoginResponse = api::LoginResponse;
 type ApiClaims = api::Claims;
 type AuthenticatedUser = api::AuthenticatedUser;
+type RepeatFrom = api::RepeatFrom;
 type RepeatRule = api::RepeatRule;
+type Priority = api::Priority;
+type TodoStatus = api::TodoStatus;
+type RateLimiter = ratelimit::RateLimiter;
+type Action = access::Action;
+type PasswordConfig = security::PasswordConfig;
+type RegistrationConfig = registration::RegistrationConfig;
+type Account<'a> = ratelimit::Account<'a>;
+
+define_sql_function!(fn lower(x: Text) -> Text);
 
 // --- Custom Error Handling ---
 
@@ -53,60 +98,40 @@ pub enum CustomError {
     JwtError(jsonwebtoken::errors::Error),
     MissingConfig,
     MissingAuthToken,
+    SessionRevoked,
+    InvalidRefreshToken,
+    InvalidMfaCode,
+    InvalidApiToken,
+    InsufficientScope(String),
+    /// Carries the number of seconds until the client may try again.
+    TooManyRequests(u64),
+    InvalidInput(String),
+    Forbidden,
+    AccountDisabled,
+    RegistrationClosed,
+    InvalidInvite,
 }
 
 pub struct AppConfig {
-    pub jwt_secret: String,
-}
-
-#[rocket::async_trait]
-impl<'r> FromRequest<'r> for AuthenticatedUser {
-    type Error = CustomError;
-
-    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
-        // Step 1: Get the AppConfig state for the secret key.
-        // If it's not present, it's a server configuration error.
-        let app_config = match req.rocket().state::<AppConfig>() {
-            Some(config) => config,
-            None => {
-                // Log the server error for debugging
-                eprintln!("Missing AppConfig in Rocket state!");
-                return Outcome::Error((Status::InternalServerError, CustomError::MissingConfig));
-            }
-        };
-
-        // Step 2: Extract and validate the token from the header.
-        // We use `if let` to handle multiple failure points concisely.
-        if let Some(auth_header) = req.headers().get_one("Authorization") {
-            if let Some(token_str) = auth_header.strip_prefix("Bearer ") {
-                // Step 3: Decode the token.
-                let key = DecodingKey::from_secret(app_config.jwt_secret.as_ref());
-                let validation = Validation::default();
-
-                match decode::<ApiClaims>(token_str, &key, &validation) {
-                    Ok(token_data) => {
-                        // Success! Provide the AuthenticatedUser.
-                        return Outcome::Success(AuthenticatedUser {
-                            user_id: token_data.claims.sub,
-                        });
-                    }
-                    Err(e) => {
-                        // Token is present but invalid (expired, bad signature, etc.)
-                        return Outcome::Error((Status::Unauthorized, CustomError::from(e)));
-                    }
-                }
-            }
-        }
-
-        // If we reach here, it means the header was missing or not "Bearer".
-        Outcome::Error((Status::Unauthorized, CustomError::MissingAuthToken))
-    }
+    pub keys: keys::Keyring,
+    pub access_token_ttl: chrono::Duration,
+    pub refresh_token_ttl: chrono::Duration,
+    /// Where this server is reachable from users' browsers, for links in emails.
+    pub public_url: String,
+    /// Whether requests come through a reverse proxy that sets the client's
+    /// address in Rocket's `ip_header`. Without one the header is the
+    /// client's own to forge, so only the connection's address is used.
+    pub trust_proxy: bool,
 }
 
 // Implement the Responder trait for our custom error
 #[rocket::async_trait]
 impl<'r> Responder<'r, 'static> for CustomError {
     fn respond_to(self, _: &'r Request<'_>) -> response::Result<'static> {
+        let retry_after = match self {
+            CustomError::TooManyRequests(seconds) => Some(seconds),
+            _ => None,
+        };
         let (status, error_json) = match self {
             // Specifically handle the case where an item is not found in the DB
             CustomError::DatabaseError(DieselError::NotFound) | CustomError::NotFound => (
@@ -148,17 +173,61 @@ impl<'r> Responder<'r, 'static> for CustomError {
                 Status::Unauthorized,
                 json!({"error": "Missing or invalid authorization token."}),
             ),
+            CustomError::SessionRevoked => (
+                Status::Unauthorized,
+                json!({"error": "This session has been logged out."}),
+            ),
+            CustomError::InvalidRefreshToken => (
+                Status::Unauthorized,
+                json!({"error": "The refresh token is invalid or has expired."}),
+            ),
+            CustomError::InvalidMfaCode => (
+                Status::Unauthorized,
+                json!({"error": "The two-factor code is invalid or the login attempt has expired."}),
+            ),
+            CustomError::InvalidApiToken => (
+                Status::Unauthorized,
+                json!({"error": "The access token is invalid or has expired."}),
+            ),
+            CustomError::InsufficientScope(message) => {
+                (Status::Forbidden, json!({"error": message}))
+            }
+            CustomError::TooManyRequests(seconds) => (
+                Status::TooManyRequests,
+                json!({"error": format!("Too many attempts. Please try again in {} seconds.", seconds)}),
+            ),
+            CustomError::InvalidInput(message) => (Status::BadRequest, json!({"error": message})),
+            CustomError::Forbidden => (
+                Status::Forbidden,
+                json!({"error": "You don't have permission to do that."}),
+            ),
+            CustomError::AccountDisabled => (
+                Status::Forbidden,
+                json!({"error": "This account has been disabled."}),
+            ),
+            CustomError::RegistrationClosed => (
+                Status::Forbidden,
+                json!({"error": "Registration is closed on this server."}),
+            ),
+            CustomError::InvalidInvite => (
+                Status::Forbidden,
+                json!({"error": "A valid invite code is required to register."}),
+            ),
         };
 
         // Build the response
-        Response::build()
+        let mut response = Response::build();
+        response
             .status(status)
             .header(ContentType::JSON)
             .sized_body(
                 error_json.to_string().len(),
                 Cursor::new(error_json.to_string()),
-            )
-            .ok()
+            );
+        if let Some(seconds) = retry_after {
+            response.raw_header("Retry-After", seconds.to_string());
+        }
+        response.ok()
     }
 }
 
@@ -189,18 +258,74 @@ fn rocket() -> _ {
         .build(manager)
         .expect("Failed to create database pool.");
 
+    // `TooDoo <command> ...` runs a maintenance command instead of the server.
+    let args: Vec<String> = env::args().skip(1).collect();
+    if !args.is_empty() {
+        std::process::exit(admin::run_command(&pool, &args));
+    }
+
     let cors = CorsOptions::default()
         .to_cors()
         .expect("error creating CORS fairing");
 
     let figment = rocket::Config::figment().merge(("address", "0.0.0.0"));
 
-    let jwt_secret = env::var("JWT_SECRET").expect("JWT_SECRET must be set");
-    let app_config = AppConfig { jwt_secret };
+    let public_url =
+        env::var("PUBLIC_URL").unwrap_or_else(|_| String::from("http://localhost:8000"));
+    let access_token_minutes = env::var("ACCESS_TOKEN_MINUTES")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(15);
+    let refresh_token_days = env::var("REFRESH_TOKEN_DAYS")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(30);
+    let app_config = AppConfig {
+        keys: keys::Keyring::from_env(),
+        access_token_ttl: chrono::Duration::minutes(access_token_minutes),
+        refresh_token_ttl: chrono::Duration::days(refresh_token_days),
+        public_url: public_url.trim_end_matches('/').to_string(),
+        trust_proxy: env::var("TRUST_PROXY").is_ok_and(|v| v == "1" || v == "true"),
+    };
+    let mailer = mailer::Mailer::new(mailer::SmtpConfig::from_env());
+    let password_config = security::PasswordConfig::from_env();
+    let registration_config = registration::RegistrationConfig::from_env();
+    let oidc = oidc::Oidc::new(oidc::OidcConfig::from_env(&app_config.public_url));
+    let rate_limiter =
+        ratelimit::RateLimiter::new(ratelimit::RateLimitConfig::from_env(), pool.clone());
+    let digest_hour = env::var("DIGEST_HOUR_UTC")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(7);
+
+    let trash_retention_days = env::var("TRASH_RETENTION_DAYS")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(30);
+
+    let reminder_poll_seconds = env::var("REMINDER_POLL_SECONDS")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(60);
+    let notifier: Arc<dyn notifier::Notifier> = Arc::new(notifier::Fanout(vec![
+        Arc::new(notifier::LogNotifier),
+        Arc::new(webhooks::WebhookNotifier::new(pool.clone())),
+        Arc::new(mailer::EmailNotifier::new(pool.clone(), mailer.clone())),
+    ]));
+    let reminder_dispatcher = scheduler::ReminderDispatcher::new(
+        notifier.clone(),
+        Duration::from_secs(reminder_poll_seconds),
+    );
 
     rocket::custom(figment)
         .manage(pool)
         .manage(app_config)
+        .manage(Events::new(notifier))
+        .manage(mailer.clone())
+        .manage(rate_limiter)
+        .manage(password_config)
+        .manage(registration_config)
+        .manage(oidc)
         .mount(
             "/api",
             routes![
@@ -210,63 +335,240 @@ fn rocket() -> _ {
                 delete_todo,
                 register_user,
                 login,
+                mfa::login_mfa,
+                oidc::oidc_status,
+                oidc::oidc_login,
+                oidc::oidc_callback,
+                auth::refresh,
+                auth::logout,
+                auth::list_sessions,
+                auth::delete_session,
+                get_profile,
+                account::change_password,
+                account::forgot_password,
+                account::reset_password,
+                account::delete_account,
+                mfa::enroll_totp,
+                mfa::confirm_totp,
+                mfa::disable_totp,
+                mfa::regenerate_recovery_codes,
+                update_email,
+                verify_email,
+                update_notification_settings,
+                webhooks::list_webhooks,
+                webhooks::create_webhook,
+                webhooks::update_webhook,
+                webhooks::delete_webhook,
+                webhooks::list_deliveries,
+                tokens::list_tokens,
+                tokens::create_token,
+                tokens::delete_token,
+                projects::list_projects,
+                projects::create_project,
+                projects::update_project,
+                projects::delete_project,
+                members::list_members,
+                members::invite_member,
+                members::update_member,
+                members::remove_member,
+                members::list_invitations,
+                members::accept_invitation,
+                members::decline_invitation,
+                workflow::reorder_todos,
+                comments::list_comments,
+                comments::add_comment,
+                comments::update_comment,
+                comments::delete_comment,
+                history::todo_history,
+                history::list_activity,
+                trash::list_trash,
+                trash::restore_todo,
+                trash::purge_todo,
+                trash::empty_trash,
+                tags::list_tags,
+                tags::rename_tag,
+                tags::merge_tag,
+                tags::delete_tag,
+                admin::list_users,
+                admin::get_user,
+                admin::update_user,
+                admin::delete_user,
+                admin::force_password_reset,
+                admin::revoke_user_sessions,
+                admin::get_registration,
+                admin::update_registration,
+                admin::list_invites,
+                registration::get_registration_mode,
+                registration::list_invites,
+                registration::create_invite,
+                registration::delete_invite,
             ],
         )
+        .mount("/", routes![keys::jwks])
         .attach(cors)
+        .attach(reminder_dispatcher)
+        .attach(mailer::DailyDigest::new(mailer, digest_hour))
+        .attach(trash::TrashPurger::new(chrono::Duration::days(
+            trash_retention_days,
+        )))
 }
 
-#[get("/todos")]
+/// Lists the user's todos, optionally only those of one project or, with
+/// `?project=inbox`, only those without one. `?assigned_to=me` narrows it to
+/// the todos assigned to the user.
+#[get("/todos?<project>&<tag>&<tag_match>&<assigned_to>&<nested>")]
 fn list_all_todos(
     pool: &State<DbPool>,
     auth_user: AuthenticatedUser,
+    project: Option<&str>,
+    tag: Vec<&str>,
+    tag_match: Option<&str>,
+    assigned_to: Option<&str>,
+    nested: Option<bool>,
 ) -> Result<Json<Vec<ApiTodo>>, CustomError> {
+    let project = project
+        .map(str::parse::<projects::ProjectFilter>)
+        .transpose()
+        .map_err(CustomError::InvalidInput)?;
+    let tag_match = tag_match
+        .map(str::parse::<tags::TagMatch>)
+        .transpose()
+        .map_err(CustomError::InvalidInput)?
+        .unwrap_or_default();
+    let assigned_to = assigned_to
+        .map(str::parse::<assignees::AssigneeFilter>)
+        .transpose()
+        .map_err(CustomError::InvalidInput)?;
     let mut conn = pool.get().expect("Failed to get DB connection from pool");
 
-    let db_todos = schema::todos::table
-        .filter(schema::todos::user_id.eq(auth_user.user_id))
+    let mut query = access::visible_todos(&mut conn, auth_user.user_id)?;
+    match project {
+        Some(projects::ProjectFilter::Inbox) => {
+            query = query.filter(schema::todos::project_id.is_null());
+        }
+        Some(projects::ProjectFilter::Project(project_id)) => {
+            query = query.filter(schema::todos::project_id.eq(project_id));
+        }
+        None => {}
+    }
+    if !tag.is_empty() {
+        query = tags::filter(query, &tag, tag_match);
+    }
+    if let Some(assigned_to) = assigned_to {
+        query = assignees::filter(query, assigned_to, auth_user.user_id);
+    }
+    let db_todos = query
+        .order((schema::todos::rank.asc(), schema::todos::id.asc()))
         .load::<DbTodo>(&mut conn)?;
 
-    let db_reminders = DbReminder::belonging_to(&db_todos)
-        .load::<DbReminder>(&mut conn)?
-        .grouped_by(&db_todos);
-
-    let combined_data: Vec<(DbTodo, Vec<DbReminder>)> =
-        db_todos.into_iter().zip(db_reminders).collect();
-
-    let api_todos: Vec<ApiTodo> = combined_data
+    let progress = subtasks::progress(&mut conn, auth_user.user_id)?;
+    let api_todos: Vec<ApiTodo> = load_api_todos(&mut conn, db_todos)?
         .into_iter()
-        .map(|(todo, reminders)| {
-            let reminder_dates = reminders
-                .into_iter()
-                .map(|r| DateTime::<Utc>::from_naive_utc_and_offset(r.reminder, Utc))
-                .collect();
-
-            ApiTodo {
-                id: todo.id,
-                title: todo.title,
-                description: todo.description,
-                completed: todo.completed,
-                due: todo
-                    .due
-                    .map(|d| DateTime::<Utc>::from_naive_utc_and_offset(d, Utc)),
-                repeat: RepeatRule::from(todo.repeat),
-                reminder: reminder_dates,
-            }
+        .map(|mut todo| {
+            todo.progress = progress.get(&todo.id).copied();
+            todo
         })
         .collect();
+    if nested.unwrap_or(false) {
+        return Ok(Json(subtasks::nest(api_todos)));
+    }
     Ok(Json(api_todos))
 }
 
+fn to_api_todo(
+    todo: DbTodo,
+    reminders: Vec<DbReminder>,
+    tags: Vec<String>,
+    assignees: Vec<i32>,
+) -> ApiTodo {
+    let reminder_dates = reminders
+        .into_iter()
+        .map(|r| DateTime::<Utc>::from_naive_utc_and_offset(r.reminder, Utc))
+        .collect();
+
+    ApiTodo {
+        id: todo.id,
+        title: todo.title,
+        description: todo.description,
+        completed: todo.completed,
+        due: todo
+            .due
+            .map(|d| DateTime::<Utc>::from_naive_utc_and_offset(d, Utc)),
+        // Stored rules were validated on write, so this only guards against hand-edited rows.
+        repeat: todo.repeat.parse().unwrap_or_default(),
+        repeat_from: RepeatFrom::from(todo.repeat_from),
+        reminder: reminder_dates,
+        project_id: todo.project_id,
+        tags,
+        assignees,
+        parent_id: todo.parent_id,
+        auto_complete: todo.auto_complete,
+        priority: Priority::parse(&todo.priority).unwrap_or_default(),
+        status: TodoStatus::parse(&todo.status).unwrap_or_default(),
+        rank: todo.rank,
+        deleted_at: todo
+            .deleted_at
+            .map(|d| DateTime::<Utc>::from_naive_utc_and_offset(d, Utc)),
+        progress: None,
+        subtasks: Vec::new(),
+    }
+}
+
+/// Like `load_api_todo` for many todos at once, leaving out progress.
+fn load_api_todos(conn: &mut SqliteConnection, todos: Vec<DbTodo>) -> QueryResult<Vec<ApiTodo>> {
+    let reminders = DbReminder::belonging_to(&todos)
+        .load::<DbReminder>(conn)?
+        .grouped_by(&todos);
+    let tags = tags::names_for(conn, &todos)?;
+    let assignees = assignees::ids_for(conn, &todos)?;
+
+    Ok(todos
+        .into_iter()
+        .zip(reminders)
+        .zip(tags)
+        .zip(assignees)
+        .map(|(((todo, reminders), tags), assignees)| to_api_todo(todo, reminders, tags, assignees))
+        .collect())
+}
+
+/// Loads everything an API todo carries besides the row itself.
+fn load_api_todo(conn: &mut SqliteConnection, todo: DbTodo) -> QueryResult<ApiTodo> {
+    let id = todo.id;
+    let reminders = DbReminder::belonging_to(&todo).load::<DbReminder>(conn)?;
+    let tags = tags::names_of(conn, &todo)?;
+    let assignees = assignees::ids_of(conn, &todo)?;
+    let mut todo = to_api_todo(todo, reminders, tags, assignees);
+    todo.progress = subtasks::progress_of(conn, id)?;
+    Ok(todo)
+}
+
 #[post("/todos", data = "<new_todo_json>")]
 fn add_todo(
     pool: &State<DbPool>,
+    reminder_wakeup: &State<ReminderWakeup>,
+    events: &State<Events>,
     auth_user: AuthenticatedUser,
     new_todo_json: Json<NewTodo>,
 ) -> Result<Json<ApiTodo>, CustomError> {
     let new_todo = new_todo_json.into_inner();
+    if new_todo.status.is_closed() {
+        return Err(CustomError::InvalidInput(String::from(
+            "New todos can't be done or cancelled.",
+        )));
+    }
     let mut conn = pool.get().expect("Failed to get DB connection from pool");
 
-    conn.transaction(|conn| {
+    let mut assigned = Vec::new();
+
+    let result = conn.transaction(|conn| {
+        projects::check_target(conn, auth_user.user_id, new_todo.project_id)?;
+        subtasks::check_parent(
+            conn,
+            auth_user.user_id,
+            None,
+            new_todo.project_id,
+            new_todo.parent_id,
+        )?;
         let db_todo = DbInsertableTodo {
             user_id: auth_user.user_id,
             title: new_todo.title.clone(),
@@ -274,6 +576,13 @@ fn add_todo(
             due: new_todo.due.map(|dt| dt.naive_utc()),
             repeat: new_todo.repeat.to_string(),
             completed: false,
+            repeat_from: new_todo.repeat_from.to_string(),
+            project_id: new_todo.project_id,
+            parent_id: new_todo.parent_id,
+            auto_complete: new_todo.auto_complete,
+            priority: new_todo.priority.as_str().to_string(),
+            status: new_todo.status.as_str().to_string(),
+            rank: workflow::next_rank(conn, auth_user.user_id)?,
         };
 
         let inserted_todo: DbTodo = diesel::insert_into(schema::todos::table)
@@ -293,6 +602,11 @@ fn add_todo(
                 .values(&db_reminders)
                 .execute(conn)?;
         }
+        history::record_created(conn, inserted_todo.id, auth_user.user_id, &new_todo.title)?;
+        let tags = tags::set_todo_tags(conn, &inserted_todo, &new_todo.tags)?;
+        let assignees;
+        (assignees, assigned) =
+            assignees::set_assignees(conn, auth_user.user_id, &inserted_todo, &new_todo.assignees)?;
 
         Ok(Json(ApiTodo {
             id: inserted_todo.id,
@@ -301,118 +615,339 @@ fn add_todo(
             due: new_todo.due,
             reminder: new_todo.reminder,
             repeat: new_todo.repeat,
+            repeat_from: new_todo.repeat_from,
             completed: false,
+            project_id: new_todo.project_id,
+            tags,
+            assignees,
+            parent_id: new_todo.parent_id,
+            auto_complete: new_todo.auto_complete,
+            priority: new_todo.priority,
+            status: new_todo.status,
+            rank: inserted_todo.rank,
+            deleted_at: None,
+            progress: None,
+            subtasks: Vec::new(),
         }))
-    })
+    });
+    if let Ok(todo) = &result {
+        reminder_wakeup.wake();
+        events.emit(TodoEvent::new(
+            EventKind::TodoCreated,
+            auth_user.user_id,
+            todo.0.clone(),
+        ));
+        emit_assigned(events, auth_user.user_id, &assigned, &todo.0);
+    }
+    result
 }
 
 #[put("/todos/<id>", data = "<updated_todo_json>")]
 fn update_todo(
     pool: &State<DbPool>,
+    reminder_wakeup: &State<ReminderWakeup>,
+    events: &State<Events>,
     auth_user: AuthenticatedUser,
     id: i32,
     updated_todo_json: Json<ApiTodo>,
 ) -> Result<Json<ApiTodo>, CustomError> {
-    let updated_todo = updated_todo_json.into_inner();
+    let mut updated_todo = updated_todo_json.into_inner();
+    updated_todo.id = id;
     let mut conn = pool.get().expect("Failed to get DB connection from pool");
 
-    conn.transaction(|conn| {
-        let target = schema::todos::table
-            .filter(schema::todos::user_id.eq(auth_user.user_id))
-            .filter(schema::todos::id.eq(id));
-        diesel::update(target)
+    let mut completed_snapshot = None;
+    let mut auto_completed = Vec::new();
+    let mut assigned = Vec::new();
+    let mut moved = Vec::new();
+
+    let result = conn.transaction(|conn| {
+        let current = access::authorize(conn, auth_user.user_id, id, Action::Edit)?;
+        let before = load_api_todo(conn, current.clone())?;
+        let mut moved_rows = Vec::new();
+        let target = schema::todos::table.filter(schema::todos::id.eq(id));
+        let was_completed = current.completed;
+        if updated_todo.project_id != current.project_id {
+            // The Inbox a todo goes back to is its creator's.
+            if updated_todo.project_id.is_none() && current.user_id != auth_user.user_id {
+                return Err(CustomError::InvalidInput(String::from(
+                    "Only the todo's creator can move it to the Inbox.",
+                )));
+            }
+            projects::check_target(conn, auth_user.user_id, updated_todo.project_id)?;
+        }
+        if updated_todo.parent_id != current.parent_id
+            || updated_todo.project_id != current.project_id
+        {
+            subtasks::check_parent(
+                conn,
+                auth_user.user_id,
+                Some(id),
+                updated_todo.project_id,
+                updated_todo.parent_id,
+            )?;
+        }
+        if updated_todo.project_id != current.project_id {
+            moved_rows =
+                subtasks::move_subtree(conn, auth_user.user_id, &current, updated_todo.project_id)?;
+        }
+        updated_todo.status = workflow::resolve_status(
+            TodoStatus::parse(&current.status).unwrap_or_default(),
+            was_completed,
+            updated_todo.status,
+            updated_todo.completed,
+        );
+        updated_todo.completed = updated_todo.status.is_closed();
+        updated_todo.rank = current.rank;
+
+        if updated_todo.completed && !was_completed {
+            let completed_at = Utc::now();
+            // Cancelling an occurrence of a repeating todo skips it without
+            // counting as having done it.
+            if updated_todo.status == TodoStatus::Done {
+                diesel::insert_into(schema::completions::table)
+                    .values(&DbInsertableCompletion {
+                        todo_id: id,
+                        completed_at: completed_at.naive_utc(),
+                        due: updated_todo.due.map(|dt| dt.naive_utc()),
+                    })
+                    .execute(conn)?;
+                completed_snapshot = Some(updated_todo.clone());
+            }
+            roll_forward(&mut updated_todo, completed_at);
+        }
+
+        let todo = diesel::update(target)
             .set((
                 schema::todos::title.eq(&updated_todo.title),
                 schema::todos::description.eq(&updated_todo.description),
                 schema::todos::due.eq(updated_todo.due.map(|dt| dt.naive_utc())),
                 schema::todos::repeat.eq(updated_todo.repeat.to_string()),
+                schema::todos::repeat_from.eq(updated_todo.repeat_from.to_string()),
                 schema::todos::completed.eq(updated_todo.completed),
+                schema::todos::project_id.eq(updated_todo.project_id),
+                schema::todos::parent_id.eq(updated_todo.parent_id),
+                schema::todos::auto_complete.eq(updated_todo.auto_complete),
+                schema::todos::priority.eq(updated_todo.priority.as_str()),
+                schema::todos::status.eq(updated_todo.status.as_str()),
             ))
-            .execute(conn)?;
-
-        diesel::delete(schema::reminders::table.filter(schema::reminders::todo_id.eq(id)))
-            .execute(conn)?;
+            .get_result::<DbTodo>(conn)?;
 
-        let db_reminders: Vec<DbInsertableReminder> = updated_todo
+        // Only touch reminders that actually changed, so ones that already
+        // fired keep their `delivered_at` and aren't sent again.
+        let wanted: Vec<NaiveDateTime> = updated_todo
             .reminder
             .iter()
-            .map(|rem| DbInsertableReminder {
-                todo_id: id,
-                reminder: rem.naive_utc(),
-            })
+            .map(|rem| rem.naive_utc())
             .collect();
+        diesel::delete(
+            schema::reminders::table
+                .filter(schema::reminders::todo_id.eq(id))
+                .filter(schema::reminders::reminder.ne_all(&wanted)),
+        )
+        .execute(conn)?;
+
+        let existing: Vec<NaiveDateTime> = schema::reminders::table
+            .filter(schema::reminders::todo_id.eq(id))
+            .select(schema::reminders::reminder)
+            .load(conn)?;
+        let mut db_reminders: Vec<DbInsertableReminder> = Vec::new();
+        for reminder in wanted {
+            if !existing.contains(&reminder) && !db_reminders.iter().any(|r| r.reminder == reminder)
+            {
+                db_reminders.push(DbInsertableReminder {
+                    todo_id: id,
+                    reminder,
+                });
+            }
+        }
         if !db_reminders.is_empty() {
             diesel::insert_into(schema::reminders::table)
                 .values(&db_reminders)
                 .execute(conn)?;
         }
+        updated_todo.tags = tags::set_todo_tags(conn, &todo, &updated_todo.tags)?;
+        (updated_todo.assignees, assigned) =
+            assignees::set_assignees(conn, auth_user.user_id, &todo, &updated_todo.assignees)?;
+        updated_todo.progress = subtasks::progress_of(conn, id)?;
+        updated_todo.subtasks = Vec::new();
+        history::record_changes(conn, auth_user.user_id, &before, &updated_todo)?;
+        if updated_todo.completed {
+            auto_completed =
+                subtasks::complete_ancestors(conn, auth_user.user_id, updated_todo.parent_id)?;
+        }
+        moved = load_api_todos(conn, moved_rows)?;
 
         Ok(Json(updated_todo))
-    })
+    });
+    if let Ok(todo) = &result {
+        reminder_wakeup.wake();
+        if let Some(snapshot) = completed_snapshot {
+            events.emit(TodoEvent::new(
+                EventKind::TodoCompleted,
+                auth_user.user_id,
+                snapshot,
+            ));
+        }
+        events.emit(TodoEvent::new(
+            EventKind::TodoUpdated,
+            auth_user.user_id,
+            todo.0.clone(),
+        ));
+        emit_assigned(events, auth_user.user_id, &assigned, &todo.0);
+        for subtask in moved {
+            events.emit(TodoEvent::new(
+                EventKind::TodoUpdated,
+                auth_user.user_id,
+                subtask,
+            ));
+        }
+        for parent in auto_completed {
+            events.emit(TodoEvent::new(
+                EventKind::TodoCompleted,
+                auth_user.user_id,
+                parent.clone(),
+            ));
+            events.emit(TodoEvent::new(
+                EventKind::TodoUpdated,
+                auth_user.user_id,
+                parent,
+            ));
+        }
+    }
+    result
+}
+
+/// Tells the people who were just assigned to a todo, unless they assigned
+/// themselves.
+fn emit_assigned(events: &Events, user_id: i32, assigned: &[i32], todo: &ApiTodo) {
+    for &assignee in assigned.iter().filter(|&&assignee| assignee != user_id) {
+        events.emit(TodoEvent::new(
+            EventKind::TodoAssigned,
+            assignee,
+            todo.clone(),
+        ));
+    }
 }
 
+/// Moves a repeating todo that was just completed on to its next occurrence:
+/// `due` advances, every reminder shifts by the same offset and `completed` is
+/// cleared again, with the status back to todo. Todos that don't repeat, or whose series has ended, are left
+/// completed.
+fn roll_forward(todo: &mut ApiTodo, completed_at: DateTime<Utc>) {
+    let Some(rule) = todo.repeat.rrule() else {
+        return;
+    };
+
+    // Repeating from completion keeps the todo's time of day, so a 09:00 task
+    // finished in the afternoon comes back at 09:00.
+    let start = match (todo.repeat_from, todo.due) {
+        (RepeatFrom::Due, Some(due)) => due,
+        (RepeatFrom::Completion, Some(due)) => {
+            completed_at.date_naive().and_time(due.time()).and_utc()
+        }
+        (_, None) => completed_at,
+    };
+
+    let Some((next_due, rest)) = rule.advance(start) else {
+        return;
+    };
+    let offset = next_due - todo.due.unwrap_or(start);
+
+    todo.due = Some(next_due);
+    todo.reminder = todo.reminder.iter().map(|rem| *rem + offset).collect();
+    todo.repeat = RepeatRule::Rule(rest);
+    todo.completed = false;
+    todo.status = TodoStatus::Todo;
+}
+
+/// Moves a todo and its subtasks to the trash. `trash` restores them or
+/// deletes them for good.
 #[delete("/todos/<id>")]
 fn delete_todo(
     pool: &State<DbPool>,
+    events: &State<Events>,
     auth_user: AuthenticatedUser,
     id: i32,
 ) -> Result<Status, CustomError> {
     let mut conn = pool.get().expect("Failed to get DB connection from pool");
 
-    conn.transaction(|conn| {
-        let todo_owner = schema::todos::table
-            .filter(schema::todos::id.eq(id))
-            .select(schema::todos::user_id)
-            .first::<i32>(conn)
-            .optional()?;
-
-        match todo_owner {
-            Some(owner_id) if owner_id == auth_user.user_id => {
-                diesel::delete(
-                    schema::reminders::table
-                        .filter(schema::reminders::todo_id.eq(id))
-                )
-                .execute(conn)?;
+    let deleted = conn.transaction(|conn| {
+        access::authorize(conn, auth_user.user_id, id, Action::Edit)?;
 
-                diesel::delete(
-                    schema::todos::table
-                        .filter(schema::todos::id.eq(id))
-                )
-                .execute(conn)?;
-                
-                Ok(Status::NoContent)
-            }
-            _ => Err(CustomError::NotFound),
+        // Subtasks, at every depth, go with their parent, as far as the user
+        // may edit them. Ones already in the trash keep the time they were
+        // put there.
+        let mut ids = subtasks::editable_descendants(conn, auth_user.user_id, id)?;
+        ids.push(id);
+        let deleted_at = Utc::now();
+        let todos = diesel::update(
+            schema::todos::table
+                .filter(schema::todos::id.eq_any(&ids))
+                .filter(schema::todos::deleted_at.is_null()),
+        )
+        .set(schema::todos::deleted_at.eq(deleted_at.naive_utc()))
+        .get_results::<DbTodo>(conn)?;
+        for todo in &todos {
+            history::record(
+                conn,
+                todo.id,
+                auth_user.user_id,
+                "deleted_at",
+                &None,
+                &Some(deleted_at),
+            )?;
         }
-    })
+
+        Ok::<_, CustomError>(load_api_todos(conn, todos)?)
+    })?;
+
+    for todo in deleted {
+        events.emit(TodoEvent::new(
+            EventKind::TodoDeleted,
+            auth_user.user_id,
+            todo,
+        ));
+    }
+    Ok(Status::NoContent)
 }
 
 #[post("/users/register", data = "<user_json>")]
 fn register_user(
     pool: &State<DbPool>,
+    rate_limiter: &State<RateLimiter>,
+    password_config: &State<PasswordConfig>,
+    registration_config: &State<RegistrationConfig>,
+    client: auth::ClientInfo,
     user_json: Json<NewUser>,
 ) -> Result<Json<ApiUser>, CustomError> {
+    rate_limiter.check(client.ip.as_deref(), None)?;
     let user = user_json.into_inner();
+    registration_config
+        .check_username(&user.username)
+        .map_err(CustomError::InvalidInput)?;
+    password_config
+        .check(&user.password, &user.username)
+        .map_err(CustomError::InvalidInput)?;
     let mut conn = pool.get().expect("Failed to get DB connection from pool");
 
     conn.transaction(|conn| {
-        let username_taken = schema::users::table
-            .filter(schema::users::username.eq(&user.username))
-            .select(schema::users::id)
-            .first::<i32>(conn)
-            .optional()?
-            .is_some();
-
-        if username_taken {
-            return Err(CustomError::UsernameTaken);
-        }
+        let role = registration::admit(conn, registration_config, user.invite_code.as_deref())?;
         let db_user = DbInsertableUser {
             username: user.username,
-            password: security::hash_password(&user.password)?,
+            password: Some(security::hash_password(&user.password, password_config)?),
+            role: role.as_str().to_string(),
         };
+        // The unique index ignores case, so "Alice" and "alice" can't both
+        // register even when they race each other.
         let new_db_user = diesel::insert_into(schema::users::table)
             .values(&db_user)
-            .get_result::<DbUser>(conn)?;
+            .get_result::<DbUser>(conn)
+            .map_err(|e| match e {
+                DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => {
+                    CustomError::UsernameTaken
+                }
+                e => CustomError::from(e),
+            })?;
 
         Ok(Json(ApiUser {
             id: new_db_user.id,
@@ -425,29 +960,178 @@ fn register_user(
 fn login(
     pool: &State<DbPool>,
     app_config: &State<AppConfig>,
+    rate_limiter: &State<RateLimiter>,
+    password_config: &State<PasswordConfig>,
+    client: auth::ClientInfo,
     user_json: Json<NewUser>,
-) -> Result<Json<ApiSessionToken>, CustomError> {
+) -> Result<Json<ApiLoginResponse>, CustomError> {
     let user = user_json.into_inner();
+    rate_limiter.check(
+        client.ip.as_deref(),
+        Some(Account::Username(&user.username)),
+    )?;
     let mut conn = pool.get().expect("Failed to get DB connection from pool");
 
-    conn.transaction(|conn| {
+    let result = conn.transaction(|conn| {
         let db_user = schema::users::table
-            .filter(schema::users::username.eq(&user.username))
+            .filter(lower(schema::users::username).eq(user.username.to_ascii_lowercase()))
             .first::<DbUser>(conn)?;
 
-        if security::verify_password(&user.password, &db_user.password)? {
-            Ok(Json(ApiSessionToken {
-                token: jsonwebtoken::encode(
-                    &Header::default(),
-                    &ApiClaims {
-                        sub: db_user.id,
-                        exp: (Utc::now() + chrono::Duration::hours(24)).timestamp() as usize,
-                    },
-                    &jsonwebtoken::EncodingKey::from_secret(app_config.jwt_secret.as_ref()),
-                )?,
-            }))
+        // Accounts that only use single sign-on can't log in with a password.
+        let Some(db_password) = &db_user.password else {
+            return Err(CustomError::NotFound);
+        };
+        if !security::verify_password(&user.password, db_password)? {
+            return Err(CustomError::NotFound);
+        }
+
+        // Upgrade hashes made with older, cheaper settings while we have the
+        // plain password.
+        if security::needs_rehash(db_password, password_config) {
+            let rehashed = security::hash_password(&user.password, password_config)?;
+            diesel::update(schema::users::table.find(db_user.id))
+                .set(schema::users::password.eq(rehashed))
+                .execute(conn)?;
+        }
+
+        if db_user.totp_enabled {
+            // The password alone isn't enough; the session is opened by
+            // `/users/login/mfa` once the second factor checks out.
+            Ok(Json(ApiLoginResponse::MfaRequired(mfa::challenge(
+                app_config, db_user.id,
+            )?)))
         } else {
-            Err(CustomError::NotFound)
+            Ok(Json(ApiLoginResponse::Session(auth::start_session(
+                conn, app_config, db_user.id, client,
+            )?)))
         }
-    })
+    });
+
+    // Unknown usernames count as failures too, so they can't be told apart.
+    // Only a full login clears the count; passing the password step of a
+    // two-factor login doesn't.
+    match &result {
+        Err(CustomError::NotFound | CustomError::DatabaseError(DieselError::NotFound)) => {
+            rate_limiter.failed(Account::Username(&user.username))
+        }
+        Ok(response) if matches!(response.0, ApiLoginResponse::Session(_)) => {
+            rate_limiter.succeeded(Account::Username(&user.username))
+        }
+        _ => {}
+    }
+    result
+}
+
+fn to_profile(user: DbUser) -> ApiProfile {
+    ApiProfile {
+        id: user.id,
+        username: user.username,
+        email: user.email,
+        email_verified: user.email_verified,
+        email_reminders: user.email_reminders,
+        daily_digest: user.daily_digest,
+        totp_enabled: user.totp_enabled,
+        has_password: user.password.is_some(),
+        role: api::Role::parse(&user.role).unwrap_or_default(),
+    }
+}
+
+#[get("/users/me")]
+fn get_profile(
+    pool: &State<DbPool>,
+    auth_user: AuthenticatedUser,
+) -> Result<Json<ApiProfile>, CustomError> {
+    let mut conn = pool.get().expect("Failed to get DB connection from pool");
+
+    let db_user = schema::users::table
+        .find(auth_user.user_id)
+        .first::<DbUser>(&mut conn)?;
+
+    Ok(Json(to_profile(db_user)))
+}
+
+/// Sets or clears the account's email address. A new address starts out
+/// unverified and is sent a confirmation link; nothing else is emailed to it
+/// until that link has been opened.
+#[put("/users/me/email", data = "<email_json>")]
+fn update_email(
+    pool: &State<DbPool>,
+    app_config: &State<AppConfig>,
+    mailer: &State<mailer::Mailer>,
+    auth_user: AuthenticatedUser,
+    email_json: Json<EmailUpdate>,
+) -> Result<Json<ApiProfile>, CustomError> {
+    let email = email_json.into_inner().email.map(|e| e.trim().to_string());
+    if let Some(email) = &email
+        && !mailer::is_valid_address(email)
+    {
+        return Err(CustomError::InvalidInput(String::from(
+            "The provided email address is not valid.",
+        )));
+    }
+    let mut conn = pool.get().expect("Failed to get DB connection from pool");
+
+    let token = email.as_ref().map(|_| security::generate_token(32));
+    let db_user = diesel::update(schema::users::table.find(auth_user.user_id))
+        .set((
+            schema::users::email.eq(&email),
+            schema::users::email_verified.eq(false),
+            schema::users::email_token_hash.eq(token.as_deref().map(security::hash_token)),
+            schema::users::email_token_expires_at.eq(token
+                .as_ref()
+                .map(|_| (Utc::now() + chrono::Duration::hours(24)).naive_utc())),
+        ))
+        .get_result::<DbUser>(&mut conn)?;
+
+    if let (Some(email), Some(token)) = (&db_user.email, token) {
+        let link = format!(
+            "{}/api/users/email/verify?token={}",
+            app_config.public_url, token
+        );
+        mailer.send_in_background(
+            email.clone(),
+            mailer::templates::verification(&db_user.username, &link),
+        );
+    }
+
+    Ok(Json(to_profile(db_user)))
+}
+
+#[get("/users/email/verify?<token>")]
+fn verify_email(pool: &State<DbPool>, token: &str) -> Result<Json<ApiProfile>, CustomError> {
+    let mut conn = pool.get().expect("Failed to get DB connection from pool");
+
+    let target = schema::users::table
+        .filter(schema::users::email_token_hash.eq(security::hash_token(token)))
+        .filter(schema::users::email_token_expires_at.gt(Utc::now().naive_utc()));
+    let db_user = diesel::update(target)
+        .set((
+            schema::users::email_verified.eq(true),
+            schema::users::email_token_hash.eq(None::<String>),
+            schema::users::email_token_expires_at.eq(None::<NaiveDateTime>),
+        ))
+        .get_result::<DbUser>(&mut conn)
+        .optional()?
+        .ok_or(CustomError::NotFound)?;
+
+    Ok(Json(to_profile(db_user)))
+}
+
+#[put("/users/me/notifications", data = "<settings_json>")]
+fn update_notification_settings(
+    pool: &State<DbPool>,
+    auth_user: AuthenticatedUser,
+    settings_json: Json<NotificationSettings>,
+) -> Result<Json<ApiProfile>, CustomError> {
+    let settings = settings_json.into_inner();
+    let mut conn = pool.get().expect("Failed to get DB connection from pool");
+
+    let db_user = diesel::update(schema::users::table.find(auth_user.user_id))
+        .set((
+            schema::users::email_reminders.eq(settings.email_reminders),
+            schema::users::daily_digest.eq(settings.daily_digest),
+        ))
+        .get_result::<DbUser>(&mut conn)?;
+
+    Ok(Json(to_profile(db_user)))
 }
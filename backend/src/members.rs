@@ -0,0 +1,283 @@
+//! Sharing projects: inviting people by username, changing their roles and
+//! leaving. What each role may do is decided in `access`.
+
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use diesel::sql_types::Text;
+use rocket::State;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+
+use crate::access::{self, Action};
+use crate::{AuthenticatedUser, CustomError, DbPool, api, assignees, models, schema};
+
+type DbMember = models::ProjectMember;
+type ApiMember = api::Member;
+type NewMember = api::NewMember;
+type MemberUpdate = api::MemberUpdate;
+type MemberRole = api::MemberRole;
+type Invitation = api::Invitation;
+
+define_sql_function!(fn lower(x: Text) -> Text);
+
+fn to_api_member(member: DbMember, username: String) -> ApiMember {
+    ApiMember {
+        user_id: member.user_id,
+        username,
+        role: MemberRole::parse(&member.role)
+            .unwrap_or(MemberRole::Viewer)
+            .min(MemberRole::Editor),
+        accepted: member.accepted_at.is_some(),
+        invited_at: Some(DateTime::<Utc>::from_naive_utc_and_offset(
+            member.invited_at,
+            Utc,
+        )),
+    }
+}
+
+fn find_member(
+    conn: &mut SqliteConnection,
+    project_id: i32,
+    user_id: i32,
+) -> Result<DbMember, CustomError> {
+    schema::project_members::table
+        .find((project_id, user_id))
+        .first::<DbMember>(conn)
+        .optional()?
+        .ok_or(CustomError::NotFound)
+}
+
+/// Only the project's creator is its owner; members can be given any other
+/// role.
+fn check_role(role: MemberRole) -> Result<(), CustomError> {
+    if role == MemberRole::Owner {
+        return Err(CustomError::InvalidInput(String::from(
+            "Only the project's creator can be its owner.",
+        )));
+    }
+    Ok(())
+}
+
+fn username(conn: &mut SqliteConnection, user_id: i32) -> QueryResult<String> {
+    schema::users::table
+        .find(user_id)
+        .select(schema::users::username)
+        .first(conn)
+}
+
+// --- Routes ---
+
+/// Lists a project's creator, members and open invitations.
+#[get("/projects/<id>/members")]
+pub fn list_members(
+    pool: &State<DbPool>,
+    auth_user: AuthenticatedUser,
+    id: i32,
+) -> Result<Json<Vec<ApiMember>>, CustomError> {
+    let mut conn = pool.get().expect("Failed to get DB connection from pool");
+
+    conn.transaction(|conn| {
+        access::authorize_project(conn, auth_user.user_id, id, Action::View)?;
+        let (owner_id, owner_name) = schema::projects::table
+            .inner_join(schema::users::table)
+            .filter(schema::projects::id.eq(id))
+            .select((schema::users::id, schema::users::username))
+            .first::<(i32, String)>(conn)?;
+        let members = schema::project_members::table
+            .inner_join(schema::users::table)
+            .filter(schema::project_members::project_id.eq(id))
+            .order(schema::project_members::invited_at.asc())
+            .select((DbMember::as_select(), schema::users::username))
+            .load::<(DbMember, String)>(conn)?;
+
+        let mut listed = vec![ApiMember {
+            user_id: owner_id,
+            username: owner_name,
+            role: MemberRole::Owner,
+            accepted: true,
+            invited_at: None,
+        }];
+        listed.extend(
+            members
+                .into_iter()
+                .map(|(member, username)| to_api_member(member, username)),
+        );
+        Ok(Json(listed))
+    })
+}
+
+/// Invites someone to a project by username. They get access once they
+/// accept.
+#[post("/projects/<id>/members", data = "<member_json>")]
+pub fn invite_member(
+    pool: &State<DbPool>,
+    auth_user: AuthenticatedUser,
+    id: i32,
+    member_json: Json<NewMember>,
+) -> Result<Json<ApiMember>, CustomError> {
+    let new_member = member_json.into_inner();
+    check_role(new_member.role)?;
+    let mut conn = pool.get().expect("Failed to get DB connection from pool");
+
+    conn.transaction(|conn| {
+        access::authorize_project(conn, auth_user.user_id, id, Action::Manage)?;
+        let (user_id, username) = schema::users::table
+            .filter(
+                lower(schema::users::username).eq(new_member.username.trim().to_ascii_lowercase()),
+            )
+            .select((schema::users::id, schema::users::username))
+            .first::<(i32, String)>(conn)
+            .optional()?
+            .ok_or_else(|| {
+                CustomError::InvalidInput(String::from("There's no user with that name."))
+            })?;
+
+        let owner_id = schema::projects::table
+            .find(id)
+            .select(schema::projects::user_id)
+            .first::<i32>(conn)?;
+        let already = schema::project_members::table
+            .find((id, user_id))
+            .first::<DbMember>(conn)
+            .optional()?
+            .is_some();
+        if user_id == owner_id || already {
+            return Err(CustomError::InvalidInput(String::from(
+                "They're already in the project or invited to it.",
+            )));
+        }
+
+        let member = diesel::insert_into(schema::project_members::table)
+            .values(&DbMember {
+                project_id: id,
+                user_id,
+                role: new_member.role.as_str().to_string(),
+                invited_by: auth_user.user_id,
+                invited_at: Utc::now().naive_utc(),
+                accepted_at: None,
+            })
+            .get_result::<DbMember>(conn)?;
+        Ok(Json(to_api_member(member, username)))
+    })
+}
+
+/// Changes a member's role. The creator's can't be changed.
+#[put("/projects/<id>/members/<user_id>", data = "<update_json>")]
+pub fn update_member(
+    pool: &State<DbPool>,
+    auth_user: AuthenticatedUser,
+    id: i32,
+    user_id: i32,
+    update_json: Json<MemberUpdate>,
+) -> Result<Json<ApiMember>, CustomError> {
+    let role = update_json.into_inner().role;
+    check_role(role)?;
+    let mut conn = pool.get().expect("Failed to get DB connection from pool");
+
+    conn.transaction(|conn| {
+        access::authorize_project(conn, auth_user.user_id, id, Action::Manage)?;
+        let member = find_member(conn, id, user_id)?;
+
+        let member = diesel::update(schema::project_members::table.find((id, member.user_id)))
+            .set(schema::project_members::role.eq(role.as_str()))
+            .get_result::<DbMember>(conn)?;
+        let username = username(conn, user_id)?;
+        Ok(Json(to_api_member(member, username)))
+    })
+}
+
+/// Removes someone from a project or withdraws their invitation. Members can
+/// also remove themselves to leave. The creator can't be removed.
+#[delete("/projects/<id>/members/<user_id>")]
+pub fn remove_member(
+    pool: &State<DbPool>,
+    auth_user: AuthenticatedUser,
+    id: i32,
+    user_id: i32,
+) -> Result<Status, CustomError> {
+    let mut conn = pool.get().expect("Failed to get DB connection from pool");
+
+    conn.transaction(|conn| {
+        if user_id != auth_user.user_id {
+            access::authorize_project(conn, auth_user.user_id, id, Action::Manage)?;
+        }
+        let member = find_member(conn, id, user_id)?;
+
+        diesel::delete(schema::project_members::table.find((id, member.user_id))).execute(conn)?;
+        // Nobody stays assigned to todos they can no longer see.
+        assignees::unassign_from_project(conn, auth_user.user_id, id, member.user_id)?;
+        Ok(Status::NoContent)
+    })
+}
+
+/// Lists the signed-in user's open invitations.
+#[get("/invitations")]
+pub fn list_invitations(
+    pool: &State<DbPool>,
+    auth_user: AuthenticatedUser,
+) -> Result<Json<Vec<Invitation>>, CustomError> {
+    let mut conn = pool.get().expect("Failed to get DB connection from pool");
+
+    let pending = schema::project_members::table
+        .inner_join(schema::projects::table)
+        .filter(schema::project_members::user_id.eq(auth_user.user_id))
+        .filter(schema::project_members::accepted_at.is_null())
+        .order(schema::project_members::invited_at.desc())
+        .select((DbMember::as_select(), schema::projects::name))
+        .load::<(DbMember, String)>(&mut conn)?;
+
+    let mut invitations = Vec::new();
+    for (member, project_name) in pending {
+        invitations.push(Invitation {
+            project_id: member.project_id,
+            project_name,
+            role: MemberRole::parse(&member.role)
+                .unwrap_or(MemberRole::Viewer)
+                .min(MemberRole::Editor),
+            invited_by: username(&mut conn, member.invited_by)?,
+            invited_at: DateTime::<Utc>::from_naive_utc_and_offset(member.invited_at, Utc),
+        });
+    }
+    Ok(Json(invitations))
+}
+
+#[post("/invitations/<project_id>")]
+pub fn accept_invitation(
+    pool: &State<DbPool>,
+    auth_user: AuthenticatedUser,
+    project_id: i32,
+) -> Result<Status, CustomError> {
+    let mut conn = pool.get().expect("Failed to get DB connection from pool");
+
+    let accepted = diesel::update(
+        schema::project_members::table
+            .find((project_id, auth_user.user_id))
+            .filter(schema::project_members::accepted_at.is_null()),
+    )
+    .set(schema::project_members::accepted_at.eq(Utc::now().naive_utc()))
+    .execute(&mut conn)?;
+    if accepted == 0 {
+        return Err(CustomError::NotFound);
+    }
+    Ok(Status::NoContent)
+}
+
+#[delete("/invitations/<project_id>")]
+pub fn decline_invitation(
+    pool: &State<DbPool>,
+    auth_user: AuthenticatedUser,
+    project_id: i32,
+) -> Result<Status, CustomError> {
+    let mut conn = pool.get().expect("Failed to get DB connection from pool");
+
+    let declined = diesel::delete(
+        schema::project_members::table
+            .find((project_id, auth_user.user_id))
+            .filter(schema::project_members::accepted_at.is_null()),
+    )
+    .execute(&mut conn)?;
+    if declined == 0 {
+        return Err(CustomError::NotFound);
+    }
+    Ok(Status::NoContent)
+}
@@ -0,0 +1,318 @@
+use chrono::{Duration, Utc};
+use diesel::prelude::*;
+use rocket::State;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+
+use crate::ratelimit::{Account, RateLimiter};
+use crate::security::PasswordConfig;
+use crate::{
+    AppConfig, AuthenticatedUser, CustomError, DbPool, DbUser, api, auth, models, schema, security,
+    totp,
+};
+
+type DbRecoveryCode = models::RecoveryCode;
+type DbInsertableRecoveryCode = models::InsertableRecoveryCode;
+type ApiLoginResponse = api::LoginResponse;
+type ApiMfaChallenge = api::MfaChallenge;
+type ApiMfaClaims = api::MfaClaims;
+type MfaLogin = api::MfaLogin;
+type MfaCode = api::MfaCode;
+type TotpEnrollment = api::TotpEnrollment;
+type RecoveryCodes = api::RecoveryCodes;
+
+/// How long the password step of a login stays valid while waiting for the code.
+const MFA_TOKEN_TTL: Duration = Duration::minutes(5);
+const RECOVERY_CODE_COUNT: usize = 10;
+/// Recovery codes are this many random bytes, shown as twice as many hex
+/// digits.
+const RECOVERY_CODE_BYTES: usize = 5;
+/// Shown as the account's issuer in authenticator apps.
+const ISSUER: &str = "TooDoo";
+
+/// Issues the token a user with two-factor authentication gets after the
+/// password check, in place of a session.
+pub fn challenge(app_config: &AppConfig, user_id: i32) -> Result<ApiMfaChallenge, CustomError> {
+    let mfa_token = app_config.keys.encode(&ApiMfaClaims {
+        sub: user_id,
+        exp: (Utc::now() + MFA_TOKEN_TTL).timestamp() as usize,
+        mfa_pending: true,
+    })?;
+
+    Ok(ApiMfaChallenge {
+        mfa_required: true,
+        mfa_token,
+    })
+}
+
+/// Accepts either a current authenticator code or an unused recovery code,
+/// and uses it up so it can't be presented again.
+fn check_second_factor(
+    conn: &mut SqliteConnection,
+    user: &DbUser,
+    code: &str,
+) -> Result<bool, CustomError> {
+    let Some(secret) = user.totp_secret.as_deref() else {
+        return Ok(false);
+    };
+
+    if let Some(step) = totp::verify(secret, code, Utc::now(), user.totp_last_step) {
+        // Conditional on the last step so two requests racing with the same
+        // code can't both get in.
+        let claimed = diesel::update(
+            schema::users::table
+                .filter(schema::users::id.eq(user.id))
+                .filter(
+                    schema::users::totp_last_step
+                        .is_null()
+                        .or(schema::users::totp_last_step.lt(step)),
+                ),
+        )
+        .set(schema::users::totp_last_step.eq(step))
+        .execute(conn)?;
+        return Ok(claimed == 1);
+    }
+
+    // Only something shaped like a recovery code is worth the Argon2 checks.
+    let Some(code) = normalize_recovery_code(code) else {
+        return Ok(false);
+    };
+    let unused = DbRecoveryCode::belonging_to(user)
+        .filter(schema::recovery_codes::used_at.is_null())
+        .load::<DbRecoveryCode>(conn)?;
+    for recovery_code in unused {
+        if security::verify_password(&code, &recovery_code.code_hash)? {
+            let claimed = diesel::update(
+                schema::recovery_codes::table
+                    .filter(schema::recovery_codes::id.eq(recovery_code.id))
+                    .filter(schema::recovery_codes::used_at.is_null()),
+            )
+            .set(schema::recovery_codes::used_at.eq(Utc::now().naive_utc()))
+            .execute(conn)?;
+            return Ok(claimed == 1);
+        }
+    }
+    Ok(false)
+}
+
+/// Recovery codes are shown grouped as `xxxxx-xxxxx`; accept them with or
+/// without the dash and in any case. Returns `None` for anything that can't
+/// be a recovery code.
+fn normalize_recovery_code(code: &str) -> Option<String> {
+    let code: String = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    (code.len() == RECOVERY_CODE_BYTES * 2 && code.bytes().all(|b| b.is_ascii_hexdigit()))
+        .then_some(code)
+}
+
+/// Replaces all of a user's recovery codes with a fresh set and returns them
+/// in plain text. Only their Argon2 hashes are stored.
+fn replace_recovery_codes(
+    conn: &mut SqliteConnection,
+    password_config: &PasswordConfig,
+    user_id: i32,
+) -> Result<Vec<String>, CustomError> {
+    diesel::delete(
+        schema::recovery_codes::table.filter(schema::recovery_codes::user_id.eq(user_id)),
+    )
+    .execute(conn)?;
+
+    let mut codes = Vec::with_capacity(RECOVERY_CODE_COUNT);
+    let mut rows = Vec::with_capacity(RECOVERY_CODE_COUNT);
+    for _ in 0..RECOVERY_CODE_COUNT {
+        let code = security::generate_token(RECOVERY_CODE_BYTES);
+        rows.push(DbInsertableRecoveryCode {
+            user_id,
+            code_hash: security::hash_password(&code, password_config)?,
+        });
+        codes.push(format!("{}-{}", &code[..5], &code[5..]));
+    }
+    diesel::insert_into(schema::recovery_codes::table)
+        .values(&rows)
+        .execute(conn)?;
+
+    Ok(codes)
+}
+
+fn load_user(conn: &mut SqliteConnection, user_id: i32) -> Result<DbUser, CustomError> {
+    Ok(schema::users::table
+        .filter(schema::users::id.eq(user_id))
+        .first::<DbUser>(conn)?)
+}
+
+// --- Routes ---
+
+/// Starts enrollment with a new secret. Two-factor authentication is only
+/// switched on once a code from it has been confirmed.
+#[post("/users/me/totp")]
+pub fn enroll_totp(
+    pool: &State<DbPool>,
+    auth_user: AuthenticatedUser,
+) -> Result<Json<TotpEnrollment>, CustomError> {
+    let mut conn = pool.get().expect("Failed to get DB connection from pool");
+
+    conn.transaction(|conn| {
+        let user = load_user(conn, auth_user.user_id)?;
+        if user.totp_enabled {
+            return Err(CustomError::InvalidInput(String::from(
+                "Two-factor authentication is already enabled.",
+            )));
+        }
+
+        let secret = totp::generate_secret();
+        diesel::update(schema::users::table.filter(schema::users::id.eq(user.id)))
+            .set((
+                schema::users::totp_secret.eq(&secret),
+                schema::users::totp_last_step.eq(None::<i64>),
+            ))
+            .execute(conn)?;
+
+        Ok(Json(TotpEnrollment {
+            otpauth_uri: totp::provisioning_uri(&secret, ISSUER, &user.username),
+            secret,
+        }))
+    })
+}
+
+#[post("/users/me/totp/confirm", data = "<code_json>")]
+pub fn confirm_totp(
+    pool: &State<DbPool>,
+    password_config: &State<PasswordConfig>,
+    auth_user: AuthenticatedUser,
+    code_json: Json<MfaCode>,
+) -> Result<Json<RecoveryCodes>, CustomError> {
+    let code = code_json.into_inner().code;
+    let mut conn = pool.get().expect("Failed to get DB connection from pool");
+
+    conn.transaction(|conn| {
+        let user = load_user(conn, auth_user.user_id)?;
+        if user.totp_enabled {
+            return Err(CustomError::InvalidInput(String::from(
+                "Two-factor authentication is already enabled.",
+            )));
+        }
+        let Some(secret) = user.totp_secret.as_deref() else {
+            return Err(CustomError::InvalidInput(String::from(
+                "Start enrollment before confirming a code.",
+            )));
+        };
+        let Some(step) = totp::verify(secret, &code, Utc::now(), None) else {
+            return Err(CustomError::InvalidMfaCode);
+        };
+
+        diesel::update(schema::users::table.filter(schema::users::id.eq(user.id)))
+            .set((
+                schema::users::totp_enabled.eq(true),
+                schema::users::totp_last_step.eq(step),
+            ))
+            .execute(conn)?;
+
+        Ok(Json(RecoveryCodes {
+            recovery_codes: replace_recovery_codes(conn, password_config, user.id)?,
+        }))
+    })
+}
+
+#[delete("/users/me/totp", data = "<code_json>")]
+pub fn disable_totp(
+    pool: &State<DbPool>,
+    auth_user: AuthenticatedUser,
+    code_json: Json<MfaCode>,
+) -> Result<Status, CustomError> {
+    let code = code_json.into_inner().code;
+    let mut conn = pool.get().expect("Failed to get DB connection from pool");
+
+    conn.transaction(|conn| {
+        let user = load_user(conn, auth_user.user_id)?;
+        if !user.totp_enabled {
+            return Err(CustomError::NotFound);
+        }
+        if !check_second_factor(conn, &user, &code)? {
+            return Err(CustomError::InvalidMfaCode);
+        }
+
+        diesel::update(schema::users::table.filter(schema::users::id.eq(user.id)))
+            .set((
+                schema::users::totp_enabled.eq(false),
+                schema::users::totp_secret.eq(None::<String>),
+                schema::users::totp_last_step.eq(None::<i64>),
+            ))
+            .execute(conn)?;
+        diesel::delete(
+            schema::recovery_codes::table.filter(schema::recovery_codes::user_id.eq(user.id)),
+        )
+        .execute(conn)?;
+
+        Ok(Status::NoContent)
+    })
+}
+
+#[post("/users/me/totp/recovery-codes", data = "<code_json>")]
+pub fn regenerate_recovery_codes(
+    pool: &State<DbPool>,
+    password_config: &State<PasswordConfig>,
+    auth_user: AuthenticatedUser,
+    code_json: Json<MfaCode>,
+) -> Result<Json<RecoveryCodes>, CustomError> {
+    let code = code_json.into_inner().code;
+    let mut conn = pool.get().expect("Failed to get DB connection from pool");
+
+    conn.transaction(|conn| {
+        let user = load_user(conn, auth_user.user_id)?;
+        if !user.totp_enabled {
+            return Err(CustomError::NotFound);
+        }
+        if !check_second_factor(conn, &user, &code)? {
+            return Err(CustomError::InvalidMfaCode);
+        }
+
+        Ok(Json(RecoveryCodes {
+            recovery_codes: replace_recovery_codes(conn, password_config, user.id)?,
+        }))
+    })
+}
+
+/// Second step of a login for accounts with two-factor authentication.
+#[post("/users/login/mfa", data = "<login_json>")]
+pub fn login_mfa(
+    pool: &State<DbPool>,
+    app_config: &State<AppConfig>,
+    rate_limiter: &State<RateLimiter>,
+    client: auth::ClientInfo,
+    login_json: Json<MfaLogin>,
+) -> Result<Json<ApiLoginResponse>, CustomError> {
+    let login = login_json.into_inner();
+    let claims = match app_config.keys.decode::<ApiMfaClaims>(&login.mfa_token) {
+        Ok(claims) if claims.mfa_pending => claims,
+        _ => return Err(CustomError::InvalidMfaCode),
+    };
+    rate_limiter.check(client.ip.as_deref(), Some(Account::MfaUser(claims.sub)))?;
+    let mut conn = pool.get().expect("Failed to get DB connection from pool");
+
+    let result = conn.transaction(|conn| {
+        let user = load_user(conn, claims.sub)?;
+        if !user.totp_enabled {
+            return Err(CustomError::InvalidMfaCode);
+        }
+        if !check_second_factor(conn, &user, &login.code)? {
+            return Err(CustomError::InvalidMfaCode);
+        }
+        auth::start_session(conn, app_config, user.id, client)
+    });
+
+    match result {
+        Ok(session) => {
+            rate_limiter.succeeded(Account::MfaUser(claims.sub));
+            Ok(Json(ApiLoginResponse::Session(session)))
+        }
+        Err(e) => {
+            if matches!(e, CustomError::InvalidMfaCode) {
+                rate_limiter.failed(Account::MfaUser(claims.sub));
+            }
+            Err(e)
+        }
+    }
+}
@@ -1,7 +1,11 @@
-use super::schema::{users, todos, reminders}; // Use `super` to refer to parent module
-use chrono::NaiveDateTime;
-use serde::Serialize;
+use super::schema::{
+    api_tokens, comment_mentions, comments, completions, invites, project_members, projects,
+    rate_limits, recovery_codes, refresh_tokens, reminders, sessions, settings, tags,
+    todo_assignees, todo_history, todo_tags, todos, users, webhook_deliveries, webhooks,
+}; // Use `super` to refer to parent module
+use chrono::{NaiveDate, NaiveDateTime};
 use diesel::prelude::*;
+use serde::Serialize;
 
 #[derive(Queryable, Selectable, Insertable, Identifiable, Associations)]
 #[diesel(table_name = users)]
@@ -10,17 +14,34 @@ use diesel::prelude::*;
 pub struct User {
     pub id: i32,
     pub username: String,
-    pub password: String,
+    /// `None` for accounts that only sign in through single sign-on.
+    pub password: Option<String>,
+    pub email: Option<String>,
+    pub email_verified: bool,
+    pub email_token_hash: Option<String>,
+    pub email_token_expires_at: Option<NaiveDateTime>,
+    pub email_reminders: bool,
+    pub daily_digest: bool,
+    pub last_digest_on: Option<NaiveDate>,
+    pub totp_secret: Option<String>,
+    pub totp_enabled: bool,
+    pub totp_last_step: Option<i64>,
+    pub password_reset_hash: Option<String>,
+    pub password_reset_expires_at: Option<NaiveDateTime>,
+    pub oidc_issuer: Option<String>,
+    pub oidc_subject: Option<String>,
+    pub role: String,
+    pub disabled_at: Option<NaiveDateTime>,
 }
 
 #[derive(Insertable)]
 #[diesel(table_name = users)]
 pub struct InsertableUser {
     pub username: String,
-    pub password: String,
+    pub password: Option<String>,
+    pub role: String,
 }
 
-
 #[derive(Queryable, Selectable, Identifiable, Associations, Serialize, Clone)]
 #[diesel(table_name = todos)]
 #[diesel(belongs_to(User, foreign_key = user_id))]
@@ -33,9 +54,16 @@ pub struct Todo {
     pub due: Option<NaiveDateTime>,
     pub repeat: String,
     pub completed: bool,
+    pub repeat_from: String,
+    pub project_id: Option<i32>,
+    pub parent_id: Option<i32>,
+    pub auto_complete: bool,
+    pub priority: String,
+    pub status: String,
+    pub rank: String,
+    pub deleted_at: Option<NaiveDateTime>,
 }
 
-
 #[derive(Queryable, Selectable, Identifiable, Associations, Serialize, Clone)]
 #[diesel(table_name = reminders)]
 #[diesel(belongs_to(Todo))]
@@ -44,6 +72,9 @@ pub struct Reminder {
     pub id: i32,
     pub todo_id: i32,
     pub reminder: NaiveDateTime,
+    pub delivered_at: Option<NaiveDateTime>,
+    pub claimed_until: Option<NaiveDateTime>,
+    pub attempts: i32,
 }
 
 #[derive(Insertable)]
@@ -62,4 +93,346 @@ pub struct InsertableTodo {
     pub due: Option<chrono::NaiveDateTime>,
     pub repeat: String,
     pub completed: bool,
-}
\ No newline at end of file
+    pub repeat_from: String,
+    pub project_id: Option<i32>,
+    pub parent_id: Option<i32>,
+    pub auto_complete: bool,
+    pub priority: String,
+    pub status: String,
+    pub rank: String,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = completions)]
+pub struct InsertableCompletion {
+    pub todo_id: i32,
+    pub completed_at: NaiveDateTime,
+    pub due: Option<NaiveDateTime>,
+}
+
+#[derive(Queryable, Selectable, Identifiable, Associations, Clone)]
+#[diesel(table_name = webhooks)]
+#[diesel(belongs_to(User, foreign_key = user_id))]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct Webhook {
+    pub id: i32,
+    pub user_id: i32,
+    pub url: String,
+    pub secret: String,
+    pub events: String,
+    pub active: bool,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = webhooks)]
+pub struct InsertableWebhook {
+    pub user_id: i32,
+    pub url: String,
+    pub secret: String,
+    pub events: String,
+    pub active: bool,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Queryable, Selectable, Identifiable, Associations, Clone)]
+#[diesel(table_name = webhook_deliveries)]
+#[diesel(belongs_to(Webhook))]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct WebhookDelivery {
+    pub id: i32,
+    pub webhook_id: i32,
+    pub delivery_id: String,
+    pub event: String,
+    pub attempt: i32,
+    pub status_code: Option<i32>,
+    pub error: Option<String>,
+    pub succeeded: bool,
+    pub attempted_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = webhook_deliveries)]
+pub struct InsertableWebhookDelivery {
+    pub webhook_id: i32,
+    pub delivery_id: String,
+    pub event: String,
+    pub attempt: i32,
+    pub status_code: Option<i32>,
+    pub error: Option<String>,
+    pub succeeded: bool,
+    pub attempted_at: NaiveDateTime,
+}
+
+#[derive(Queryable, Selectable, Identifiable, Associations, Clone)]
+#[diesel(table_name = sessions)]
+#[diesel(belongs_to(User, foreign_key = user_id))]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct Session {
+    pub id: i32,
+    pub user_id: i32,
+    pub created_at: NaiveDateTime,
+    pub revoked_at: Option<NaiveDateTime>,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+    pub last_seen_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = sessions)]
+pub struct InsertableSession {
+    pub user_id: i32,
+    pub created_at: NaiveDateTime,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+    pub last_seen_at: NaiveDateTime,
+}
+
+#[derive(Queryable, Selectable, Identifiable, Associations, Clone)]
+#[diesel(table_name = refresh_tokens)]
+#[diesel(belongs_to(Session))]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct RefreshToken {
+    pub id: i32,
+    pub session_id: i32,
+    pub token_hash: String,
+    pub created_at: NaiveDateTime,
+    pub expires_at: NaiveDateTime,
+    pub used_at: Option<NaiveDateTime>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = refresh_tokens)]
+pub struct InsertableRefreshToken {
+    pub session_id: i32,
+    pub token_hash: String,
+    pub created_at: NaiveDateTime,
+    pub expires_at: NaiveDateTime,
+}
+
+#[derive(Queryable, Selectable, Identifiable, Associations, Clone)]
+#[diesel(table_name = recovery_codes)]
+#[diesel(belongs_to(User, foreign_key = user_id))]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct RecoveryCode {
+    pub id: i32,
+    pub user_id: i32,
+    pub code_hash: String,
+    pub used_at: Option<NaiveDateTime>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = recovery_codes)]
+pub struct InsertableRecoveryCode {
+    pub user_id: i32,
+    pub code_hash: String,
+}
+
+#[derive(Queryable, Selectable, Identifiable, Associations, Clone)]
+#[diesel(table_name = api_tokens)]
+#[diesel(belongs_to(User, foreign_key = user_id))]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct ApiToken {
+    pub id: i32,
+    pub user_id: i32,
+    pub name: String,
+    pub token_hash: String,
+    pub scopes: String,
+    pub created_at: NaiveDateTime,
+    pub expires_at: Option<NaiveDateTime>,
+    pub last_used_at: Option<NaiveDateTime>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = api_tokens)]
+pub struct InsertableApiToken {
+    pub user_id: i32,
+    pub name: String,
+    pub token_hash: String,
+    pub scopes: String,
+    pub created_at: NaiveDateTime,
+    pub expires_at: Option<NaiveDateTime>,
+}
+
+#[derive(Queryable, Selectable, Insertable, AsChangeset, Clone)]
+#[diesel(table_name = rate_limits)]
+#[diesel(primary_key(key))]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct RateLimit {
+    pub key: String,
+    pub tokens: f64,
+    pub updated_at: NaiveDateTime,
+    pub failures: i32,
+    pub locked_until: Option<NaiveDateTime>,
+}
+
+#[derive(Queryable, Selectable, Insertable, AsChangeset, Clone)]
+#[diesel(table_name = settings)]
+#[diesel(primary_key(key))]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct Setting {
+    pub key: String,
+    pub value: String,
+}
+
+#[derive(Queryable, Selectable, Identifiable, Associations, Clone)]
+#[diesel(table_name = invites)]
+#[diesel(belongs_to(User, foreign_key = created_by))]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct Invite {
+    pub id: i32,
+    pub created_by: i32,
+    pub code_hash: String,
+    pub max_uses: Option<i32>,
+    pub uses: i32,
+    pub created_at: NaiveDateTime,
+    pub expires_at: Option<NaiveDateTime>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = invites)]
+pub struct InsertableInvite {
+    pub created_by: i32,
+    pub code_hash: String,
+    pub max_uses: Option<i32>,
+    pub created_at: NaiveDateTime,
+    pub expires_at: Option<NaiveDateTime>,
+}
+
+#[derive(Queryable, Selectable, Identifiable, Associations, Clone)]
+#[diesel(table_name = projects)]
+#[diesel(belongs_to(User, foreign_key = user_id))]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct Project {
+    pub id: i32,
+    pub user_id: i32,
+    pub name: String,
+    pub color: Option<String>,
+    pub icon: Option<String>,
+    pub sort_order: i32,
+    pub archived: bool,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = projects)]
+pub struct InsertableProject {
+    pub user_id: i32,
+    pub name: String,
+    pub color: Option<String>,
+    pub icon: Option<String>,
+    pub sort_order: i32,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Queryable, Selectable, Identifiable, Associations, Clone)]
+#[diesel(table_name = tags)]
+#[diesel(belongs_to(User, foreign_key = user_id))]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct Tag {
+    pub id: i32,
+    pub user_id: i32,
+    pub name: String,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = tags)]
+pub struct InsertableTag {
+    pub user_id: i32,
+    pub name: String,
+}
+
+#[derive(Queryable, Selectable, Identifiable, Associations, Insertable, Clone)]
+#[diesel(table_name = todo_tags)]
+#[diesel(primary_key(todo_id, tag_id))]
+#[diesel(belongs_to(Todo))]
+#[diesel(belongs_to(Tag))]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct TodoTag {
+    pub todo_id: i32,
+    pub tag_id: i32,
+}
+
+#[derive(Queryable, Selectable, Identifiable, Associations, Insertable, Clone)]
+#[diesel(table_name = project_members)]
+#[diesel(primary_key(project_id, user_id))]
+#[diesel(belongs_to(Project))]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct ProjectMember {
+    pub project_id: i32,
+    pub user_id: i32,
+    pub role: String,
+    pub invited_by: i32,
+    pub invited_at: NaiveDateTime,
+    pub accepted_at: Option<NaiveDateTime>,
+}
+
+#[derive(Queryable, Selectable, Identifiable, Associations, Insertable, Clone)]
+#[diesel(table_name = todo_assignees)]
+#[diesel(primary_key(todo_id, user_id))]
+#[diesel(belongs_to(Todo))]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct TodoAssignee {
+    pub todo_id: i32,
+    pub user_id: i32,
+    pub assigned_by: i32,
+    pub assigned_at: NaiveDateTime,
+}
+
+#[derive(Queryable, Selectable, Identifiable, Associations, Clone)]
+#[diesel(table_name = todo_history)]
+#[diesel(belongs_to(Todo))]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct HistoryEntry {
+    pub id: i32,
+    pub todo_id: i32,
+    /// `None` once the user who made the change has deleted their account.
+    pub user_id: Option<i32>,
+    pub field: String,
+    pub old_value: Option<String>,
+    pub new_value: Option<String>,
+    pub changed_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = todo_history)]
+pub struct InsertableHistoryEntry {
+    pub todo_id: i32,
+    pub user_id: i32,
+    pub field: String,
+    pub old_value: Option<String>,
+    pub new_value: Option<String>,
+    pub changed_at: NaiveDateTime,
+}
+
+#[derive(Queryable, Selectable, Identifiable, Associations, Clone)]
+#[diesel(table_name = comments)]
+#[diesel(belongs_to(Todo))]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct Comment {
+    pub id: i32,
+    pub todo_id: i32,
+    pub user_id: i32,
+    pub body: String,
+    pub created_at: NaiveDateTime,
+    pub edited_at: Option<NaiveDateTime>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = comments)]
+pub struct InsertableComment {
+    pub todo_id: i32,
+    pub user_id: i32,
+    pub body: String,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Queryable, Selectable, Identifiable, Associations, Insertable, Clone)]
+#[diesel(table_name = comment_mentions)]
+#[diesel(primary_key(comment_id, user_id))]
+#[diesel(belongs_to(Comment))]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct CommentMention {
+    pub comment_id: i32,
+    pub user_id: i32,
+}
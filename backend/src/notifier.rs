@@ -0,0 +1,126 @@
+use chrono::{DateTime, Utc};
+use rocket::tokio;
+use std::fmt;
+use std::sync::Arc;
+
+use crate::api::{EventKind, Todo};
+
+/// A reminder that has come due, together with the todo it belongs to.
+#[derive(Debug, Clone)]
+pub struct DueReminder {
+    pub reminder_id: i32,
+    pub user_id: i32,
+    pub remind_at: DateTime<Utc>,
+    pub todo: Todo,
+}
+
+/// A change to one of a user's todos. `todo` is the state after the change,
+/// or the last state before it for deletions.
+#[derive(Debug, Clone)]
+pub struct TodoEvent {
+    pub kind: EventKind,
+    pub user_id: i32,
+    pub occurred_at: DateTime<Utc>,
+    pub todo: Todo,
+}
+
+impl TodoEvent {
+    pub fn new(kind: EventKind, user_id: i32, todo: Todo) -> Self {
+        TodoEvent {
+            kind,
+            user_id,
+            occurred_at: Utc::now(),
+            todo,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct NotifyError(pub String);
+
+impl fmt::Display for NotifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for NotifyError {}
+
+/// Delivers notifications to users. Implementations are handed to the
+/// reminder dispatcher in `rocket()`; returning an error leaves the reminder
+/// pending so it is retried later, up to `scheduler::MAX_ATTEMPTS` times.
+#[rocket::async_trait]
+pub trait Notifier: Send + Sync {
+    async fn reminder_due(&self, reminder: &DueReminder) -> Result<(), NotifyError>;
+
+    /// Channels that only care about reminders can ignore todo events.
+    async fn todo_event(&self, _event: &TodoEvent) -> Result<(), NotifyError> {
+        Ok(())
+    }
+}
+
+/// Sends everything to several channels. Every channel is tried even if one
+/// fails; a reminder that any channel failed to send is reported as failed,
+/// so the dispatcher retries it a few times with growing delays rather than
+/// losing it. The channels that did send it will see it again on each retry.
+pub struct Fanout(pub Vec<Arc<dyn Notifier>>);
+
+#[rocket::async_trait]
+impl Notifier for Fanout {
+    async fn reminder_due(&self, reminder: &DueReminder) -> Result<(), NotifyError> {
+        let mut last_error = None;
+        for notifier in &self.0 {
+            if let Err(e) = notifier.reminder_due(reminder).await {
+                eprintln!("A notification channel failed: {}", e);
+                last_error = Some(e);
+            }
+        }
+        match last_error {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    async fn todo_event(&self, event: &TodoEvent) -> Result<(), NotifyError> {
+        for notifier in &self.0 {
+            if let Err(e) = notifier.todo_event(event).await {
+                eprintln!("A notification channel failed: {}", e);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Managed handle that route handlers use to publish todo events without
+/// waiting on delivery.
+#[derive(Clone)]
+pub struct Events(Arc<dyn Notifier>);
+
+impl Events {
+    pub fn new(notifier: Arc<dyn Notifier>) -> Self {
+        Events(notifier)
+    }
+
+    pub fn emit(&self, event: TodoEvent) {
+        let notifier = self.0.clone();
+        tokio::spawn(async move {
+            if let Err(e) = notifier.todo_event(&event).await {
+                eprintln!("Failed to publish {} event: {}", event.kind.as_str(), e);
+            }
+        });
+    }
+}
+
+/// Writes reminders to the server log.
+pub struct LogNotifier;
+
+#[rocket::async_trait]
+impl Notifier for LogNotifier {
+    async fn reminder_due(&self, reminder: &DueReminder) -> Result<(), NotifyError> {
+        eprintln!(
+            "Reminder for user {}: \"{}\" (todo {}) at {}",
+            reminder.user_id, reminder.todo.title, reminder.todo.id, reminder.remind_at
+        );
+        Ok(())
+    }
+}
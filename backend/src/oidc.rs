@@ -0,0 +1,501 @@
+//! Single sign-on through an OpenID Connect provider, using the
+//! authorization code flow with PKCE.
+//!
+//! `/users/oidc/login` sends the browser to the provider; the provider sends
+//! it back to `/users/oidc/callback`, where the code is exchanged for an ID
+//! token, the user is looked up or created, and a normal TooDoo session is
+//! opened. The session tokens are handed to the frontend in the fragment of
+//! a redirect to `/login/oidc`, so they never show up in server logs. Users
+//! with two-factor authentication get the same challenge there as after a
+//! password login, and finish at `/users/login/mfa`.
+
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel::prelude::*;
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{Algorithm, DecodingKey, Validation, decode, decode_header};
+use rocket::State;
+use rocket::http::{Cookie, CookieJar, SameSite};
+use rocket::response::Redirect;
+use rocket::serde::json::{Json, json};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::env;
+use std::sync::Mutex;
+use std::time::Duration as StdDuration;
+
+use crate::ratelimit::RateLimiter;
+use crate::{
+    AppConfig, CustomError, DbInsertableUser, DbPool, DbUser, RegistrationConfig, api, auth, mfa,
+    registration, schema, security,
+};
+
+type ApiLoginResponse = api::LoginResponse;
+
+/// How long the user has to get through the provider's login page.
+const PENDING_LOGIN_TTL: Duration = Duration::minutes(10);
+
+/// Holds a login's `state` in the browser that started it, so the callback
+/// can't be finished in another one.
+const STATE_COOKIE: &str = "oidc_state";
+/// Most logins that can be waiting on the provider at once, so starting
+/// logins without finishing them can't use up the server's memory.
+const MAX_PENDING_LOGINS: usize = 10_000;
+const REQUEST_TIMEOUT: StdDuration = StdDuration::from_secs(10);
+
+pub struct OidcConfig {
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: Option<String>,
+    pub redirect_url: String,
+    pub scopes: String,
+}
+
+impl OidcConfig {
+    /// Reads `OIDC_ISSUER`, `OIDC_CLIENT_ID`, `OIDC_CLIENT_SECRET`,
+    /// `OIDC_REDIRECT_URL` and `OIDC_SCOPES`. Returns `None` when
+    /// `OIDC_ISSUER` is unset, which disables single sign-on.
+    pub fn from_env(public_url: &str) -> Option<Self> {
+        let issuer = env::var("OIDC_ISSUER").ok()?;
+        Some(OidcConfig {
+            issuer: issuer.trim_end_matches('/').to_string(),
+            client_id: env::var("OIDC_CLIENT_ID")
+                .expect("OIDC_CLIENT_ID must be set when OIDC_ISSUER is"),
+            client_secret: env::var("OIDC_CLIENT_SECRET").ok(),
+            redirect_url: env::var("OIDC_REDIRECT_URL")
+                .unwrap_or_else(|_| format!("{}/api/users/oidc/callback", public_url)),
+            scopes: env::var("OIDC_SCOPES")
+                .unwrap_or_else(|_| String::from("openid email profile")),
+        })
+    }
+}
+
+/// A login that has been sent to the provider and not come back yet.
+struct PendingLogin {
+    code_verifier: String,
+    nonce: String,
+    started_at: NaiveDateTime,
+}
+
+/// Managed state for the OIDC routes.
+pub struct Oidc {
+    config: Option<OidcConfig>,
+    client: reqwest::Client,
+    /// Keyed by the `state` parameter of the authorization request.
+    pending: Mutex<HashMap<String, PendingLogin>>,
+}
+
+impl Oidc {
+    pub fn new(config: Option<OidcConfig>) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .expect("Failed to create OIDC HTTP client.");
+        Oidc {
+            config,
+            client,
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+
+    async fn discover(&self, config: &OidcConfig) -> Result<ProviderMetadata, String> {
+        let url = format!("{}/.well-known/openid-configuration", config.issuer);
+        let metadata: ProviderMetadata = self.get_json(&url).await?;
+        if metadata.issuer.trim_end_matches('/') != config.issuer {
+            return Err(format!(
+                "Provider reports issuer {} instead of {}",
+                metadata.issuer, config.issuer
+            ));
+        }
+        Ok(metadata)
+    }
+
+    async fn get_json<T: for<'de> Deserialize<'de>>(&self, url: &str) -> Result<T, String> {
+        self.client
+            .get(url)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| e.to_string())?
+            .json()
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    /// Exchanges the authorization code for tokens and returns the verified
+    /// claims of the ID token.
+    async fn redeem(
+        &self,
+        config: &OidcConfig,
+        code: &str,
+        pending: &PendingLogin,
+    ) -> Result<IdTokenClaims, String> {
+        let metadata = self.discover(config).await?;
+
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", config.redirect_url.as_str()),
+            ("client_id", config.client_id.as_str()),
+            ("code_verifier", pending.code_verifier.as_str()),
+        ];
+        if let Some(secret) = &config.client_secret {
+            form.push(("client_secret", secret.as_str()));
+        }
+        let tokens: TokenResponse = self
+            .client
+            .post(&metadata.token_endpoint)
+            .form(&form)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| format!("Token request failed: {}", e))?
+            .json()
+            .await
+            .map_err(|e| format!("Invalid token response: {}", e))?;
+
+        let header = decode_header(&tokens.id_token).map_err(|e| e.to_string())?;
+        let key = match header.alg {
+            Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512 => {
+                let secret = config
+                    .client_secret
+                    .as_ref()
+                    .ok_or("HMAC-signed ID token but no client secret configured")?;
+                DecodingKey::from_secret(secret.as_bytes())
+            }
+            _ => {
+                let jwks: JwkSet = self.get_json(&metadata.jwks_uri).await?;
+                let jwk = match &header.kid {
+                    Some(kid) => jwks.find(kid),
+                    None if jwks.keys.len() == 1 => jwks.keys.first(),
+                    None => None,
+                }
+                .ok_or("The ID token is signed with an unknown key")?;
+                DecodingKey::from_jwk(jwk).map_err(|e| e.to_string())?
+            }
+        };
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_audience(&[&config.client_id]);
+        validation.set_issuer(&[&config.issuer, &metadata.issuer]);
+        let claims = decode::<IdTokenClaims>(&tokens.id_token, &key, &validation)
+            .map_err(|e| format!("Invalid ID token: {}", e))?
+            .claims;
+
+        if claims.nonce.as_deref() != Some(pending.nonce.as_str()) {
+            return Err(String::from("The ID token nonce doesn't match"));
+        }
+        Ok(claims)
+    }
+}
+
+/// What the provider appends to the redirect URL.
+#[derive(FromForm)]
+pub struct CallbackParams<'r> {
+    code: Option<&'r str>,
+    state: Option<&'r str>,
+    error: Option<&'r str>,
+}
+
+#[derive(Deserialize)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+#[derive(Deserialize)]
+struct IdTokenClaims {
+    sub: String,
+    nonce: Option<String>,
+    email: Option<String>,
+    #[serde(default)]
+    email_verified: bool,
+    preferred_username: Option<String>,
+}
+
+/// Finds the user signed in at the provider: first by their provider
+/// identity, then by a verified email address matching one of ours, which
+/// links the two. Anyone else gets a new account without a password, as long
+/// as registration is open to anyone.
+fn link_or_provision(
+    conn: &mut SqliteConnection,
+    registration_config: &RegistrationConfig,
+    issuer: &str,
+    claims: &IdTokenClaims,
+) -> Result<DbUser, CustomError> {
+    let linked = schema::users::table
+        .filter(schema::users::oidc_issuer.eq(issuer))
+        .filter(schema::users::oidc_subject.eq(&claims.sub))
+        .first::<DbUser>(conn)
+        .optional()?;
+    if let Some(user) = linked {
+        return Ok(user);
+    }
+
+    let verified_email = claims.email.as_ref().filter(|_| claims.email_verified);
+    let by_email = match verified_email {
+        Some(email) => schema::users::table
+            .filter(schema::users::email.eq(email))
+            .filter(schema::users::email_verified.eq(true))
+            .filter(schema::users::oidc_subject.is_null())
+            .first::<DbUser>(conn)
+            .optional()?,
+        None => None,
+    };
+    let user_id = match by_email {
+        Some(user) => user.id,
+        None => {
+            let role = registration::admit(conn, registration_config, None)?;
+            let new_user = DbInsertableUser {
+                username: available_username(conn, registration_config, claims)?,
+                password: None,
+                role: role.as_str().to_string(),
+            };
+            diesel::insert_into(schema::users::table)
+                .values(&new_user)
+                .get_result::<DbUser>(conn)?
+                .id
+        }
+    };
+
+    let user = diesel::update(schema::users::table.find(user_id))
+        .set((
+            schema::users::oidc_issuer.eq(issuer),
+            schema::users::oidc_subject.eq(&claims.sub),
+        ))
+        .get_result::<DbUser>(conn)?;
+    match (verified_email, &user.email) {
+        (Some(email), None) => Ok(diesel::update(schema::users::table.find(user_id))
+            .set((
+                schema::users::email.eq(email),
+                schema::users::email_verified.eq(true),
+            ))
+            .get_result::<DbUser>(conn)?),
+        _ => Ok(user),
+    }
+}
+
+/// Derives a free username from the provider's claims, adding a number if
+/// the preferred one is taken.
+fn available_username(
+    conn: &mut SqliteConnection,
+    registration_config: &RegistrationConfig,
+    claims: &IdTokenClaims,
+) -> Result<String, CustomError> {
+    let wanted = claims
+        .preferred_username
+        .as_deref()
+        .or_else(|| claims.email.as_deref().and_then(|e| e.split('@').next()))
+        .unwrap_or("user");
+    // Leaves room for the number.
+    let max_length = registration_config
+        .username_max_length
+        .saturating_sub(4)
+        .max(1);
+    let mut base: String = wanted
+        .chars()
+        .filter(|c| registration::is_username_char(*c))
+        .take(max_length)
+        .collect();
+    if base.is_empty() {
+        base = String::from("user");
+    }
+    while base.len() < registration_config.username_min_length {
+        base.push('_');
+    }
+
+    let mut candidate = base.clone();
+    for suffix in 2.. {
+        if !registration::username_taken(conn, &candidate)? {
+            break;
+        }
+        candidate = format!("{}{}", base, suffix);
+    }
+    Ok(candidate)
+}
+
+fn pkce_challenge(verifier: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()))
+}
+
+fn frontend_redirect(app_config: &AppConfig, fragment: String) -> Redirect {
+    Redirect::to(format!("{}/login/oidc#{}", app_config.public_url, fragment))
+}
+
+fn error_redirect(app_config: &AppConfig, message: &str) -> Redirect {
+    let fragment = reqwest::Url::parse_with_params("x:", &[("error", message)])
+        .map(|url| url.query().unwrap_or_default().to_string())
+        .unwrap_or_default();
+    frontend_redirect(app_config, fragment)
+}
+
+/// The state cookie, scoped to the callback so it isn't sent anywhere else.
+fn state_cookie(config: &OidcConfig, state: String) -> Cookie<'static> {
+    let path = reqwest::Url::parse(&config.redirect_url)
+        .map(|url| url.path().to_string())
+        .unwrap_or_else(|_| String::from("/"));
+    Cookie::build((STATE_COOKIE, state))
+        .path(path)
+        .http_only(true)
+        .secure(config.redirect_url.starts_with("https:"))
+        // Lax still sends it on the provider's top-level redirect back.
+        .same_site(SameSite::Lax)
+        .max_age(rocket::time::Duration::seconds(
+            PENDING_LOGIN_TTL.num_seconds(),
+        ))
+        .build()
+}
+
+// --- Routes ---
+
+/// Tells the frontend whether to offer single sign-on.
+#[get("/users/oidc")]
+pub fn oidc_status(oidc: &State<Oidc>) -> Json<rocket::serde::json::Value> {
+    Json(json!({ "enabled": oidc.config.is_some() }))
+}
+
+#[get("/users/oidc/login")]
+pub async fn oidc_login(
+    oidc: &State<Oidc>,
+    rate_limiter: &State<RateLimiter>,
+    client: auth::ClientInfo,
+    cookies: &CookieJar<'_>,
+) -> Result<Redirect, CustomError> {
+    let config = oidc.config.as_ref().ok_or(CustomError::NotFound)?;
+    rate_limiter.check(client.ip.as_deref(), None)?;
+    let metadata = oidc.discover(config).await.map_err(|e| {
+        eprintln!("OIDC discovery failed: {}", e);
+        CustomError::MissingConfig
+    })?;
+
+    let state = security::generate_token(16);
+    let nonce = security::generate_token(16);
+    let code_verifier = security::generate_token(32);
+    let mut url = reqwest::Url::parse(&metadata.authorization_endpoint).map_err(|e| {
+        eprintln!("Invalid OIDC authorization endpoint: {}", e);
+        CustomError::MissingConfig
+    })?;
+    url.query_pairs_mut()
+        .append_pair("response_type", "code")
+        .append_pair("client_id", &config.client_id)
+        .append_pair("redirect_uri", &config.redirect_url)
+        .append_pair("scope", &config.scopes)
+        .append_pair("state", &state)
+        .append_pair("nonce", &nonce)
+        .append_pair("code_challenge", &pkce_challenge(&code_verifier))
+        .append_pair("code_challenge_method", "S256");
+
+    let now = Utc::now().naive_utc();
+    let mut pending = oidc.pending.lock().unwrap();
+    pending.retain(|_, login| now - login.started_at < PENDING_LOGIN_TTL);
+    if pending.len() >= MAX_PENDING_LOGINS {
+        let oldest = pending.values().map(|login| login.started_at).min();
+        let retry_after =
+            oldest.map_or(PENDING_LOGIN_TTL, |oldest| oldest + PENDING_LOGIN_TTL - now);
+        return Err(CustomError::TooManyRequests(
+            retry_after.num_seconds().max(1) as u64,
+        ));
+    }
+    pending.insert(
+        state.clone(),
+        PendingLogin {
+            code_verifier,
+            nonce,
+            started_at: now,
+        },
+    );
+    cookies.add(state_cookie(config, state));
+
+    Ok(Redirect::to(url.to_string()))
+}
+
+#[get("/users/oidc/callback?<params..>")]
+pub async fn oidc_callback(
+    pool: &State<DbPool>,
+    app_config: &State<AppConfig>,
+    registration_config: &State<RegistrationConfig>,
+    oidc: &State<Oidc>,
+    client: auth::ClientInfo,
+    cookies: &CookieJar<'_>,
+    params: CallbackParams<'_>,
+) -> Result<Redirect, CustomError> {
+    let config = oidc.config.as_ref().ok_or(CustomError::NotFound)?;
+    let started_here = cookies
+        .get(STATE_COOKIE)
+        .map(|cookie| cookie.value().to_string());
+    cookies.remove(state_cookie(config, String::new()));
+    if let Some(error) = params.error {
+        return Ok(error_redirect(app_config, error));
+    }
+    let (Some(code), Some(state)) = (params.code, params.state) else {
+        return Ok(error_redirect(app_config, "invalid_request"));
+    };
+    // A callback from a login this browser didn't start could sign it in as
+    // someone else.
+    if started_here.as_deref() != Some(state) {
+        return Ok(error_redirect(app_config, "login_failed"));
+    }
+
+    // Each login can only come back once, and only within its time limit.
+    let pending = oidc.pending.lock().unwrap().remove(state);
+    let Some(pending) =
+        pending.filter(|login| Utc::now().naive_utc() - login.started_at < PENDING_LOGIN_TTL)
+    else {
+        return Ok(error_redirect(app_config, "login_expired"));
+    };
+
+    let claims = match oidc.redeem(config, code, &pending).await {
+        Ok(claims) => claims,
+        Err(e) => {
+            eprintln!("OIDC login failed: {}", e);
+            return Ok(error_redirect(app_config, "login_failed"));
+        }
+    };
+
+    let mut conn = pool.get().expect("Failed to get DB connection from pool");
+    let response = match conn.transaction(|conn| {
+        let user = link_or_provision(conn, registration_config, &config.issuer, &claims)?;
+        // The provider vouches for the first factor only.
+        if user.totp_enabled {
+            Ok(ApiLoginResponse::MfaRequired(mfa::challenge(
+                app_config, user.id,
+            )?))
+        } else {
+            Ok(ApiLoginResponse::Session(auth::start_session(
+                conn, app_config, user.id, client,
+            )?))
+        }
+    }) {
+        Ok(response) => response,
+        Err(CustomError::RegistrationClosed | CustomError::InvalidInvite) => {
+            return Ok(error_redirect(app_config, "registration_closed"));
+        }
+        Err(CustomError::AccountDisabled) => {
+            return Ok(error_redirect(app_config, "account_disabled"));
+        }
+        Err(e) => return Err(e),
+    };
+
+    let params = match &response {
+        ApiLoginResponse::Session(session) => vec![
+            ("token", session.token.as_str()),
+            ("refresh_token", session.refresh_token.as_str()),
+        ],
+        ApiLoginResponse::MfaRequired(challenge) => {
+            vec![("mfa_token", challenge.mfa_token.as_str())]
+        }
+    };
+    let fragment = reqwest::Url::parse_with_params("x:", &params)
+        .map(|url| url.query().unwrap_or_default().to_string())
+        .unwrap_or_default();
+    Ok(frontend_redirect(app_config, fragment))
+}
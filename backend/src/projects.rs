@@ -0,0 +1,292 @@
+//! Projects group a user's todos into lists. Todos without a project are in
+//! the implicit Inbox. Projects can be shared; see `members`.
+
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use rocket::State;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use std::str::FromStr;
+
+use crate::access::{self, Action};
+use crate::{
+    AuthenticatedUser, CustomError, DbPool, DbTodo, api, assignees, history, models, schema,
+    subtasks,
+};
+
+type DbProject = models::Project;
+type DbInsertableProject = models::InsertableProject;
+type ApiProject = api::Project;
+type NewProject = api::NewProject;
+type MemberRole = api::MemberRole;
+
+const MAX_NAME_LEN: usize = 100;
+const MAX_ICON_LEN: usize = 32;
+
+/// Which todos `GET /todos?project=` asks for: `inbox` or a project id.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProjectFilter {
+    Inbox,
+    Project(i32),
+}
+
+impl FromStr for ProjectFilter {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "inbox" => Ok(ProjectFilter::Inbox),
+            value => value
+                .parse()
+                .map(ProjectFilter::Project)
+                .map_err(|_| String::from("The project filter must be `inbox` or a project id.")),
+        }
+    }
+}
+
+fn to_api_project(project: DbProject, role: MemberRole) -> ApiProject {
+    ApiProject {
+        id: project.id,
+        name: project.name,
+        color: project.color,
+        icon: project.icon,
+        sort_order: project.sort_order,
+        archived: project.archived,
+        created_at: DateTime::<Utc>::from_naive_utc_and_offset(project.created_at, Utc),
+        owner_id: project.user_id,
+        role,
+    }
+}
+
+fn validate(project: &NewProject) -> Result<(), CustomError> {
+    let name = project.name.trim();
+    if name.is_empty() || name.chars().count() > MAX_NAME_LEN {
+        return Err(CustomError::InvalidInput(format!(
+            "The project name must be between 1 and {} characters long.",
+            MAX_NAME_LEN
+        )));
+    }
+    if let Some(color) = &project.color {
+        let hex = color.strip_prefix('#').unwrap_or_default();
+        if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(CustomError::InvalidInput(String::from(
+                "The project color must be a hex color such as #3f51b5.",
+            )));
+        }
+    }
+    if project
+        .icon
+        .as_ref()
+        .is_some_and(|icon| icon.chars().count() > MAX_ICON_LEN)
+    {
+        return Err(CustomError::InvalidInput(format!(
+            "The project icon must be at most {} characters long.",
+            MAX_ICON_LEN
+        )));
+    }
+    Ok(())
+}
+
+/// Checks that `user_id` can put a todo into `project_id`, which takes
+/// being allowed to edit its todos. Archived projects keep the todos they
+/// have but don't take new ones.
+pub fn check_target(
+    conn: &mut SqliteConnection,
+    user_id: i32,
+    project_id: Option<i32>,
+) -> Result<(), CustomError> {
+    let Some(project_id) = project_id else {
+        return Ok(());
+    };
+    match access::authorize_project(conn, user_id, project_id, Action::Edit) {
+        Err(CustomError::NotFound) => {
+            return Err(CustomError::InvalidInput(String::from(
+                "The project doesn't exist.",
+            )));
+        }
+        result => result?,
+    };
+    let archived = schema::projects::table
+        .find(project_id)
+        .select(schema::projects::archived)
+        .first::<bool>(conn)?;
+    if archived {
+        return Err(CustomError::InvalidInput(String::from(
+            "Todos can't be moved into an archived project.",
+        )));
+    }
+    Ok(())
+}
+
+// --- Routes ---
+
+/// Lists the user's projects and the ones shared with them, in their sort
+/// order. Archived ones are left out unless asked for.
+#[get("/projects?<archived>")]
+pub fn list_projects(
+    pool: &State<DbPool>,
+    auth_user: AuthenticatedUser,
+    archived: Option<bool>,
+) -> Result<Json<Vec<ApiProject>>, CustomError> {
+    let mut conn = pool.get().expect("Failed to get DB connection from pool");
+
+    let roles: Vec<(i32, String)> = schema::project_members::table
+        .filter(schema::project_members::user_id.eq(auth_user.user_id))
+        .filter(schema::project_members::accepted_at.is_not_null())
+        .select((
+            schema::project_members::project_id,
+            schema::project_members::role,
+        ))
+        .load(&mut conn)?;
+    let shared_ids: Vec<i32> = roles.iter().map(|(project_id, _)| *project_id).collect();
+
+    let mut query = schema::projects::table
+        .filter(
+            schema::projects::user_id
+                .eq(auth_user.user_id)
+                .or(schema::projects::id.eq_any(shared_ids)),
+        )
+        .into_boxed();
+    if !archived.unwrap_or(false) {
+        query = query.filter(schema::projects::archived.eq(false));
+    }
+    let projects = query
+        .order((
+            schema::projects::sort_order.asc(),
+            schema::projects::id.asc(),
+        ))
+        .load::<DbProject>(&mut conn)?;
+
+    Ok(Json(
+        projects
+            .into_iter()
+            .map(|project| {
+                let role = if project.user_id == auth_user.user_id {
+                    MemberRole::Owner
+                } else {
+                    roles
+                        .iter()
+                        .find(|(project_id, _)| *project_id == project.id)
+                        .and_then(|(_, role)| MemberRole::parse(role))
+                        .unwrap_or(MemberRole::Viewer)
+                };
+                to_api_project(project, role)
+            })
+            .collect(),
+    ))
+}
+
+#[post("/projects", data = "<project_json>")]
+pub fn create_project(
+    pool: &State<DbPool>,
+    auth_user: AuthenticatedUser,
+    project_json: Json<NewProject>,
+) -> Result<Json<ApiProject>, CustomError> {
+    let project = project_json.into_inner();
+    validate(&project)?;
+    let mut conn = pool.get().expect("Failed to get DB connection from pool");
+
+    conn.transaction(|conn| {
+        let sort_order = match project.sort_order {
+            Some(sort_order) => sort_order,
+            None => schema::projects::table
+                .filter(schema::projects::user_id.eq(auth_user.user_id))
+                .select(diesel::dsl::max(schema::projects::sort_order))
+                .first::<Option<i32>>(conn)?
+                .map_or(0, |last| last + 1),
+        };
+
+        let inserted = diesel::insert_into(schema::projects::table)
+            .values(&DbInsertableProject {
+                user_id: auth_user.user_id,
+                name: project.name.trim().to_string(),
+                color: project.color,
+                icon: project.icon,
+                sort_order,
+                created_at: Utc::now().naive_utc(),
+            })
+            .get_result::<DbProject>(conn)?;
+
+        Ok(Json(to_api_project(inserted, MemberRole::Owner)))
+    })
+}
+
+#[put("/projects/<id>", data = "<project_json>")]
+pub fn update_project(
+    pool: &State<DbPool>,
+    auth_user: AuthenticatedUser,
+    id: i32,
+    project_json: Json<NewProject>,
+) -> Result<Json<ApiProject>, CustomError> {
+    let project = project_json.into_inner();
+    validate(&project)?;
+    let mut conn = pool.get().expect("Failed to get DB connection from pool");
+
+    conn.transaction(|conn| {
+        let role = access::authorize_project(conn, auth_user.user_id, id, Action::Manage)?;
+        let target = schema::projects::table.filter(schema::projects::id.eq(id));
+        let current = target.first::<DbProject>(conn)?;
+
+        let updated = diesel::update(target)
+            .set((
+                schema::projects::name.eq(project.name.trim()),
+                schema::projects::color.eq(&project.color),
+                schema::projects::icon.eq(&project.icon),
+                schema::projects::sort_order.eq(project.sort_order.unwrap_or(current.sort_order)),
+                schema::projects::archived.eq(project.archived.unwrap_or(current.archived)),
+            ))
+            .get_result::<DbProject>(conn)?;
+
+        Ok(Json(to_api_project(updated, role)))
+    })
+}
+
+/// Deletes a project. Its todos aren't deleted with it; they go back to the
+/// Inbox of whoever created them.
+#[delete("/projects/<id>")]
+pub fn delete_project(
+    pool: &State<DbPool>,
+    auth_user: AuthenticatedUser,
+    id: i32,
+) -> Result<Status, CustomError> {
+    let mut conn = pool.get().expect("Failed to get DB connection from pool");
+
+    conn.transaction(|conn| {
+        access::authorize_project(conn, auth_user.user_id, id, Action::Manage)?;
+
+        let todos = diesel::update(schema::todos::table.filter(schema::todos::project_id.eq(id)))
+            .set(schema::todos::project_id.eq(None::<i32>))
+            .get_results::<DbTodo>(conn)?;
+        diesel::delete(
+            schema::project_members::table.filter(schema::project_members::project_id.eq(id)),
+        )
+        .execute(conn)?;
+        for todo in &todos {
+            history::record(
+                conn,
+                todo.id,
+                auth_user.user_id,
+                "project_id",
+                &Some(id),
+                &None,
+            )?;
+            assignees::unassign_hidden(conn, auth_user.user_id, todo)?;
+        }
+        // The todos are now spread over their creators' Inboxes, where a
+        // subtask may have ended up away from its parent.
+        let ids: Vec<i32> = todos.iter().map(|todo| todo.id).collect();
+        for (todo_id, parent_id) in subtasks::detach_strays(conn, &ids)? {
+            history::record(
+                conn,
+                todo_id,
+                auth_user.user_id,
+                "parent_id",
+                &Some(parent_id),
+                &None,
+            )?;
+        }
+        diesel::delete(schema::projects::table.filter(schema::projects::id.eq(id)))
+            .execute(conn)?;
+        Ok(Status::NoContent)
+    })
+}
@@ -0,0 +1,389 @@
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel::prelude::*;
+use std::collections::HashMap;
+use std::env;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use crate::{CustomError, DbPool, models, schema};
+
+type DbRateLimit = models::RateLimit;
+
+/// Longest an account can be locked out for, however many failures it has.
+const MAX_LOCKOUT: Duration = Duration::hours(1);
+/// Failed attempts are forgotten after this long without any attempt.
+const FAILURE_MEMORY: Duration = Duration::hours(1);
+/// Entries untouched for this long are back at their defaults and can go.
+const IDLE_AFTER: Duration = Duration::days(1);
+/// How many updates happen between sweeps of idle entries.
+const PRUNE_EVERY: u32 = 1000;
+
+/// A token bucket: up to `burst` requests at once, refilled at `per_minute`.
+#[derive(Debug, Clone, Copy)]
+pub struct Limit {
+    pub burst: f64,
+    pub per_minute: f64,
+}
+
+impl Limit {
+    fn from_env(prefix: &str, burst: f64, per_minute: f64) -> Self {
+        let read = |name: String, default: f64| {
+            env::var(&name)
+                .map(|v| {
+                    v.parse()
+                        .unwrap_or_else(|_| panic!("{} must be a number", name))
+                })
+                .unwrap_or(default)
+        };
+        Limit {
+            burst: read(format!("{}_BURST", prefix), burst),
+            per_minute: read(format!("{}_PER_MINUTE", prefix), per_minute),
+        }
+    }
+}
+
+pub struct RateLimitConfig {
+    /// Applies to every client address on the public auth endpoints.
+    pub per_ip: Limit,
+    /// Applies to login attempts for one account, from wherever they come.
+    pub per_account: Limit,
+    /// Failed attempts before an account is locked.
+    pub lockout_after: i32,
+    /// First lockout; it doubles with every further failure.
+    pub lockout: Duration,
+    /// Keep counters in the database so they survive restarts.
+    pub persistent: bool,
+}
+
+impl RateLimitConfig {
+    /// Reads `RATE_LIMIT_IP_BURST`, `RATE_LIMIT_IP_PER_MINUTE`,
+    /// `RATE_LIMIT_ACCOUNT_BURST`, `RATE_LIMIT_ACCOUNT_PER_MINUTE`,
+    /// `LOGIN_LOCKOUT_AFTER`, `LOGIN_LOCKOUT_SECONDS` and `RATE_LIMIT_STORE`
+    /// (`memory` or `sqlite`).
+    pub fn from_env() -> Self {
+        let persistent = match env::var("RATE_LIMIT_STORE").as_deref() {
+            Ok("sqlite") => true,
+            Ok("memory") | Err(_) => false,
+            Ok(other) => panic!("RATE_LIMIT_STORE must be memory or sqlite, not {}", other),
+        };
+
+        RateLimitConfig {
+            per_ip: Limit::from_env("RATE_LIMIT_IP", 20.0, 10.0),
+            per_account: Limit::from_env("RATE_LIMIT_ACCOUNT", 10.0, 5.0),
+            lockout_after: env::var("LOGIN_LOCKOUT_AFTER")
+                .map(|v| v.parse().expect("LOGIN_LOCKOUT_AFTER must be a number"))
+                .unwrap_or(5),
+            lockout: Duration::seconds(
+                env::var("LOGIN_LOCKOUT_SECONDS")
+                    .map(|v| v.parse().expect("LOGIN_LOCKOUT_SECONDS must be a number"))
+                    .unwrap_or(60),
+            ),
+            persistent,
+        }
+    }
+}
+
+/// Whose attempts are being counted.
+pub enum Account<'a> {
+    /// Password logins, by the username that was tried.
+    Username(&'a str),
+    /// Second-factor codes, by the user that passed the password step.
+    MfaUser(i32),
+}
+
+impl Account<'_> {
+    fn key(&self) -> String {
+        match self {
+            Account::Username(username) => format!("user:{}", username.trim().to_lowercase()),
+            Account::MfaUser(user_id) => format!("mfa:{}", user_id),
+        }
+    }
+}
+
+// --- Storage ---
+
+/// Where counters live. The limiter serializes access, so implementations
+/// don't need to guard against concurrent updates to the same key.
+pub trait LimitStore: Send + Sync {
+    fn load(&self, key: &str) -> Option<DbRateLimit>;
+    fn save(&self, state: DbRateLimit);
+    /// Drops entries last updated before `before`.
+    fn prune(&self, before: NaiveDateTime);
+}
+
+/// Keeps counters in memory; they reset when the server restarts.
+#[derive(Default)]
+pub struct MemoryStore(Mutex<HashMap<String, DbRateLimit>>);
+
+impl LimitStore for MemoryStore {
+    fn load(&self, key: &str) -> Option<DbRateLimit> {
+        self.0.lock().unwrap().get(key).cloned()
+    }
+
+    fn save(&self, state: DbRateLimit) {
+        self.0.lock().unwrap().insert(state.key.clone(), state);
+    }
+
+    fn prune(&self, before: NaiveDateTime) {
+        self.0
+            .lock()
+            .unwrap()
+            .retain(|_, state| state.updated_at >= before);
+    }
+}
+
+/// Keeps counters in the `rate_limits` table. Database errors are logged and
+/// otherwise ignored, so an unavailable database doesn't lock everyone out.
+pub struct SqliteStore(DbPool);
+
+impl SqliteStore {
+    pub fn new(pool: DbPool) -> Self {
+        SqliteStore(pool)
+    }
+
+    fn with_conn<T>(&self, f: impl FnOnce(&mut SqliteConnection) -> QueryResult<T>) -> Option<T> {
+        let result = self
+            .0
+            .get()
+            .map_err(|e| e.to_string())
+            .and_then(|mut conn| f(&mut conn).map_err(|e| e.to_string()));
+        match result {
+            Ok(value) => Some(value),
+            Err(e) => {
+                eprintln!("Rate limit store error: {}", e);
+                None
+            }
+        }
+    }
+}
+
+impl LimitStore for SqliteStore {
+    fn load(&self, key: &str) -> Option<DbRateLimit> {
+        self.with_conn(|conn| {
+            schema::rate_limits::table
+                .find(key)
+                .first::<DbRateLimit>(conn)
+                .optional()
+        })
+        .flatten()
+    }
+
+    fn save(&self, state: DbRateLimit) {
+        self.with_conn(|conn| {
+            diesel::replace_into(schema::rate_limits::table)
+                .values(&state)
+                .execute(conn)
+        });
+    }
+
+    fn prune(&self, before: NaiveDateTime) {
+        self.with_conn(|conn| {
+            diesel::delete(
+                schema::rate_limits::table.filter(schema::rate_limits::updated_at.lt(before)),
+            )
+            .execute(conn)
+        });
+    }
+}
+
+// --- Limiter ---
+
+/// Throttles the unauthenticated auth endpoints with per-address and
+/// per-account token buckets, and locks accounts for a growing amount of
+/// time after repeated failed logins.
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    store: Box<dyn LimitStore>,
+    lock: Mutex<()>,
+    updates: AtomicU32,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig, pool: DbPool) -> Self {
+        let store: Box<dyn LimitStore> = if config.persistent {
+            Box::new(SqliteStore::new(pool))
+        } else {
+            Box::new(MemoryStore::default())
+        };
+        RateLimiter {
+            config,
+            store,
+            lock: Mutex::new(()),
+            updates: AtomicU32::new(0),
+        }
+    }
+
+    /// Counts a request from `ip` and, for login-like requests, an attempt on
+    /// `account`. Fails with `TooManyRequests` if either is over its limit or
+    /// the account is locked.
+    pub fn check(&self, ip: Option<&str>, account: Option<Account>) -> Result<(), CustomError> {
+        let _guard = self.lock.lock().unwrap();
+        let now = Utc::now().naive_utc();
+
+        if let Some(account) = &account {
+            let state = self.state(&account.key(), self.config.per_account, now);
+            if let Some(locked_until) = state.locked_until.filter(|until| *until > now) {
+                return Err(too_many_requests(locked_until - now));
+            }
+        }
+        if let Some(ip) = ip {
+            self.take(&format!("ip:{}", ip), self.config.per_ip, now)?;
+        }
+        if let Some(account) = &account {
+            self.take(&account.key(), self.config.per_account, now)?;
+        }
+        Ok(())
+    }
+
+    /// Records a failed attempt, locking the account once there have been too
+    /// many. Each failure past the threshold doubles the lockout.
+    pub fn failed(&self, account: Account) {
+        let _guard = self.lock.lock().unwrap();
+        let now = Utc::now().naive_utc();
+
+        let mut state = self.state(&account.key(), self.config.per_account, now);
+        state.failures += 1;
+        if state.failures >= self.config.lockout_after {
+            let doublings = (state.failures - self.config.lockout_after).min(16) as u32;
+            let lockout = (self.config.lockout * 2i32.pow(doublings)).min(MAX_LOCKOUT);
+            state.locked_until = Some(now + lockout);
+        }
+        self.save(state, now);
+    }
+
+    /// Clears the failure count after a successful login.
+    pub fn succeeded(&self, account: Account) {
+        let _guard = self.lock.lock().unwrap();
+        let now = Utc::now().naive_utc();
+
+        let mut state = self.state(&account.key(), self.config.per_account, now);
+        if state.failures > 0 || state.locked_until.is_some() {
+            state.failures = 0;
+            state.locked_until = None;
+            self.save(state, now);
+        }
+    }
+
+    /// Loads `key` with its bucket refilled up to `now`, starting full for
+    /// keys seen for the first time.
+    fn state(&self, key: &str, limit: Limit, now: NaiveDateTime) -> DbRateLimit {
+        let Some(mut state) = self.store.load(key) else {
+            return DbRateLimit {
+                key: key.to_string(),
+                tokens: limit.burst,
+                updated_at: now,
+                failures: 0,
+                locked_until: None,
+            };
+        };
+
+        let idle = now - state.updated_at;
+        if idle > FAILURE_MEMORY {
+            state.failures = 0;
+        }
+        let refill = idle.as_seconds_f64().max(0.0) * limit.per_minute / 60.0;
+        state.tokens = (state.tokens + refill).min(limit.burst);
+        state.updated_at = now;
+        state
+    }
+
+    fn take(&self, key: &str, limit: Limit, now: NaiveDateTime) -> Result<(), CustomError> {
+        let mut state = self.state(key, limit, now);
+        let result = if state.tokens >= 1.0 {
+            state.tokens -= 1.0;
+            Ok(())
+        } else {
+            let wait = (1.0 - state.tokens) * 60.0 / limit.per_minute;
+            Err(too_many_requests(Duration::milliseconds(
+                (wait * 1000.0) as i64,
+            )))
+        };
+        self.save(state, now);
+        result
+    }
+
+    fn save(&self, state: DbRateLimit, now: NaiveDateTime) {
+        self.store.save(state);
+        if self.updates.fetch_add(1, Ordering::Relaxed) % PRUNE_EVERY == PRUNE_EVERY - 1 {
+            self.store.prune(now - IDLE_AFTER);
+        }
+    }
+}
+
+fn too_many_requests(wait: Duration) -> CustomError {
+    // Round up, so a client that waits exactly this long gets through.
+    let seconds = (wait.num_milliseconds() + 999) / 1000;
+    CustomError::TooManyRequests(seconds.max(1) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMIT: Limit = Limit {
+        burst: 3.0,
+        per_minute: 6.0,
+    };
+
+    fn limiter() -> RateLimiter {
+        RateLimiter {
+            config: RateLimitConfig {
+                per_ip: LIMIT,
+                per_account: LIMIT,
+                lockout_after: 5,
+                lockout: Duration::minutes(1),
+                persistent: false,
+            },
+            store: Box::new(MemoryStore::default()),
+            lock: Mutex::new(()),
+            updates: AtomicU32::new(0),
+        }
+    }
+
+    fn start() -> NaiveDateTime {
+        chrono::DateTime::from_timestamp(1_700_000_000, 0)
+            .unwrap()
+            .naive_utc()
+    }
+
+    #[test]
+    fn allows_a_burst_then_asks_to_wait_for_a_token() {
+        let limiter = limiter();
+        for _ in 0..3 {
+            assert!(limiter.take("ip:1", LIMIT, start()).is_ok());
+        }
+        assert!(matches!(
+            limiter.take("ip:1", LIMIT, start()),
+            Err(CustomError::TooManyRequests(10))
+        ));
+        // Another key has its own bucket.
+        assert!(limiter.take("ip:2", LIMIT, start()).is_ok());
+    }
+
+    #[test]
+    fn refills_at_the_configured_rate() {
+        let limiter = limiter();
+        for _ in 0..3 {
+            assert!(limiter.take("ip:1", LIMIT, start()).is_ok());
+        }
+        let later = start() + Duration::seconds(5);
+        assert!(matches!(
+            limiter.take("ip:1", LIMIT, later),
+            Err(CustomError::TooManyRequests(5))
+        ));
+        let later = start() + Duration::seconds(10);
+        assert!(limiter.take("ip:1", LIMIT, later).is_ok());
+        assert!(limiter.take("ip:1", LIMIT, later).is_err());
+    }
+
+    #[test]
+    fn never_refills_past_the_burst() {
+        let limiter = limiter();
+        assert!(limiter.take("ip:1", LIMIT, start()).is_ok());
+        let much_later = start() + Duration::hours(1);
+        for _ in 0..3 {
+            assert!(limiter.take("ip:1", LIMIT, much_later).is_ok());
+        }
+        assert!(limiter.take("ip:1", LIMIT, much_later).is_err());
+    }
+}
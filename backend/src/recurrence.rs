@@ -0,0 +1,745 @@
+use chrono::{DateTime, Datelike, Days, Months, NaiveDate, NaiveDateTime, Utc, Weekday};
+use std::fmt;
+use std::str::FromStr;
+
+// --- RFC 5545 Recurrence Rules ---
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Frequency {
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+impl fmt::Display for Frequency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Frequency::Daily => "DAILY",
+            Frequency::Weekly => "WEEKLY",
+            Frequency::Monthly => "MONTHLY",
+            Frequency::Yearly => "YEARLY",
+        })
+    }
+}
+
+/// A `BYDAY` entry such as `MO`, `2TU` or `-1FR`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WeekdayNum {
+    pub ordinal: Option<i8>,
+    pub weekday: Weekday,
+}
+
+impl fmt::Display for WeekdayNum {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(n) = self.ordinal {
+            write!(f, "{}", n)?;
+        }
+        f.write_str(weekday_code(self.weekday))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RRule {
+    pub freq: Frequency,
+    pub interval: u32,
+    pub count: Option<u32>,
+    pub until: Option<DateTime<Utc>>,
+    pub by_month: Vec<u8>,
+    pub by_month_day: Vec<i8>,
+    pub by_day: Vec<WeekdayNum>,
+    pub by_set_pos: Vec<i16>,
+    pub exdates: Vec<DateTime<Utc>>,
+}
+
+/// Upper bound on the periods scanned for the next occurrence, so rules that
+/// can never match (e.g. `BYMONTH=2;BYMONTHDAY=30`) terminate.
+const MAX_PERIODS: u32 = 10_000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseRuleError(String);
+
+impl fmt::Display for ParseRuleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid recurrence rule: {}", self.0)
+    }
+}
+
+impl std::error::Error for ParseRuleError {}
+
+fn invalid<T>(message: impl Into<String>) -> Result<T, ParseRuleError> {
+    Err(ParseRuleError(message.into()))
+}
+
+impl RRule {
+    pub fn new(freq: Frequency) -> Self {
+        RRule {
+            freq,
+            interval: 1,
+            count: None,
+            until: None,
+            by_month: Vec::new(),
+            by_month_day: Vec::new(),
+            by_day: Vec::new(),
+            by_set_pos: Vec::new(),
+            exdates: Vec::new(),
+        }
+    }
+
+    /// Returns the legacy `RepeatRule` name if this rule is exactly one of the
+    /// old fixed values, so those keep round-tripping unchanged.
+    pub fn shorthand(&self) -> Option<&'static str> {
+        if *self != RRule::new(self.freq) {
+            return None;
+        }
+        Some(match self.freq {
+            Frequency::Daily => "Daily",
+            Frequency::Weekly => "Weekly",
+            Frequency::Monthly => "Monthly",
+            Frequency::Yearly => "Yearly",
+        })
+    }
+
+    pub fn from_shorthand(name: &str) -> Option<Self> {
+        let freq = match name.to_ascii_lowercase().as_str() {
+            "daily" => Frequency::Daily,
+            "weekly" => Frequency::Weekly,
+            "monthly" => Frequency::Monthly,
+            "yearly" => Frequency::Yearly,
+            _ => return None,
+        };
+        Some(RRule::new(freq))
+    }
+
+    /// Finds the first occurrence after `start`, which is itself counted as the
+    /// first instance of the series (the RFC 5545 `DTSTART`). Returns it along
+    /// with the rule to store for the rest of the series: `COUNT` is reduced by
+    /// the instances used up and `EXDATE`s that are now in the past are dropped.
+    pub fn advance(&self, start: DateTime<Utc>) -> Option<(DateTime<Utc>, RRule)> {
+        let start_date = start.date_naive();
+        let mut instance = 1;
+
+        for period in 0..MAX_PERIODS {
+            for date in self.expand_period(start_date, period)? {
+                let occurrence = date.and_time(start.time()).and_utc();
+                if occurrence <= start {
+                    continue;
+                }
+                if self.until.is_some_and(|until| occurrence > until) {
+                    return None;
+                }
+                instance += 1;
+                if self.count.is_some_and(|count| instance > count) {
+                    return None;
+                }
+                if self.exdates.contains(&occurrence) {
+                    continue;
+                }
+
+                let mut rest = self.clone();
+                rest.count = self.count.map(|count| count - (instance - 1));
+                rest.exdates.retain(|exdate| *exdate > occurrence);
+                return Some((occurrence, rest));
+            }
+        }
+        None
+    }
+
+    /// Expands the `period`-th interval after the one containing `start` into
+    /// its candidate dates, in order and with `BYSETPOS` applied. Returns `None`
+    /// once the calendar runs out.
+    fn expand_period(&self, start: NaiveDate, period: u32) -> Option<Vec<NaiveDate>> {
+        let step = period.checked_mul(self.interval)?;
+        let mut dates = match self.freq {
+            Frequency::Daily => {
+                let date = start.checked_add_days(Days::new(step.into()))?;
+                let matches_day = self.by_day.is_empty()
+                    || self.by_day.iter().any(|d| d.weekday == date.weekday());
+                let matches_month_day = self.by_month_day.is_empty()
+                    || self
+                        .by_month_day
+                        .iter()
+                        .any(|d| resolve_month_day(date.year(), date.month(), *d) == Some(date));
+                if matches_day && matches_month_day {
+                    vec![date]
+                } else {
+                    Vec::new()
+                }
+            }
+            Frequency::Weekly => {
+                let monday = start.week(Weekday::Mon).first_day();
+                let monday = monday.checked_add_days(Days::new(u64::from(step) * 7))?;
+                monday
+                    .iter_days()
+                    .take(7)
+                    .filter(|date| match self.by_day.is_empty() {
+                        true => date.weekday() == start.weekday(),
+                        false => self.by_day.iter().any(|d| d.weekday == date.weekday()),
+                    })
+                    .collect()
+            }
+            Frequency::Monthly => {
+                let first = start.with_day(1)?.checked_add_months(Months::new(step))?;
+                self.expand_month(first.year(), first.month(), start)
+            }
+            Frequency::Yearly => {
+                let year = start.year().checked_add(i32::try_from(step).ok()?)?;
+                NaiveDate::from_ymd_opt(year, 1, 1)?;
+                self.expand_year(year, start)
+            }
+        };
+
+        if !self.by_month.is_empty() {
+            dates.retain(|date| self.by_month.contains(&(date.month() as u8)));
+        }
+        dates.sort();
+        dates.dedup();
+
+        if self.by_set_pos.is_empty() {
+            return Some(dates);
+        }
+        let mut selected: Vec<NaiveDate> = self
+            .by_set_pos
+            .iter()
+            .filter_map(|pos| pick(&dates, i32::from(*pos)))
+            .collect();
+        selected.sort();
+        selected.dedup();
+        Some(selected)
+    }
+
+    fn expand_month(&self, year: i32, month: u32, start: NaiveDate) -> Vec<NaiveDate> {
+        let Some(first) = NaiveDate::from_ymd_opt(year, month, 1) else {
+            return Vec::new();
+        };
+        let last = last_day_of_month(first);
+
+        if !self.by_month_day.is_empty() {
+            return self
+                .by_month_day
+                .iter()
+                .filter_map(|d| resolve_month_day(year, month, *d))
+                .filter(|date| {
+                    self.by_day.is_empty()
+                        || self
+                            .by_day
+                            .iter()
+                            .any(|d| weekday_num_dates(first, last, d).contains(date))
+                })
+                .collect();
+        }
+        if !self.by_day.is_empty() {
+            return self
+                .by_day
+                .iter()
+                .flat_map(|d| weekday_num_dates(first, last, d))
+                .collect();
+        }
+        NaiveDate::from_ymd_opt(year, month, start.day())
+            .into_iter()
+            .collect()
+    }
+
+    fn expand_year(&self, year: i32, start: NaiveDate) -> Vec<NaiveDate> {
+        if !self.by_month.is_empty() {
+            return self
+                .by_month
+                .iter()
+                .flat_map(|month| self.expand_month(year, u32::from(*month), start))
+                .collect();
+        }
+        if !self.by_month_day.is_empty() {
+            return (1..=12)
+                .flat_map(|month| self.expand_month(year, month, start))
+                .collect();
+        }
+        if !self.by_day.is_empty() {
+            let (Some(first), Some(last)) = (
+                NaiveDate::from_ymd_opt(year, 1, 1),
+                NaiveDate::from_ymd_opt(year, 12, 31),
+            ) else {
+                return Vec::new();
+            };
+            return self
+                .by_day
+                .iter()
+                .flat_map(|d| weekday_num_dates(first, last, d))
+                .collect();
+        }
+        NaiveDate::from_ymd_opt(year, start.month(), start.day())
+            .into_iter()
+            .collect()
+    }
+
+    fn validate(&self) -> Result<(), ParseRuleError> {
+        if self.interval == 0 {
+            return invalid("INTERVAL must be at least 1");
+        }
+        if self.count == Some(0) {
+            return invalid("COUNT must be at least 1");
+        }
+        if self.count.is_some() && self.until.is_some() {
+            return invalid("COUNT and UNTIL must not both be set");
+        }
+        if self.freq == Frequency::Weekly && !self.by_month_day.is_empty() {
+            return invalid("BYMONTHDAY is not allowed with FREQ=WEEKLY");
+        }
+        let ordinals_allowed = matches!(self.freq, Frequency::Monthly | Frequency::Yearly);
+        for day in &self.by_day {
+            match day.ordinal {
+                Some(_) if !ordinals_allowed => {
+                    return invalid("numbered BYDAY values require FREQ=MONTHLY or FREQ=YEARLY");
+                }
+                Some(n) if n == 0 || !(-53..=53).contains(&n) => {
+                    return invalid(format!("BYDAY ordinal {} is out of range", n));
+                }
+                _ => {}
+            }
+        }
+        if let Some(m) = self.by_month.iter().find(|m| !(1..=12).contains(*m)) {
+            return invalid(format!("BYMONTH value {} is out of range", m));
+        }
+        if let Some(d) = self
+            .by_month_day
+            .iter()
+            .find(|d| **d == 0 || !(-31..=31).contains(*d))
+        {
+            return invalid(format!("BYMONTHDAY value {} is out of range", d));
+        }
+        if let Some(p) = self
+            .by_set_pos
+            .iter()
+            .find(|p| **p == 0 || !(-366..=366).contains(*p))
+        {
+            return invalid(format!("BYSETPOS value {} is out of range", p));
+        }
+        if !self.by_set_pos.is_empty()
+            && self.by_day.is_empty()
+            && self.by_month_day.is_empty()
+            && self.by_month.is_empty()
+        {
+            return invalid("BYSETPOS requires another BYxxx rule part");
+        }
+        Ok(())
+    }
+
+    fn parse_rule_value(&mut self, value: &str) -> Result<(), ParseRuleError> {
+        let mut seen: Vec<&str> = Vec::new();
+        for part in value.split(';').filter(|p| !p.is_empty()) {
+            let Some((key, val)) = part.split_once('=') else {
+                return invalid(format!("malformed rule part '{}'", part));
+            };
+            if seen.contains(&key) {
+                return invalid(format!("{} is specified more than once", key));
+            }
+            seen.push(key);
+
+            match key {
+                "FREQ" => {} // Parsed up front in `from_str`.
+                "INTERVAL" => self.interval = parse_number(key, val)?,
+                "COUNT" => self.count = Some(parse_number(key, val)?),
+                "UNTIL" => {
+                    // A bare date includes the whole of that day.
+                    let until = parse_date_time(val)?;
+                    self.until = Some(if val.len() == 8 {
+                        until + chrono::Duration::days(1) - chrono::Duration::seconds(1)
+                    } else {
+                        until
+                    });
+                }
+                "BYMONTH" => self.by_month = parse_list(key, val)?,
+                "BYMONTHDAY" => self.by_month_day = parse_list(key, val)?,
+                "BYSETPOS" => self.by_set_pos = parse_list(key, val)?,
+                "BYDAY" => {
+                    self.by_day = val
+                        .split(',')
+                        .map(parse_weekday_num)
+                        .collect::<Result<_, _>>()?
+                }
+                "WKST" if val == "MO" => {} // Monday is the only week start we use.
+                _ => return invalid(format!("unsupported rule part '{}'", key)),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for RRule {
+    type Err = ParseRuleError;
+
+    /// Accepts either a bare rule value (`FREQ=DAILY;COUNT=3`) or iCalendar
+    /// content lines, where an `RRULE:` line may be followed by `EXDATE:` lines.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut rule_value = None;
+        let mut exdates = Vec::new();
+
+        for line in s.to_ascii_uppercase().lines().map(str::trim) {
+            if line.is_empty() {
+                continue;
+            }
+            if line.starts_with("EXDATE") {
+                let Some((_, values)) = line.split_once(':') else {
+                    return invalid("EXDATE line has no value");
+                };
+                for value in values.split(',') {
+                    exdates.push(parse_date_time(value)?);
+                }
+            } else if rule_value.is_some() {
+                return invalid("only one RRULE may be given");
+            } else {
+                rule_value = Some(line.strip_prefix("RRULE:").unwrap_or(line).to_string());
+            }
+        }
+
+        let Some(rule_value) = rule_value else {
+            return invalid("missing RRULE");
+        };
+        let freq = rule_value
+            .split(';')
+            .find_map(|part| part.strip_prefix("FREQ="));
+        let freq = match freq {
+            Some("DAILY") => Frequency::Daily,
+            Some("WEEKLY") => Frequency::Weekly,
+            Some("MONTHLY") => Frequency::Monthly,
+            Some("YEARLY") => Frequency::Yearly,
+            Some(other) => return invalid(format!("unsupported FREQ '{}'", other)),
+            None => return invalid("FREQ is required"),
+        };
+
+        let mut rule = RRule::new(freq);
+        rule.parse_rule_value(&rule_value)?;
+        exdates.sort();
+        exdates.dedup();
+        rule.exdates = exdates;
+        rule.validate()?;
+        Ok(rule)
+    }
+}
+
+impl fmt::Display for RRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "RRULE:FREQ={}", self.freq)?;
+        if self.interval != 1 {
+            write!(f, ";INTERVAL={}", self.interval)?;
+        }
+        if let Some(count) = self.count {
+            write!(f, ";COUNT={}", count)?;
+        }
+        if let Some(until) = self.until {
+            write!(f, ";UNTIL={}", format_date_time(&until))?;
+        }
+        write_list(f, "BYMONTH", &self.by_month)?;
+        write_list(f, "BYMONTHDAY", &self.by_month_day)?;
+        write_list(f, "BYDAY", &self.by_day)?;
+        write_list(f, "BYSETPOS", &self.by_set_pos)?;
+        if !self.exdates.is_empty() {
+            let dates: Vec<String> = self.exdates.iter().map(format_date_time).collect();
+            write!(f, "\nEXDATE:{}", dates.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+// --- Calendar Helpers ---
+
+fn last_day_of_month(first: NaiveDate) -> NaiveDate {
+    first
+        .checked_add_months(Months::new(1))
+        .and_then(|next| next.pred_opt())
+        .unwrap_or(NaiveDate::MAX)
+}
+
+/// Resolves a `BYMONTHDAY` value, where negative values count from the end.
+fn resolve_month_day(year: i32, month: u32, day: i8) -> Option<NaiveDate> {
+    let first = NaiveDate::from_ymd_opt(year, month, 1)?;
+    if day > 0 {
+        return first.with_day(day as u32);
+    }
+    let from_end = u64::from(day.unsigned_abs()) - 1;
+    last_day_of_month(first)
+        .checked_sub_days(Days::new(from_end))
+        .filter(|date| date.month() == month)
+}
+
+/// All dates in `first..=last` matching a `BYDAY` entry, honouring its ordinal.
+fn weekday_num_dates(first: NaiveDate, last: NaiveDate, day: &WeekdayNum) -> Vec<NaiveDate> {
+    let offset =
+        (7 + day.weekday.num_days_from_monday() - first.weekday().num_days_from_monday()) % 7;
+    let matching: Vec<NaiveDate> = first
+        .iter_days()
+        .skip(offset as usize)
+        .step_by(7)
+        .take_while(|date| *date <= last)
+        .collect();
+    match day.ordinal {
+        None => matching,
+        Some(n) => pick(&matching, i32::from(n)).into_iter().collect(),
+    }
+}
+
+/// Picks the `pos`-th element (1-based, negative counts from the end).
+fn pick<T: Copy>(items: &[T], pos: i32) -> Option<T> {
+    let index = if pos > 0 {
+        usize::try_from(pos - 1).ok()?
+    } else {
+        items.len().checked_sub(usize::try_from(-pos).ok()?)?
+    };
+    items.get(index).copied()
+}
+
+// --- Parsing and Formatting Helpers ---
+
+fn parse_number<T: FromStr>(key: &str, value: &str) -> Result<T, ParseRuleError> {
+    value
+        .parse()
+        .or_else(|_| invalid(format!("{} has invalid value '{}'", key, value)))
+}
+
+fn parse_list<T: FromStr>(key: &str, value: &str) -> Result<Vec<T>, ParseRuleError> {
+    value.split(',').map(|v| parse_number(key, v)).collect()
+}
+
+fn write_list<T: fmt::Display>(f: &mut fmt::Formatter<'_>, key: &str, values: &[T]) -> fmt::Result {
+    if values.is_empty() {
+        return Ok(());
+    }
+    let values: Vec<String> = values.iter().map(ToString::to_string).collect();
+    write!(f, ";{}={}", key, values.join(","))
+}
+
+fn parse_weekday_num(value: &str) -> Result<WeekdayNum, ParseRuleError> {
+    if value.len() < 2 || !value.is_char_boundary(value.len() - 2) {
+        return invalid(format!("invalid BYDAY value '{}'", value));
+    }
+    let (ordinal, code) = value.split_at(value.len() - 2);
+    let weekday = match code {
+        "MO" => Weekday::Mon,
+        "TU" => Weekday::Tue,
+        "WE" => Weekday::Wed,
+        "TH" => Weekday::Thu,
+        "FR" => Weekday::Fri,
+        "SA" => Weekday::Sat,
+        "SU" => Weekday::Sun,
+        _ => return invalid(format!("invalid BYDAY value '{}'", value)),
+    };
+    let ordinal = match ordinal {
+        "" => None,
+        n => Some(parse_number("BYDAY", n.strip_prefix('+').unwrap_or(n))?),
+    };
+    Ok(WeekdayNum { ordinal, weekday })
+}
+
+fn weekday_code(weekday: Weekday) -> &'static str {
+    match weekday {
+        Weekday::Mon => "MO",
+        Weekday::Tue => "TU",
+        Weekday::Wed => "WE",
+        Weekday::Thu => "TH",
+        Weekday::Fri => "FR",
+        Weekday::Sat => "SA",
+        Weekday::Sun => "SU",
+    }
+}
+
+/// Parses `YYYYMMDD`, `YYYYMMDDTHHMMSS` or `YYYYMMDDTHHMMSSZ`. Values without
+/// an explicit zone are taken as UTC, which is how all dates are stored.
+fn parse_date_time(value: &str) -> Result<DateTime<Utc>, ParseRuleError> {
+    let trimmed = value.strip_suffix('Z').unwrap_or(value);
+    let naive = NaiveDateTime::parse_from_str(trimmed, "%Y%m%dT%H%M%S").or_else(|_| {
+        NaiveDate::parse_from_str(trimmed, "%Y%m%d").map(|d| d.and_time(Default::default()))
+    });
+    match naive {
+        Ok(naive) => Ok(naive.and_utc()),
+        Err(_) => invalid(format!("invalid date '{}'", value)),
+    }
+}
+
+fn format_date_time(value: &DateTime<Utc>) -> String {
+    value.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(value: &str) -> DateTime<Utc> {
+        parse_date_time(value).unwrap()
+    }
+
+    fn rule(value: &str) -> RRule {
+        value.parse().unwrap()
+    }
+
+    /// The first `n` occurrences after `start`, following the stored rule
+    /// from one to the next the way completing a todo does.
+    fn occurrences(rule: &RRule, start: DateTime<Utc>, n: usize) -> Vec<DateTime<Utc>> {
+        let (mut rule, mut start) = (rule.clone(), start);
+        let mut dates = Vec::new();
+        while dates.len() < n {
+            let Some((next, rest)) = rule.advance(start) else {
+                break;
+            };
+            dates.push(next);
+            (rule, start) = (rest, next);
+        }
+        dates
+    }
+
+    #[test]
+    fn display_round_trips() {
+        for value in [
+            "RRULE:FREQ=DAILY",
+            "RRULE:FREQ=WEEKLY;INTERVAL=2;BYDAY=MO,WE,FR",
+            "RRULE:FREQ=MONTHLY;COUNT=5;BYDAY=-1FR",
+            "RRULE:FREQ=MONTHLY;UNTIL=20261231T235959Z;BYMONTHDAY=1,-1",
+            "RRULE:FREQ=YEARLY;BYMONTH=3;BYDAY=2SU;BYSETPOS=1",
+            "RRULE:FREQ=DAILY\nEXDATE:20260102T090000Z,20260104T090000Z",
+        ] {
+            let parsed = rule(value);
+            assert_eq!(parsed.to_string(), value);
+            assert_eq!(rule(&parsed.to_string()), parsed);
+        }
+    }
+
+    #[test]
+    fn parses_bare_values_and_shorthands() {
+        assert_eq!(
+            rule("freq=weekly;byday=mo"),
+            rule("RRULE:FREQ=WEEKLY;BYDAY=MO")
+        );
+        assert_eq!(rule("FREQ=MONTHLY").shorthand(), Some("Monthly"));
+        assert_eq!(rule("FREQ=MONTHLY;INTERVAL=2").shorthand(), None);
+        assert_eq!(
+            RRule::from_shorthand("Yearly"),
+            Some(RRule::new(Frequency::Yearly))
+        );
+    }
+
+    #[test]
+    fn month_end_by_month_day() {
+        let start = at("20260131T090000Z");
+        assert_eq!(
+            occurrences(&rule("FREQ=MONTHLY;BYMONTHDAY=31"), start, 3),
+            [
+                at("20260331T090000Z"),
+                at("20260531T090000Z"),
+                at("20260731T090000Z")
+            ]
+        );
+        assert_eq!(
+            occurrences(&rule("FREQ=MONTHLY;BYMONTHDAY=-1"), start, 3),
+            [
+                at("20260228T090000Z"),
+                at("20260331T090000Z"),
+                at("20260430T090000Z")
+            ]
+        );
+        assert_eq!(
+            occurrences(
+                &rule("FREQ=MONTHLY;BYMONTHDAY=-1"),
+                at("20280131T090000Z"),
+                1
+            ),
+            [at("20280229T090000Z")]
+        );
+    }
+
+    #[test]
+    fn count_is_used_up() {
+        let start = at("20260101T090000Z");
+        let (next, rest) = rule("FREQ=DAILY;COUNT=3").advance(start).unwrap();
+        assert_eq!(next, at("20260102T090000Z"));
+        assert_eq!(rest.count, Some(2));
+        assert_eq!(
+            occurrences(&rule("FREQ=DAILY;COUNT=3"), start, 10),
+            [at("20260102T090000Z"), at("20260103T090000Z")]
+        );
+        assert_eq!(rule("FREQ=DAILY;COUNT=1").advance(start), None);
+    }
+
+    #[test]
+    fn until_ends_the_series() {
+        let start = at("20260101T090000Z");
+        // A bare date covers the whole day.
+        assert_eq!(
+            occurrences(&rule("FREQ=DAILY;UNTIL=20260103"), start, 10),
+            [at("20260102T090000Z"), at("20260103T090000Z")]
+        );
+        assert_eq!(
+            occurrences(&rule("FREQ=DAILY;UNTIL=20260103T080000Z"), start, 10),
+            [at("20260102T090000Z")]
+        );
+    }
+
+    #[test]
+    fn exdates_are_skipped_and_dropped() {
+        let rule = rule("FREQ=DAILY\nEXDATE:20260102T090000Z,20260105T090000Z");
+        let (next, rest) = rule.advance(at("20260101T090000Z")).unwrap();
+        assert_eq!(next, at("20260103T090000Z"));
+        assert_eq!(rest.exdates, [at("20260105T090000Z")]);
+    }
+
+    #[test]
+    fn by_day_and_set_pos() {
+        // 2026-01-01 is a Thursday.
+        let start = at("20260101T090000Z");
+        assert_eq!(
+            occurrences(&rule("FREQ=WEEKLY;BYDAY=MO,FR"), start, 3),
+            [
+                at("20260102T090000Z"),
+                at("20260105T090000Z"),
+                at("20260109T090000Z")
+            ]
+        );
+        assert_eq!(
+            occurrences(&rule("FREQ=MONTHLY;BYDAY=-1FR"), start, 2),
+            [at("20260130T090000Z"), at("20260227T090000Z")]
+        );
+        // The last weekday of the month.
+        assert_eq!(
+            occurrences(
+                &rule("FREQ=MONTHLY;BYDAY=MO,TU,WE,TH,FR;BYSETPOS=-1"),
+                start,
+                2
+            ),
+            [at("20260130T090000Z"), at("20260227T090000Z")]
+        );
+    }
+
+    #[test]
+    fn impossible_rules_end() {
+        let start = at("20260101T090000Z");
+        assert_eq!(
+            rule("FREQ=YEARLY;BYMONTH=2;BYMONTHDAY=30").advance(start),
+            None
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_rules() {
+        for value in [
+            "",
+            "INTERVAL=2",
+            "FREQ=HOURLY",
+            "FREQ=DAILY;INTERVAL=0",
+            "FREQ=DAILY;COUNT=0",
+            "FREQ=DAILY;COUNT=x",
+            "FREQ=DAILY;COUNT=2;UNTIL=20260101",
+            "FREQ=DAILY;UNTIL=2026-01-01",
+            "FREQ=DAILY;COUNT=1;COUNT=2",
+            "FREQ=DAILY;BYHOUR=9",
+            "FREQ=DAILY;BYDAY=1MO",
+            "FREQ=MONTHLY;BYDAY=0MO",
+            "FREQ=MONTHLY;BYDAY=XX",
+            "FREQ=WEEKLY;BYMONTHDAY=1",
+            "FREQ=MONTHLY;BYMONTHDAY=32",
+            "FREQ=YEARLY;BYMONTH=13",
+            "FREQ=MONTHLY;BYSETPOS=1",
+            "FREQ=DAILY;WKST=SU",
+            "FREQ=DAILY\nFREQ=WEEKLY",
+            "FREQ=DAILY\nEXDATE:tomorrow",
+        ] {
+            assert!(value.parse::<RRule>().is_err(), "{:?} was accepted", value);
+        }
+    }
+}
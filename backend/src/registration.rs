@@ -0,0 +1,289 @@
+//! Who may create an account: the registration mode, invite codes and the
+//! rules for usernames.
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use diesel::prelude::*;
+use diesel::sql_types::Text;
+use rocket::State;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use std::env;
+
+use crate::{AuthenticatedUser, CustomError, DbPool, admin, api, models, schema, security};
+
+type DbInvite = models::Invite;
+type DbInsertableInvite = models::InsertableInvite;
+type DbSetting = models::Setting;
+type ApiInvite = api::Invite;
+type NewInvite = api::NewInvite;
+type RegistrationMode = api::RegistrationMode;
+type RegistrationSettings = api::RegistrationSettings;
+type Role = api::Role;
+
+const MODE_SETTING: &str = "registration";
+
+define_sql_function!(fn lower(x: Text) -> Text);
+
+pub struct RegistrationConfig {
+    /// Used until an admin picks a mode.
+    pub default_mode: RegistrationMode,
+    pub username_min_length: usize,
+    pub username_max_length: usize,
+    /// Whether users who aren't admins may mint invite codes.
+    pub user_invites: bool,
+}
+
+impl RegistrationConfig {
+    /// Reads `REGISTRATION_MODE`, `USERNAME_MIN_LENGTH`,
+    /// `USERNAME_MAX_LENGTH` and `USER_INVITES`.
+    pub fn from_env() -> Self {
+        let read = |name: &str, default: usize| {
+            env::var(name)
+                .map(|v| {
+                    v.parse()
+                        .unwrap_or_else(|_| panic!("{} must be a number", name))
+                })
+                .unwrap_or(default)
+        };
+
+        RegistrationConfig {
+            default_mode: env::var("REGISTRATION_MODE")
+                .map(|v| {
+                    RegistrationMode::parse(&v).unwrap_or_else(|| {
+                        panic!("REGISTRATION_MODE must be open, invite-only or closed")
+                    })
+                })
+                .unwrap_or(RegistrationMode::Open),
+            username_min_length: read("USERNAME_MIN_LENGTH", 3),
+            username_max_length: read("USERNAME_MAX_LENGTH", 32),
+            user_invites: env::var("USER_INVITES")
+                .map(|v| v != "false" && v != "0")
+                .unwrap_or(true),
+        }
+    }
+
+    /// Checks a new username against the rules. The error is meant to be
+    /// shown to the user.
+    pub fn check_username(&self, username: &str) -> Result<(), String> {
+        let length = username.chars().count();
+        if length < self.username_min_length || length > self.username_max_length {
+            return Err(format!(
+                "The username must be between {} and {} characters long.",
+                self.username_min_length, self.username_max_length
+            ));
+        }
+        if !username.chars().all(is_username_char) {
+            return Err(String::from(
+                "The username may only contain letters, digits, dots, dashes and underscores.",
+            ));
+        }
+        Ok(())
+    }
+}
+
+pub fn is_username_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_')
+}
+
+/// Whether the username, compared without regard to case, belongs to someone.
+pub fn username_taken(conn: &mut SqliteConnection, username: &str) -> QueryResult<bool> {
+    Ok(schema::users::table
+        .filter(lower(schema::users::username).eq(username.to_ascii_lowercase()))
+        .select(schema::users::id)
+        .first::<i32>(conn)
+        .optional()?
+        .is_some())
+}
+
+pub fn mode(
+    conn: &mut SqliteConnection,
+    config: &RegistrationConfig,
+) -> QueryResult<RegistrationMode> {
+    let value = schema::settings::table
+        .find(MODE_SETTING)
+        .select(schema::settings::value)
+        .first::<String>(conn)
+        .optional()?;
+    Ok(value
+        .as_deref()
+        .and_then(RegistrationMode::parse)
+        .unwrap_or(config.default_mode))
+}
+
+pub fn set_mode(conn: &mut SqliteConnection, mode: RegistrationMode) -> QueryResult<()> {
+    let setting = DbSetting {
+        key: String::from(MODE_SETTING),
+        value: String::from(mode.as_str()),
+    };
+    diesel::insert_into(schema::settings::table)
+        .values(&setting)
+        .on_conflict(schema::settings::key)
+        .do_update()
+        .set(&setting)
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Decides whether a new account may be created right now, using up one
+/// use of `invite_code` if that's what lets it in. Returns the role the
+/// account gets. The first account is always let in, since that's how an
+/// instance gets its admin.
+pub fn admit(
+    conn: &mut SqliteConnection,
+    config: &RegistrationConfig,
+    invite_code: Option<&str>,
+) -> Result<Role, CustomError> {
+    let role = admin::initial_role(conn)?;
+    if role == Role::Admin {
+        return Ok(role);
+    }
+
+    match mode(conn, config)? {
+        RegistrationMode::Open => Ok(role),
+        RegistrationMode::InviteOnly => match invite_code {
+            Some(code) if redeem_invite(conn, code, Utc::now().naive_utc())? => Ok(role),
+            _ => Err(CustomError::InvalidInvite),
+        },
+        RegistrationMode::Closed => Err(CustomError::RegistrationClosed),
+    }
+}
+
+/// Counts one use of an invite, if it exists, hasn't expired and isn't used
+/// up. A single conditional update, so concurrent registrations can't push
+/// it past its limit.
+fn redeem_invite(conn: &mut SqliteConnection, code: &str, now: NaiveDateTime) -> QueryResult<bool> {
+    let redeemed = diesel::update(
+        schema::invites::table
+            .filter(schema::invites::code_hash.eq(security::hash_token(code.trim())))
+            .filter(
+                schema::invites::expires_at
+                    .is_null()
+                    .or(schema::invites::expires_at.gt(now)),
+            )
+            .filter(
+                schema::invites::max_uses
+                    .is_null()
+                    .or(schema::invites::max_uses.gt(schema::invites::uses.nullable())),
+            ),
+    )
+    .set(schema::invites::uses.eq(schema::invites::uses + 1))
+    .execute(conn)?;
+    Ok(redeemed == 1)
+}
+
+pub fn to_api_invite(invite: DbInvite, code: Option<String>) -> ApiInvite {
+    ApiInvite {
+        id: invite.id,
+        created_by: invite.created_by,
+        max_uses: invite.max_uses,
+        uses: invite.uses,
+        created_at: DateTime::<Utc>::from_naive_utc_and_offset(invite.created_at, Utc),
+        expires_at: invite
+            .expires_at
+            .map(|at| DateTime::<Utc>::from_naive_utc_and_offset(at, Utc)),
+        code,
+    }
+}
+
+// --- Routes ---
+
+/// Tells the registration page whether to ask for an invite code.
+#[get("/users/registration")]
+pub fn get_registration_mode(
+    pool: &State<DbPool>,
+    config: &State<RegistrationConfig>,
+) -> Result<Json<RegistrationSettings>, CustomError> {
+    let mut conn = pool.get().expect("Failed to get DB connection from pool");
+    Ok(Json(RegistrationSettings {
+        mode: mode(&mut conn, config)?,
+    }))
+}
+
+#[get("/invites")]
+pub fn list_invites(
+    pool: &State<DbPool>,
+    auth_user: AuthenticatedUser,
+) -> Result<Json<Vec<ApiInvite>>, CustomError> {
+    let mut conn = pool.get().expect("Failed to get DB connection from pool");
+
+    let invites = schema::invites::table
+        .filter(schema::invites::created_by.eq(auth_user.user_id))
+        .order(schema::invites::created_at.desc())
+        .load::<DbInvite>(&mut conn)?;
+
+    Ok(Json(
+        invites
+            .into_iter()
+            .map(|invite| to_api_invite(invite, None))
+            .collect(),
+    ))
+}
+
+/// Mints an invite code. The code is only shown in this response; only its
+/// hash is stored.
+#[post("/invites", data = "<invite_json>")]
+pub fn create_invite(
+    pool: &State<DbPool>,
+    config: &State<RegistrationConfig>,
+    auth_user: AuthenticatedUser,
+    invite_json: Json<NewInvite>,
+) -> Result<Json<ApiInvite>, CustomError> {
+    let new_invite = invite_json.into_inner();
+    if new_invite.max_uses.is_some_and(|max_uses| max_uses < 1) {
+        return Err(CustomError::InvalidInput(String::from(
+            "An invite must allow at least one use.",
+        )));
+    }
+    let now = Utc::now();
+    if new_invite.expires_at.is_some_and(|at| at <= now) {
+        return Err(CustomError::InvalidInput(String::from(
+            "The expiry date must be in the future.",
+        )));
+    }
+    let mut conn = pool.get().expect("Failed to get DB connection from pool");
+
+    conn.transaction(|conn| {
+        if !config.user_invites && admin::role_of(conn, auth_user.user_id)? != Role::Admin {
+            return Err(CustomError::Forbidden);
+        }
+
+        let code = security::generate_token(12);
+        let invite = diesel::insert_into(schema::invites::table)
+            .values(&DbInsertableInvite {
+                created_by: auth_user.user_id,
+                code_hash: security::hash_token(&code),
+                max_uses: new_invite.max_uses,
+                created_at: now.naive_utc(),
+                expires_at: new_invite.expires_at.map(|at| at.naive_utc()),
+            })
+            .get_result::<DbInvite>(conn)?;
+
+        Ok(Json(to_api_invite(invite, Some(code))))
+    })
+}
+
+/// Revokes an invite. Admins can revoke anyone's.
+#[delete("/invites/<id>")]
+pub fn delete_invite(
+    pool: &State<DbPool>,
+    auth_user: AuthenticatedUser,
+    id: i32,
+) -> Result<Status, CustomError> {
+    let mut conn = pool.get().expect("Failed to get DB connection from pool");
+
+    conn.transaction(|conn| {
+        let invite = schema::invites::table
+            .find(id)
+            .first::<DbInvite>(conn)
+            .optional()?
+            .ok_or(CustomError::NotFound)?;
+        if invite.created_by != auth_user.user_id
+            && admin::role_of(conn, auth_user.user_id)? != Role::Admin
+        {
+            return Err(CustomError::NotFound);
+        }
+
+        diesel::delete(schema::invites::table.find(invite.id)).execute(conn)?;
+        Ok(Status::NoContent)
+    })
+}
@@ -0,0 +1,316 @@
+use chrono::{DateTime, NaiveDateTime, Utc};
+use diesel::prelude::*;
+use rocket::fairing::{self, Fairing, Info, Kind};
+use rocket::tokio::{self, sync::Notify};
+use rocket::{Build, Orbit, Rocket, Shutdown};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::notifier::{DueReminder, Notifier};
+use crate::{DbPool, DbReminder, DbTodo, assignees, schema, tags, to_api_todo};
+
+/// How many reminders are claimed per pass; anything beyond is picked up
+/// straight away on the next one.
+const BATCH_SIZE: i64 = 100;
+
+/// How long a dispatcher holds the reminders it claimed. Ones it neither
+/// delivered nor released by then, because it stopped mid-send, are claimed
+/// again.
+const CLAIM_SECONDS: i64 = 5 * 60;
+
+/// How many times sending a reminder is tried before it's given up on.
+pub const MAX_ATTEMPTS: i32 = 8;
+
+/// How long the first retry of a failed reminder waits. Each one after that
+/// waits twice as long as the one before.
+const RETRY_SECONDS: i64 = 60;
+
+/// Handle for request handlers to wake the dispatcher after reminders change,
+/// so a reminder due sooner than the poll interval isn't fired late.
+#[derive(Clone, Default)]
+pub struct ReminderWakeup(Arc<Notify>);
+
+impl ReminderWakeup {
+    pub fn wake(&self) {
+        self.0.notify_one();
+    }
+}
+
+/// Fairing that fires stored reminders from inside the Rocket process.
+///
+/// Each pending reminder is claimed for a while by setting `claimed_until`
+/// before it is handed to the notifier, and only marked `delivered_at` once
+/// the notifier has sent it. A claim that runs out because the server
+/// stopped mid-send is taken up again, so reminders aren't lost across
+/// restarts, and reminders that came due while the server was down are
+/// caught up on the first pass after liftoff.
+pub struct ReminderDispatcher {
+    notifier: Arc<dyn Notifier>,
+    poll_interval: Duration,
+    wakeup: ReminderWakeup,
+}
+
+impl ReminderDispatcher {
+    pub fn new(notifier: Arc<dyn Notifier>, poll_interval: Duration) -> Self {
+        ReminderDispatcher {
+            notifier,
+            poll_interval,
+            wakeup: ReminderWakeup::default(),
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for ReminderDispatcher {
+    fn info(&self) -> Info {
+        Info {
+            name: "Reminder Dispatcher",
+            kind: Kind::Ignite | Kind::Liftoff,
+        }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> fairing::Result {
+        Ok(rocket.manage(self.wakeup.clone()))
+    }
+
+    async fn on_liftoff(&self, rocket: &Rocket<Orbit>) {
+        let Some(pool) = rocket.state::<DbPool>().cloned() else {
+            eprintln!("Missing database pool in Rocket state, reminders will not be sent!");
+            return;
+        };
+
+        tokio::spawn(run(
+            pool,
+            self.notifier.clone(),
+            self.poll_interval,
+            self.wakeup.clone(),
+            rocket.shutdown(),
+        ));
+    }
+}
+
+async fn run(
+    pool: DbPool,
+    notifier: Arc<dyn Notifier>,
+    poll_interval: Duration,
+    wakeup: ReminderWakeup,
+    shutdown: Shutdown,
+) {
+    loop {
+        let all_sent = dispatch_due(&pool, notifier.as_ref()).await;
+
+        // Sleep until the next pending reminder, but never longer than the poll
+        // interval, and back off fully if something failed so we don't spin.
+        let next_due = with_conn(&pool, next_pending).await.flatten();
+        let sleep_for = match next_due {
+            Some(at) if all_sent => (at - Utc::now().naive_utc())
+                .to_std()
+                .unwrap_or_default()
+                .min(poll_interval),
+            _ => poll_interval,
+        };
+
+        tokio::select! {
+            _ = shutdown.clone() => break,
+            _ = tokio::time::sleep(sleep_for) => {}
+            _ = wakeup.0.notified() => {}
+        }
+    }
+}
+
+/// Claims and sends every reminder that is due. Returns `false` if anything
+/// could not be sent; those reminders are released to be retried.
+async fn dispatch_due(pool: &DbPool, notifier: &dyn Notifier) -> bool {
+    let Some(claimed) = with_conn(pool, |conn| claim_due(conn, Utc::now().naive_utc())).await
+    else {
+        return false;
+    };
+
+    let mut all_sent = true;
+    for reminder in claimed {
+        let reminder_id = reminder.reminder_id;
+        match notifier.reminder_due(&reminder).await {
+            Ok(()) => {
+                with_conn(pool, move |conn| {
+                    mark_delivered(conn, reminder_id, Utc::now().naive_utc())
+                })
+                .await;
+            }
+            Err(e) => {
+                eprintln!("Failed to send reminder {}: {}", reminder_id, e);
+                all_sent = false;
+                with_conn(pool, move |conn| {
+                    retry_later(conn, reminder_id, Utc::now().naive_utc())
+                })
+                .await;
+            }
+        }
+    }
+    all_sent
+}
+
+fn claim_due(conn: &mut SqliteConnection, now: NaiveDateTime) -> QueryResult<Vec<DueReminder>> {
+    let claimed_until = now + chrono::Duration::seconds(CLAIM_SECONDS);
+    conn.transaction(|conn| {
+        let pending: Vec<(DbReminder, DbTodo)> = schema::reminders::table
+            .inner_join(schema::todos::table)
+            .filter(schema::reminders::delivered_at.is_null())
+            .filter(schema::reminders::reminder.le(now))
+            .filter(
+                schema::reminders::claimed_until
+                    .is_null()
+                    .or(schema::reminders::claimed_until.le(now)),
+            )
+            // Reminders of todos in the trash wait until they're restored.
+            .filter(schema::todos::deleted_at.is_null())
+            .order(schema::reminders::reminder.asc())
+            .limit(BATCH_SIZE)
+            .load(conn)?;
+
+        let mut claimed = Vec::new();
+        for (reminder, todo) in pending {
+            // Reminders for finished todos are retired without being sent.
+            if todo.completed {
+                mark_delivered(conn, reminder.id, now)?;
+                continue;
+            }
+
+            // The conditional update makes the claim safe even if another
+            // dispatcher is polling the same database.
+            let won = diesel::update(
+                schema::reminders::table
+                    .filter(schema::reminders::id.eq(reminder.id))
+                    .filter(schema::reminders::delivered_at.is_null())
+                    .filter(
+                        schema::reminders::claimed_until
+                            .is_null()
+                            .or(schema::reminders::claimed_until.le(now)),
+                    ),
+            )
+            .set(schema::reminders::claimed_until.eq(claimed_until))
+            .execute(conn)?
+                == 1;
+            if !won {
+                continue;
+            }
+
+            let all_reminders = DbReminder::belonging_to(&todo).load::<DbReminder>(conn)?;
+            let tags = tags::names_of(conn, &todo)?;
+            let assignees = assignees::ids_of(conn, &todo)?;
+            claimed.push(DueReminder {
+                reminder_id: reminder.id,
+                user_id: todo.user_id,
+                remind_at: DateTime::<Utc>::from_naive_utc_and_offset(reminder.reminder, Utc),
+                todo: to_api_todo(todo, all_reminders, tags, assignees),
+            });
+        }
+        Ok(claimed)
+    })
+}
+
+fn mark_delivered(
+    conn: &mut SqliteConnection,
+    reminder_id: i32,
+    now: NaiveDateTime,
+) -> QueryResult<()> {
+    diesel::update(schema::reminders::table.filter(schema::reminders::id.eq(reminder_id)))
+        .set((
+            schema::reminders::delivered_at.eq(now),
+            schema::reminders::claimed_until.eq(None::<NaiveDateTime>),
+        ))
+        .execute(conn)
+        .map(|_| ())
+}
+
+/// How long to wait before trying a reminder again after it failed
+/// `attempts` times.
+fn retry_delay(attempts: i32) -> chrono::Duration {
+    chrono::Duration::seconds(RETRY_SECONDS << (attempts - 1).clamp(0, 30))
+}
+
+/// Keeps a reminder that failed to send claimed until it's due to be tried
+/// again, or gives up on it once it has failed `MAX_ATTEMPTS` times.
+fn retry_later(
+    conn: &mut SqliteConnection,
+    reminder_id: i32,
+    now: NaiveDateTime,
+) -> QueryResult<()> {
+    let attempts = diesel::update(schema::reminders::table.find(reminder_id))
+        .set(schema::reminders::attempts.eq(schema::reminders::attempts + 1))
+        .returning(schema::reminders::attempts)
+        .get_result::<i32>(conn)
+        .optional()?;
+    match attempts {
+        None => Ok(()),
+        Some(attempts) if attempts >= MAX_ATTEMPTS => {
+            eprintln!(
+                "Giving up on reminder {} after {} attempts",
+                reminder_id, attempts
+            );
+            mark_delivered(conn, reminder_id, now)
+        }
+        Some(attempts) => diesel::update(schema::reminders::table.find(reminder_id))
+            .set(schema::reminders::claimed_until.eq(now + retry_delay(attempts)))
+            .execute(conn)
+            .map(|_| ()),
+    }
+}
+
+/// When the next reminder can be sent: when it's due, or for one that's
+/// claimed, when the claim runs out.
+fn next_pending(conn: &mut SqliteConnection) -> QueryResult<Option<NaiveDateTime>> {
+    let pending = schema::reminders::table
+        .inner_join(schema::todos::table)
+        .filter(schema::reminders::delivered_at.is_null())
+        .filter(schema::todos::deleted_at.is_null());
+    let next_due = pending
+        .filter(schema::reminders::claimed_until.is_null())
+        .select(diesel::dsl::min(schema::reminders::reminder))
+        .first::<Option<NaiveDateTime>>(conn)?;
+    let next_expiry = pending
+        .filter(schema::reminders::claimed_until.is_not_null())
+        .select(diesel::dsl::min(schema::reminders::claimed_until))
+        .first::<Option<NaiveDateTime>>(conn)?;
+    Ok(next_due.into_iter().chain(next_expiry).min())
+}
+
+/// Runs blocking Diesel work off the async executor for background tasks.
+/// Failures are logged and turned into `None`, since there is no request to
+/// report them to.
+pub async fn with_conn<T, F>(pool: &DbPool, f: F) -> Option<T>
+where
+    T: Send + 'static,
+    F: FnOnce(&mut SqliteConnection) -> QueryResult<T> + Send + 'static,
+{
+    let pool = pool.clone();
+    let result = tokio::task::spawn_blocking(move || {
+        let mut conn = pool.get().map_err(|e| e.to_string())?;
+        f(&mut conn).map_err(|e| e.to_string())
+    })
+    .await;
+
+    match result {
+        Ok(Ok(value)) => Some(value),
+        Ok(Err(e)) => {
+            eprintln!("Background database error: {}", e);
+            None
+        }
+        Err(e) => {
+            eprintln!("Background database task failed: {}", e);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retries_back_off_exponentially() {
+        assert_eq!(retry_delay(1), chrono::Duration::minutes(1));
+        assert_eq!(retry_delay(2), chrono::Duration::minutes(2));
+        assert_eq!(retry_delay(3), chrono::Duration::minutes(4));
+        assert_eq!(retry_delay(MAX_ATTEMPTS - 1), chrono::Duration::minutes(64));
+    }
+}
@@ -1,10 +1,174 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    api_tokens (id) {
+        id -> Integer,
+        user_id -> Integer,
+        name -> Text,
+        token_hash -> Text,
+        scopes -> Text,
+        created_at -> Timestamp,
+        expires_at -> Nullable<Timestamp>,
+        last_used_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    comment_mentions (comment_id, user_id) {
+        comment_id -> Integer,
+        user_id -> Integer,
+    }
+}
+
+diesel::table! {
+    comments (id) {
+        id -> Integer,
+        todo_id -> Integer,
+        user_id -> Integer,
+        body -> Text,
+        created_at -> Timestamp,
+        edited_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    completions (id) {
+        id -> Integer,
+        todo_id -> Integer,
+        completed_at -> Timestamp,
+        due -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    invites (id) {
+        id -> Integer,
+        created_by -> Integer,
+        code_hash -> Text,
+        max_uses -> Nullable<Integer>,
+        uses -> Integer,
+        created_at -> Timestamp,
+        expires_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    project_members (project_id, user_id) {
+        project_id -> Integer,
+        user_id -> Integer,
+        role -> Text,
+        invited_by -> Integer,
+        invited_at -> Timestamp,
+        accepted_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    projects (id) {
+        id -> Integer,
+        user_id -> Integer,
+        name -> Text,
+        color -> Nullable<Text>,
+        icon -> Nullable<Text>,
+        sort_order -> Integer,
+        archived -> Bool,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    rate_limits (key) {
+        key -> Text,
+        tokens -> Double,
+        updated_at -> Timestamp,
+        failures -> Integer,
+        locked_until -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    recovery_codes (id) {
+        id -> Integer,
+        user_id -> Integer,
+        code_hash -> Text,
+        used_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    refresh_tokens (id) {
+        id -> Integer,
+        session_id -> Integer,
+        token_hash -> Text,
+        created_at -> Timestamp,
+        expires_at -> Timestamp,
+        used_at -> Nullable<Timestamp>,
+    }
+}
+
 diesel::table! {
     reminders (id) {
         id -> Integer,
         todo_id -> Integer,
         reminder -> Timestamp,
+        delivered_at -> Nullable<Timestamp>,
+        claimed_until -> Nullable<Timestamp>,
+        attempts -> Integer,
+    }
+}
+
+diesel::table! {
+    sessions (id) {
+        id -> Integer,
+        user_id -> Integer,
+        created_at -> Timestamp,
+        revoked_at -> Nullable<Timestamp>,
+        user_agent -> Nullable<Text>,
+        ip -> Nullable<Text>,
+        last_seen_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    settings (key) {
+        key -> Text,
+        value -> Text,
+    }
+}
+
+diesel::table! {
+    tags (id) {
+        id -> Integer,
+        user_id -> Integer,
+        name -> Text,
+    }
+}
+
+diesel::table! {
+    todo_assignees (todo_id, user_id) {
+        todo_id -> Integer,
+        user_id -> Integer,
+        assigned_by -> Integer,
+        assigned_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    todo_history (id) {
+        id -> Integer,
+        todo_id -> Integer,
+        user_id -> Nullable<Integer>,
+        field -> Text,
+        old_value -> Nullable<Text>,
+        new_value -> Nullable<Text>,
+        changed_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    todo_tags (todo_id, tag_id) {
+        todo_id -> Integer,
+        tag_id -> Integer,
     }
 }
 
@@ -17,6 +181,21 @@ diesel::table! {
         due -> Nullable<Timestamp>,
         repeat -> Text,
         completed -> Bool,
+        repeat_from -> Text,
+        project_id -> Nullable<Integer>,
+        parent_id -> Nullable<Integer>,
+        auto_complete -> Bool,
+        priority -> Text,
+        status -> Text,
+        rank -> Text,
+        deleted_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    username_renames (user_id) {
+        user_id -> Integer,
+        old_username -> Text,
     }
 }
 
@@ -24,15 +203,100 @@ diesel::table! {
     users (id) {
         id -> Integer,
         username -> Text,
-        password -> Text,
+        password -> Nullable<Text>,
+        email -> Nullable<Text>,
+        email_verified -> Bool,
+        email_token_hash -> Nullable<Text>,
+        email_token_expires_at -> Nullable<Timestamp>,
+        email_reminders -> Bool,
+        daily_digest -> Bool,
+        last_digest_on -> Nullable<Date>,
+        totp_secret -> Nullable<Text>,
+        totp_enabled -> Bool,
+        totp_last_step -> Nullable<BigInt>,
+        password_reset_hash -> Nullable<Text>,
+        password_reset_expires_at -> Nullable<Timestamp>,
+        oidc_issuer -> Nullable<Text>,
+        oidc_subject -> Nullable<Text>,
+        role -> Text,
+        disabled_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    webhook_deliveries (id) {
+        id -> Integer,
+        webhook_id -> Integer,
+        delivery_id -> Text,
+        event -> Text,
+        attempt -> Integer,
+        status_code -> Nullable<Integer>,
+        error -> Nullable<Text>,
+        succeeded -> Bool,
+        attempted_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    webhooks (id) {
+        id -> Integer,
+        user_id -> Integer,
+        url -> Text,
+        secret -> Text,
+        events -> Text,
+        active -> Bool,
+        created_at -> Timestamp,
     }
 }
 
+diesel::joinable!(api_tokens -> users (user_id));
+diesel::joinable!(comment_mentions -> comments (comment_id));
+diesel::joinable!(comment_mentions -> users (user_id));
+diesel::joinable!(comments -> todos (todo_id));
+diesel::joinable!(comments -> users (user_id));
+diesel::joinable!(completions -> todos (todo_id));
+diesel::joinable!(invites -> users (created_by));
+diesel::joinable!(project_members -> projects (project_id));
+diesel::joinable!(project_members -> users (user_id));
+diesel::joinable!(projects -> users (user_id));
+diesel::joinable!(recovery_codes -> users (user_id));
+diesel::joinable!(refresh_tokens -> sessions (session_id));
 diesel::joinable!(reminders -> todos (todo_id));
+diesel::joinable!(sessions -> users (user_id));
+diesel::joinable!(tags -> users (user_id));
+diesel::joinable!(todo_assignees -> todos (todo_id));
+diesel::joinable!(todo_assignees -> users (user_id));
+diesel::joinable!(todo_history -> todos (todo_id));
+diesel::joinable!(todo_history -> users (user_id));
+diesel::joinable!(todo_tags -> tags (tag_id));
+diesel::joinable!(todo_tags -> todos (todo_id));
+diesel::joinable!(todos -> projects (project_id));
 diesel::joinable!(todos -> users (user_id));
+diesel::joinable!(username_renames -> users (user_id));
+diesel::joinable!(webhook_deliveries -> webhooks (webhook_id));
+diesel::joinable!(webhooks -> users (user_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
+    api_tokens,
+    comment_mentions,
+    comments,
+    completions,
+    invites,
+    project_members,
+    projects,
+    rate_limits,
+    recovery_codes,
+    refresh_tokens,
     reminders,
+    sessions,
+    settings,
+    tags,
+    todo_assignees,
+    todo_history,
+    todo_tags,
     todos,
+    username_renames,
     users,
+    webhook_deliveries,
+    webhooks,
 );
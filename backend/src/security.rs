@@ -1,23 +1,200 @@
 use argon2::{
+    Algorithm, Argon2, Params, PasswordHash, PasswordVerifier, Version,
     password_hash::{
-        rand_core::OsRng,
-        PasswordHasher, SaltString
+        PasswordHasher, SaltString,
+        rand_core::{OsRng, RngCore},
     },
-    Argon2, PasswordHash, PasswordVerifier
 };
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::env;
+use std::fs;
 
-pub fn hash_password(password: &String) -> Result<String, argon2::password_hash::Error> {
-    let salt = SaltString::generate(&mut OsRng);
+/// Passwords longer than this are rejected outright, so hashing can't be used
+/// to tie up the server.
+const MAX_PASSWORD_LEN: usize = 1024;
+
+/// Rules new passwords have to follow, and the Argon2 parameters they are
+/// hashed with.
+pub struct PasswordConfig {
+    pub min_length: usize,
+    /// Uppercase hex SHA-1 digests of known breached passwords.
+    pub breached: HashSet<String>,
+    pub argon2: Params,
+}
+
+impl PasswordConfig {
+    /// Reads `PASSWORD_MIN_LENGTH`, `PASSWORD_BREACHED_LIST` and
+    /// `ARGON2_MEMORY_KIB`, `ARGON2_ITERATIONS` and `ARGON2_PARALLELISM`.
+    ///
+    /// The breached list is a file with one entry per line, either a plain
+    /// password or its SHA-1 digest in hex, optionally followed by `:count` as
+    /// in the Have I Been Pwned downloads.
+    pub fn from_env() -> Self {
+        let read = |name: &str, default: u32| {
+            env::var(name)
+                .map(|v| {
+                    v.parse()
+                        .unwrap_or_else(|_| panic!("{} must be a number", name))
+                })
+                .unwrap_or(default)
+        };
+
+        let breached = match env::var("PASSWORD_BREACHED_LIST") {
+            Ok(path) => {
+                let list = fs::read_to_string(&path).unwrap_or_else(|e| {
+                    panic!("Failed to read PASSWORD_BREACHED_LIST {}: {}", path, e)
+                });
+                list.lines().filter_map(breached_entry).collect()
+            }
+            Err(_) => HashSet::new(),
+        };
+
+        let argon2 = Params::new(
+            read("ARGON2_MEMORY_KIB", Params::DEFAULT_M_COST),
+            read("ARGON2_ITERATIONS", Params::DEFAULT_T_COST),
+            read("ARGON2_PARALLELISM", Params::DEFAULT_P_COST),
+            None,
+        )
+        .expect("ARGON2_MEMORY_KIB, ARGON2_ITERATIONS or ARGON2_PARALLELISM is out of range");
+
+        PasswordConfig {
+            min_length: read("PASSWORD_MIN_LENGTH", 8) as usize,
+            breached,
+            argon2,
+        }
+    }
 
-    let argon2 = Argon2::default();
+    /// Checks a new password for `username` against the policy. The error is
+    /// meant to be shown to the user.
+    pub fn check(&self, password: &str, username: &str) -> Result<(), String> {
+        let length = password.chars().count();
+        if length < self.min_length {
+            return Err(format!(
+                "The password must be at least {} characters long.",
+                self.min_length
+            ));
+        }
+        if length > MAX_PASSWORD_LEN {
+            return Err(format!(
+                "The password must be at most {} characters long.",
+                MAX_PASSWORD_LEN
+            ));
+        }
+        if resembles_username(password, username) {
+            return Err(String::from(
+                "The password must not be based on the username.",
+            ));
+        }
+        if self.breached.contains(&sha1_hex(password)) {
+            return Err(String::from(
+                "This password has appeared in a data breach. Please choose a different one.",
+            ));
+        }
+        Ok(())
+    }
 
-    let password_hash = argon2.hash_password(password.as_bytes(), &salt)?;
+    fn hasher(&self) -> Argon2<'static> {
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, self.argon2.clone())
+    }
+}
+
+fn breached_entry(line: &str) -> Option<String> {
+    let line = line.trim();
+    if line.is_empty() {
+        return None;
+    }
+    let digest = line.split(':').next().unwrap_or(line);
+    if digest.len() == 40 && digest.bytes().all(|b| b.is_ascii_hexdigit()) {
+        Some(digest.to_ascii_uppercase())
+    } else {
+        Some(sha1_hex(line))
+    }
+}
+
+fn sha1_hex(value: &str) -> String {
+    hex::encode_upper(Sha1::digest(value.as_bytes()))
+}
+
+/// Whether the password is the username in disguise: containing it, being
+/// contained in it, or spelling it backwards, ignoring case and punctuation.
+fn resembles_username(password: &str, username: &str) -> bool {
+    let normalize = |s: &str| -> String {
+        s.chars()
+            .filter(|c| c.is_alphanumeric())
+            .flat_map(char::to_lowercase)
+            .collect()
+    };
+    let password = normalize(password);
+    let username = normalize(username);
+    if username.len() < 3 || password.is_empty() {
+        return false;
+    }
+    let reversed: String = username.chars().rev().collect();
+
+    password.contains(&username) || username.contains(&password) || password.contains(&reversed)
+}
+
+pub fn hash_password(
+    password: &str,
+    config: &PasswordConfig,
+) -> Result<String, argon2::password_hash::Error> {
+    let salt = SaltString::generate(&mut OsRng);
+
+    let password_hash = config.hasher().hash_password(password.as_bytes(), &salt)?;
 
     Ok(password_hash.to_string())
 }
 
-pub fn verify_password(password: &String, db_password: &String) -> Result<bool, argon2::password_hash::Error> {
+pub fn verify_password(
+    password: &str,
+    db_password: &str,
+) -> Result<bool, argon2::password_hash::Error> {
     let parsed_hash = PasswordHash::new(db_password)?;
 
-    Ok(Argon2::default().verify_password(password.as_bytes(), &parsed_hash).is_ok())
-}
\ No newline at end of file
+    // The algorithm and parameters are taken from the stored hash.
+    Ok(Argon2::default()
+        .verify_password(password.as_bytes(), &parsed_hash)
+        .is_ok())
+}
+
+/// Whether a stored hash was made with a weaker algorithm or cheaper
+/// parameters than the current configuration, and should be redone the next
+/// time the plain password is at hand.
+pub fn needs_rehash(db_password: &str, config: &PasswordConfig) -> bool {
+    let Ok(parsed_hash) = PasswordHash::new(db_password) else {
+        return false;
+    };
+    if parsed_hash.algorithm != Algorithm::Argon2id.ident()
+        || parsed_hash.version != Some(Version::V0x13.into())
+    {
+        return true;
+    }
+    match Params::try_from(&parsed_hash) {
+        Ok(params) => {
+            params.m_cost() < config.argon2.m_cost()
+                || params.t_cost() < config.argon2.t_cost()
+                || params.p_cost() < config.argon2.p_cost()
+        }
+        Err(_) => true,
+    }
+}
+
+/// Returns `len` bytes from the operating system's secure random generator.
+pub fn random_bytes(len: usize) -> Vec<u8> {
+    let mut buf = vec![0u8; len];
+    OsRng.fill_bytes(&mut buf);
+    buf
+}
+
+/// Returns a random hex string of `bytes` bytes, for secrets and opaque tokens.
+pub fn generate_token(bytes: usize) -> String {
+    hex::encode(random_bytes(bytes))
+}
+
+/// Hashes an opaque token for storage. Tokens are long and random, so a fast
+/// hash is enough; we only need a leaked table not to reveal usable tokens.
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
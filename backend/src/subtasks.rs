@@ -0,0 +1,375 @@
+//! Subtasks: a todo can be nested under another of the user's todos, to any
+//! depth. A subtask is always in the same project, or the same Inbox, as its
+//! parent. Parents report how far along their subtasks are and can complete
+//! themselves once all of them are done.
+
+use chrono::Utc;
+use diesel::prelude::*;
+use std::collections::{HashMap, HashSet};
+
+use crate::access::{self, Action};
+use crate::{CustomError, DbTodo, api, assignees, history, load_api_todo, models, schema};
+
+type DbInsertableCompletion = models::InsertableCompletion;
+type ApiTodo = api::Todo;
+type Progress = api::Progress;
+type RepeatRule = api::RepeatRule;
+type TodoStatus = api::TodoStatus;
+
+/// Checks that a todo in `project_id` can be put under `parent_id`.
+/// `todo_id` is `None` for a todo that's being created. The user has to be
+/// allowed to edit the parent, which has to be in the same project and can't
+/// be the todo itself or one of its subtasks.
+pub fn check_parent(
+    conn: &mut SqliteConnection,
+    user_id: i32,
+    todo_id: Option<i32>,
+    project_id: Option<i32>,
+    parent_id: Option<i32>,
+) -> Result<(), CustomError> {
+    let Some(parent_id) = parent_id else {
+        return Ok(());
+    };
+    let parent = match access::authorize(conn, user_id, parent_id, Action::Edit) {
+        Err(CustomError::NotFound) => {
+            return Err(CustomError::InvalidInput(String::from(
+                "The parent todo doesn't exist.",
+            )));
+        }
+        result => result?,
+    };
+    if parent.project_id != project_id {
+        return Err(CustomError::InvalidInput(String::from(
+            "A subtask has to be in the same project as its parent.",
+        )));
+    }
+
+    let mut seen = HashSet::new();
+    let mut next = Some(parent_id);
+    while let Some(id) = next {
+        if Some(id) == todo_id {
+            return Err(CustomError::InvalidInput(String::from(
+                "A todo can't be nested under itself or one of its subtasks.",
+            )));
+        }
+        if !seen.insert(id) {
+            break;
+        }
+        next = schema::todos::table
+            .find(id)
+            .select(schema::todos::parent_id)
+            .first::<Option<i32>>(conn)
+            .optional()?
+            .flatten();
+    }
+    Ok(())
+}
+
+/// The project a todo is in, and for todos in an Inbox whose Inbox it is.
+fn place(project_id: Option<i32>, user_id: i32) -> (Option<i32>, Option<i32>) {
+    (project_id, project_id.is_none().then_some(user_id))
+}
+
+fn same_place(a: &DbTodo, b: &DbTodo) -> bool {
+    place(a.project_id, a.user_id) == place(b.project_id, b.user_id)
+}
+
+/// A todo's subtasks at every depth, in the trash or not, leaving out the
+/// ones `keep` turns down together with everything under them.
+fn subtree(
+    conn: &mut SqliteConnection,
+    todo_id: i32,
+    mut keep: impl FnMut(&mut SqliteConnection, &DbTodo) -> QueryResult<bool>,
+) -> QueryResult<Vec<DbTodo>> {
+    let mut seen = vec![todo_id];
+    let mut frontier = vec![todo_id];
+    let mut found = Vec::new();
+    while !frontier.is_empty() {
+        let children = schema::todos::table
+            .filter(schema::todos::parent_id.eq_any(&frontier))
+            .filter(schema::todos::id.ne_all(&seen))
+            .load::<DbTodo>(conn)?;
+        frontier.clear();
+        for child in children {
+            seen.push(child.id);
+            if keep(conn, &child)? {
+                frontier.push(child.id);
+                found.push(child);
+            }
+        }
+    }
+    Ok(found)
+}
+
+/// The ids of a todo's subtasks at every depth.
+pub fn descendants(conn: &mut SqliteConnection, todo_id: i32) -> QueryResult<Vec<i32>> {
+    Ok(subtree(conn, todo_id, |_, _| Ok(true))?
+        .into_iter()
+        .map(|todo| todo.id)
+        .collect())
+}
+
+/// The ids of a todo's subtasks at every depth that the user may edit. A
+/// subtask they can't edit is left out together with everything under it.
+pub fn editable_descendants(
+    conn: &mut SqliteConnection,
+    user_id: i32,
+    todo_id: i32,
+) -> QueryResult<Vec<i32>> {
+    Ok(subtree(conn, todo_id, |conn, todo| {
+        access::allows(conn, user_id, todo, Action::Edit)
+    })?
+    .into_iter()
+    .map(|todo| todo.id)
+    .collect())
+}
+
+/// Moves the subtasks of a todo that's moving to `project_id`, or to the
+/// Inbox for `None`, along with it. The user has to be allowed to edit every
+/// one of them, and only their own subtasks can go to their Inbox. Returns
+/// the subtasks that moved.
+pub fn move_subtree(
+    conn: &mut SqliteConnection,
+    user_id: i32,
+    todo: &DbTodo,
+    project_id: Option<i32>,
+) -> Result<Vec<DbTodo>, CustomError> {
+    let subtasks = subtree(conn, todo.id, |_, subtask| Ok(same_place(todo, subtask)))?;
+    for subtask in &subtasks {
+        if !access::allows(conn, user_id, subtask, Action::Edit)? {
+            return Err(CustomError::Forbidden);
+        }
+        if project_id.is_none() && subtask.user_id != user_id {
+            return Err(CustomError::InvalidInput(String::from(
+                "Subtasks someone else created can't be moved to your Inbox.",
+            )));
+        }
+    }
+
+    let ids: Vec<i32> = subtasks.iter().map(|subtask| subtask.id).collect();
+    let moved = diesel::update(schema::todos::table.filter(schema::todos::id.eq_any(&ids)))
+        .set(schema::todos::project_id.eq(project_id))
+        .get_results::<DbTodo>(conn)?;
+    for subtask in &moved {
+        history::record(
+            conn,
+            subtask.id,
+            user_id,
+            "project_id",
+            &todo.project_id,
+            &project_id,
+        )?;
+        assignees::unassign_hidden(conn, user_id, subtask)?;
+    }
+    Ok(moved)
+}
+
+/// Moves todos whose parent is no longer in the same project or Inbox as
+/// they are to the top level, for when todos were moved without their
+/// subtrees. Looks at the given todos and at their subtasks. Returns each
+/// todo it moved with the parent it had.
+pub fn detach_strays(conn: &mut SqliteConnection, ids: &[i32]) -> QueryResult<Vec<(i32, i32)>> {
+    let todos = schema::todos::table
+        .filter(
+            schema::todos::id
+                .eq_any(ids)
+                .or(schema::todos::parent_id.eq_any(ids)),
+        )
+        .filter(schema::todos::parent_id.is_not_null())
+        .load::<DbTodo>(conn)?;
+
+    let mut detached = Vec::new();
+    for todo in todos {
+        let Some(parent_id) = todo.parent_id else {
+            continue;
+        };
+        let parent = schema::todos::table
+            .find(parent_id)
+            .first::<DbTodo>(conn)
+            .optional()?;
+        if parent.is_some_and(|parent| same_place(&parent, &todo)) {
+            continue;
+        }
+        diesel::update(schema::todos::table.find(todo.id))
+            .set(schema::todos::parent_id.eq(None::<i32>))
+            .execute(conn)?;
+        detached.push((todo.id, parent_id));
+    }
+    Ok(detached)
+}
+
+/// The progress of a single todo, or `None` if it has no subtasks. Only
+/// subtasks in the same project or Inbox count, since those are the ones
+/// everyone who can see the todo can see.
+pub fn progress_of(conn: &mut SqliteConnection, todo_id: i32) -> QueryResult<Option<Progress>> {
+    let Some(todo) = schema::todos::table
+        .find(todo_id)
+        .first::<DbTodo>(conn)
+        .optional()?
+    else {
+        return Ok(None);
+    };
+    let subtasks: Vec<bool> = subtree(conn, todo_id, |_, subtask| Ok(same_place(&todo, subtask)))?
+        .into_iter()
+        .filter(|subtask| subtask.deleted_at.is_none())
+        .map(|subtask| subtask.completed)
+        .collect();
+    if subtasks.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(Progress {
+        completed: subtasks.iter().filter(|&&completed| completed).count(),
+        total: subtasks.len(),
+    }))
+}
+
+/// The progress of each todo the user can see that has subtasks, counting
+/// the same subtasks as `progress_of`.
+pub fn progress(conn: &mut SqliteConnection, user_id: i32) -> QueryResult<HashMap<i32, Progress>> {
+    let rows = access::visible_todos(conn, user_id)?
+        .select((
+            schema::todos::id,
+            schema::todos::parent_id,
+            schema::todos::completed,
+            schema::todos::project_id,
+            schema::todos::user_id,
+        ))
+        .load::<(i32, Option<i32>, bool, Option<i32>, i32)>(conn)?;
+
+    let places: HashMap<i32, _> = rows
+        .iter()
+        .map(|&(id, _, _, project_id, owner_id)| (id, place(project_id, owner_id)))
+        .collect();
+    let mut children: HashMap<i32, Vec<(i32, bool)>> = HashMap::new();
+    for &(id, parent_id, completed, project_id, owner_id) in &rows {
+        let Some(parent_id) = parent_id else {
+            continue;
+        };
+        if places.get(&parent_id) == Some(&place(project_id, owner_id)) {
+            children.entry(parent_id).or_default().push((id, completed));
+        }
+    }
+
+    let mut progress = HashMap::new();
+    for &(id, ..) in &rows {
+        tally(id, &children, &mut progress, &mut HashSet::new());
+    }
+    progress.retain(|_, progress: &mut Progress| progress.total > 0);
+    Ok(progress)
+}
+
+fn tally(
+    id: i32,
+    children: &HashMap<i32, Vec<(i32, bool)>>,
+    memo: &mut HashMap<i32, Progress>,
+    visiting: &mut HashSet<i32>,
+) -> Progress {
+    if let Some(progress) = memo.get(&id) {
+        return *progress;
+    }
+    let mut progress = Progress {
+        completed: 0,
+        total: 0,
+    };
+    // Hand-edited rows could form a loop; a todo met again on the way down
+    // counts as having no subtasks.
+    if visiting.insert(id) {
+        for &(child, completed) in children.get(&id).into_iter().flatten() {
+            let below = tally(child, children, memo, visiting);
+            progress.total += 1 + below.total;
+            progress.completed += usize::from(completed) + below.completed;
+        }
+        memo.insert(id, progress);
+    }
+    progress
+}
+
+/// Completes the ancestors of a todo that was just completed, for as long as
+/// each one asked for it and has no open subtasks left. Repeating todos are
+/// left alone, since their subtasks don't come back with them, and so are
+/// ancestors `user_id`, whoever completed the subtask, may not edit. Returns
+/// the todos it completed.
+pub fn complete_ancestors(
+    conn: &mut SqliteConnection,
+    user_id: i32,
+    parent_id: Option<i32>,
+) -> Result<Vec<ApiTodo>, CustomError> {
+    let mut completed = Vec::new();
+    let mut next = parent_id;
+    while let Some(id) = next {
+        let parent = match access::authorize(conn, user_id, id, Action::Edit) {
+            Ok(parent) => parent,
+            Err(CustomError::NotFound | CustomError::Forbidden) => break,
+            Err(e) => return Err(e),
+        };
+        let repeats = parent
+            .repeat
+            .parse::<RepeatRule>()
+            .is_ok_and(|repeat| repeat.rrule().is_some());
+        if parent.completed || !parent.auto_complete || repeats {
+            break;
+        }
+        let open = schema::todos::table
+            .filter(schema::todos::parent_id.eq(id))
+            .filter(schema::todos::completed.eq(false))
+            .filter(schema::todos::deleted_at.is_null())
+            .count()
+            .get_result::<i64>(conn)?;
+        if open > 0 {
+            break;
+        }
+        let before_status = parent.status;
+
+        diesel::insert_into(schema::completions::table)
+            .values(&DbInsertableCompletion {
+                todo_id: id,
+                completed_at: Utc::now().naive_utc(),
+                due: parent.due,
+            })
+            .execute(conn)?;
+        let parent = diesel::update(schema::todos::table.find(id))
+            .set((
+                schema::todos::completed.eq(true),
+                schema::todos::status.eq(TodoStatus::Done.as_str()),
+            ))
+            .get_result::<DbTodo>(conn)?;
+        history::record(conn, id, user_id, "completed", &false, &true)?;
+        history::record(
+            conn,
+            id,
+            user_id,
+            "status",
+            &TodoStatus::parse(&before_status).unwrap_or_default(),
+            &TodoStatus::Done,
+        )?;
+
+        next = parent.parent_id;
+        completed.push(load_api_todo(conn, parent)?);
+    }
+    Ok(completed)
+}
+
+/// Arranges a list of todos into trees. Todos whose parent isn't in the list
+/// become roots.
+pub fn nest(todos: Vec<ApiTodo>) -> Vec<ApiTodo> {
+    let ids: HashSet<i32> = todos.iter().map(|todo| todo.id).collect();
+    let mut children: HashMap<i32, Vec<ApiTodo>> = HashMap::new();
+    let mut roots = Vec::new();
+    for todo in todos {
+        match todo.parent_id.filter(|parent_id| ids.contains(parent_id)) {
+            Some(parent_id) => children.entry(parent_id).or_default().push(todo),
+            None => roots.push(todo),
+        }
+    }
+    for root in &mut roots {
+        attach(root, &mut children);
+    }
+    roots
+}
+
+fn attach(todo: &mut ApiTodo, children: &mut HashMap<i32, Vec<ApiTodo>>) {
+    todo.subtasks = children.remove(&todo.id).unwrap_or_default();
+    for subtask in &mut todo.subtasks {
+        attach(subtask, children);
+    }
+}
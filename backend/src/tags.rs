@@ -0,0 +1,361 @@
+//! Tags label todos across projects. They belong to a user, are matched by
+//! name without regard to case and are created the first time they're used.
+//! A todo only carries tags of the user who owns it, whoever edits it.
+
+use diesel::dsl::count_distinct;
+use diesel::prelude::*;
+use diesel::sql_types::Text;
+use diesel::sqlite::Sqlite;
+use rocket::State;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use crate::{AuthenticatedUser, CustomError, DbPool, api, history, models, schema};
+
+type DbTag = models::Tag;
+type DbInsertableTag = models::InsertableTag;
+type DbTodo = models::Todo;
+type DbTodoTag = models::TodoTag;
+type ApiTag = api::Tag;
+type TagRename = api::TagRename;
+type TagMerge = api::TagMerge;
+
+const MAX_NAME_LEN: usize = 50;
+
+define_sql_function!(fn lower(x: Text) -> Text);
+
+/// Whether `GET /todos?tag=` wants todos with any of the tags or all of them.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum TagMatch {
+    #[default]
+    Any,
+    All,
+}
+
+impl FromStr for TagMatch {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "any" => Ok(TagMatch::Any),
+            "all" => Ok(TagMatch::All),
+            _ => Err(String::from("The tag match must be `any` or `all`.")),
+        }
+    }
+}
+
+fn key(name: &str) -> String {
+    name.to_ascii_lowercase()
+}
+
+fn check_name(name: &str) -> Result<(), CustomError> {
+    if name.is_empty() || name.chars().count() > MAX_NAME_LEN {
+        return Err(CustomError::InvalidInput(format!(
+            "Tag names must be between 1 and {} characters long.",
+            MAX_NAME_LEN
+        )));
+    }
+    Ok(())
+}
+
+/// Trims the names and drops ones that only differ in case from an earlier
+/// one.
+fn normalize(names: &[String]) -> Result<Vec<String>, CustomError> {
+    let mut normalized: Vec<String> = Vec::new();
+    for name in names {
+        let name = name.trim();
+        check_name(name)?;
+        if !normalized.iter().any(|seen| key(seen) == key(name)) {
+            normalized.push(name.to_string());
+        }
+    }
+    Ok(normalized)
+}
+
+fn find_by_name(
+    conn: &mut SqliteConnection,
+    user_id: i32,
+    name: &str,
+) -> QueryResult<Option<DbTag>> {
+    schema::tags::table
+        .filter(schema::tags::user_id.eq(user_id))
+        .filter(lower(schema::tags::name).eq(key(name)))
+        .first::<DbTag>(conn)
+        .optional()
+}
+
+fn find_owned(conn: &mut SqliteConnection, user_id: i32, id: i32) -> Result<DbTag, CustomError> {
+    schema::tags::table
+        .filter(schema::tags::user_id.eq(user_id))
+        .filter(schema::tags::id.eq(id))
+        .first::<DbTag>(conn)
+        .optional()?
+        .ok_or(CustomError::NotFound)
+}
+
+/// Replaces the tags on a todo, creating the ones its owner doesn't have yet.
+/// Returns the names as stored, so a tag keeps the spelling it was created
+/// with.
+pub fn set_todo_tags(
+    conn: &mut SqliteConnection,
+    todo: &DbTodo,
+    names: &[String],
+) -> Result<Vec<String>, CustomError> {
+    let (user_id, todo_id) = (todo.user_id, todo.id);
+    let mut tags = Vec::new();
+    for name in normalize(names)? {
+        let tag = match find_by_name(conn, user_id, &name)? {
+            Some(tag) => tag,
+            None => diesel::insert_into(schema::tags::table)
+                .values(&DbInsertableTag { user_id, name })
+                .get_result::<DbTag>(conn)?,
+        };
+        tags.push(tag);
+    }
+
+    let tag_ids: Vec<i32> = tags.iter().map(|tag| tag.id).collect();
+    diesel::delete(
+        schema::todo_tags::table
+            .filter(schema::todo_tags::todo_id.eq(todo_id))
+            .filter(schema::todo_tags::tag_id.ne_all(&tag_ids)),
+    )
+    .execute(conn)?;
+    let links: Vec<DbTodoTag> = tag_ids
+        .iter()
+        .map(|&tag_id| DbTodoTag { todo_id, tag_id })
+        .collect();
+    diesel::insert_or_ignore_into(schema::todo_tags::table)
+        .values(&links)
+        .execute(conn)?;
+
+    Ok(tags.into_iter().map(|tag| tag.name).collect())
+}
+
+/// The tag names of each todo, in the same order as `todos`.
+pub fn names_for(conn: &mut SqliteConnection, todos: &[DbTodo]) -> QueryResult<Vec<Vec<String>>> {
+    let links = DbTodoTag::belonging_to(todos)
+        .inner_join(schema::tags::table)
+        .select((DbTodoTag::as_select(), schema::tags::name))
+        .order(lower(schema::tags::name).asc())
+        .load::<(DbTodoTag, String)>(conn)?;
+
+    Ok(links
+        .grouped_by(todos)
+        .into_iter()
+        .map(|links| links.into_iter().map(|(_, name)| name).collect())
+        .collect())
+}
+
+pub fn names_of(conn: &mut SqliteConnection, todo: &DbTodo) -> QueryResult<Vec<String>> {
+    Ok(names_for(conn, std::slice::from_ref(todo))?
+        .pop()
+        .unwrap_or_default())
+}
+
+/// Narrows a todo query down to the todos that carry any or all of the named
+/// tags, whoever owns the tags.
+pub fn filter(
+    query: schema::todos::BoxedQuery<'static, Sqlite>,
+    names: &[&str],
+    tag_match: TagMatch,
+) -> schema::todos::BoxedQuery<'static, Sqlite> {
+    let mut keys: Vec<String> = names.iter().map(|name| key(name.trim())).collect();
+    keys.sort();
+    keys.dedup();
+    let wanted = keys.len() as i64;
+
+    let tagged = schema::todo_tags::table
+        .inner_join(schema::tags::table)
+        .filter(lower(schema::tags::name).eq_any(keys))
+        .select(schema::todo_tags::todo_id);
+    match tag_match {
+        TagMatch::Any => query.filter(schema::todos::id.eq_any(tagged)),
+        TagMatch::All => query.filter(
+            schema::todos::id.eq_any(
+                tagged
+                    .group_by(schema::todo_tags::todo_id)
+                    .having(count_distinct(lower(schema::tags::name)).eq(wanted)),
+            ),
+        ),
+    }
+}
+
+/// Runs `change` to a tag and records how it changed the tags of each todo
+/// that carried it.
+fn recording_changes<T>(
+    conn: &mut SqliteConnection,
+    user_id: i32,
+    tag_id: i32,
+    change: impl FnOnce(&mut SqliteConnection) -> Result<T, CustomError>,
+) -> Result<T, CustomError> {
+    let todos = schema::todos::table
+        .filter(
+            schema::todos::id.eq_any(
+                schema::todo_tags::table
+                    .filter(schema::todo_tags::tag_id.eq(tag_id))
+                    .select(schema::todo_tags::todo_id),
+            ),
+        )
+        .load::<DbTodo>(conn)?;
+    let before = names_for(conn, &todos)?;
+    let result = change(conn)?;
+    let after = names_for(conn, &todos)?;
+    for ((todo, before), after) in todos.iter().zip(before).zip(after) {
+        history::record(conn, todo.id, user_id, "tags", &before, &after)?;
+    }
+    Ok(result)
+}
+
+fn to_api_tag(tag: DbTag, todos: i64) -> ApiTag {
+    ApiTag {
+        id: tag.id,
+        name: tag.name,
+        todos,
+    }
+}
+
+fn todo_count(conn: &mut SqliteConnection, tag_id: i32) -> QueryResult<i64> {
+    schema::todo_tags::table
+        .inner_join(schema::todos::table)
+        .filter(schema::todo_tags::tag_id.eq(tag_id))
+        .filter(schema::todos::deleted_at.is_null())
+        .count()
+        .get_result(conn)
+}
+
+// --- Routes ---
+
+/// Lists the user's tags by name, with how many todos carry each.
+#[get("/tags")]
+pub fn list_tags(
+    pool: &State<DbPool>,
+    auth_user: AuthenticatedUser,
+) -> Result<Json<Vec<ApiTag>>, CustomError> {
+    let mut conn = pool.get().expect("Failed to get DB connection from pool");
+
+    let tags = schema::tags::table
+        .filter(schema::tags::user_id.eq(auth_user.user_id))
+        .order(lower(schema::tags::name).asc())
+        .load::<DbTag>(&mut conn)?;
+    let counts: HashMap<i32, i64> = schema::todo_tags::table
+        .inner_join(schema::tags::table)
+        .inner_join(schema::todos::table)
+        .filter(schema::tags::user_id.eq(auth_user.user_id))
+        .filter(schema::todos::deleted_at.is_null())
+        .group_by(schema::todo_tags::tag_id)
+        .select((schema::todo_tags::tag_id, diesel::dsl::count_star()))
+        .load::<(i32, i64)>(&mut conn)?
+        .into_iter()
+        .collect();
+
+    Ok(Json(
+        tags.into_iter()
+            .map(|tag| {
+                let todos = counts.get(&tag.id).copied().unwrap_or(0);
+                to_api_tag(tag, todos)
+            })
+            .collect(),
+    ))
+}
+
+/// Renames a tag. Renaming onto another existing tag is refused; merging is
+/// how two tags become one.
+#[put("/tags/<id>", data = "<rename_json>")]
+pub fn rename_tag(
+    pool: &State<DbPool>,
+    auth_user: AuthenticatedUser,
+    id: i32,
+    rename_json: Json<TagRename>,
+) -> Result<Json<ApiTag>, CustomError> {
+    let name = rename_json.into_inner().name.trim().to_string();
+    check_name(&name)?;
+    let mut conn = pool.get().expect("Failed to get DB connection from pool");
+
+    conn.transaction(|conn| {
+        let tag = find_owned(conn, auth_user.user_id, id)?;
+        if find_by_name(conn, auth_user.user_id, &name)?.is_some_and(|other| other.id != tag.id) {
+            return Err(CustomError::InvalidInput(String::from(
+                "A tag with that name already exists. Merge the tags instead.",
+            )));
+        }
+
+        let renamed = recording_changes(conn, auth_user.user_id, tag.id, |conn| {
+            Ok(diesel::update(schema::tags::table.find(tag.id))
+                .set(schema::tags::name.eq(&name))
+                .get_result::<DbTag>(conn)?)
+        })?;
+        let todos = todo_count(conn, renamed.id)?;
+        Ok(Json(to_api_tag(renamed, todos)))
+    })
+}
+
+/// Moves every todo of one tag over to another and deletes the first.
+#[post("/tags/<id>/merge", data = "<merge_json>")]
+pub fn merge_tag(
+    pool: &State<DbPool>,
+    auth_user: AuthenticatedUser,
+    id: i32,
+    merge_json: Json<TagMerge>,
+) -> Result<Json<ApiTag>, CustomError> {
+    let into = merge_json.into_inner().into;
+    if into == id {
+        return Err(CustomError::InvalidInput(String::from(
+            "A tag can't be merged into itself.",
+        )));
+    }
+    let mut conn = pool.get().expect("Failed to get DB connection from pool");
+
+    conn.transaction(|conn| {
+        let source = find_owned(conn, auth_user.user_id, id)?;
+        let target = find_owned(conn, auth_user.user_id, into)?;
+
+        recording_changes(conn, auth_user.user_id, source.id, |conn| {
+            let links: Vec<DbTodoTag> = schema::todo_tags::table
+                .filter(schema::todo_tags::tag_id.eq(source.id))
+                .select(schema::todo_tags::todo_id)
+                .load::<i32>(conn)?
+                .into_iter()
+                .map(|todo_id| DbTodoTag {
+                    todo_id,
+                    tag_id: target.id,
+                })
+                .collect();
+            // Todos that already carry both tags keep their existing link.
+            diesel::insert_or_ignore_into(schema::todo_tags::table)
+                .values(&links)
+                .execute(conn)?;
+            diesel::delete(
+                schema::todo_tags::table.filter(schema::todo_tags::tag_id.eq(source.id)),
+            )
+            .execute(conn)?;
+            diesel::delete(schema::tags::table.find(source.id)).execute(conn)?;
+            Ok(())
+        })?;
+
+        let todos = todo_count(conn, target.id)?;
+        Ok(Json(to_api_tag(target, todos)))
+    })
+}
+
+/// Deletes a tag and takes it off every todo. The todos themselves stay.
+#[delete("/tags/<id>")]
+pub fn delete_tag(
+    pool: &State<DbPool>,
+    auth_user: AuthenticatedUser,
+    id: i32,
+) -> Result<Status, CustomError> {
+    let mut conn = pool.get().expect("Failed to get DB connection from pool");
+
+    conn.transaction(|conn| {
+        let tag = find_owned(conn, auth_user.user_id, id)?;
+        recording_changes(conn, auth_user.user_id, tag.id, |conn| {
+            diesel::delete(schema::todo_tags::table.filter(schema::todo_tags::tag_id.eq(tag.id)))
+                .execute(conn)?;
+            diesel::delete(schema::tags::table.find(tag.id)).execute(conn)?;
+            Ok(())
+        })?;
+        Ok(Status::NoContent)
+    })
+}
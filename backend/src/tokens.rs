@@ -0,0 +1,197 @@
+use chrono::{DateTime, NaiveDateTime, Utc};
+use diesel::prelude::*;
+use rocket::State;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+
+use crate::api::{self, Scope};
+use crate::auth::LAST_SEEN_RESOLUTION;
+use crate::{AuthenticatedUser, CustomError, DbPool, models, schema, security};
+
+type DbApiToken = models::ApiToken;
+type DbInsertableApiToken = models::InsertableApiToken;
+type ApiApiToken = api::ApiToken;
+type NewApiToken = api::NewApiToken;
+
+/// Marks personal access tokens so the guard can tell them from JWTs, and so
+/// they are easy to spot in leaked logs or commits.
+pub const TOKEN_PREFIX: &str = "tdp_";
+const MAX_NAME_LEN: usize = 100;
+
+/// The scope a personal access token needs to call each route, by handler
+/// name. Routes that aren't listed, like account, session and token
+/// management, only accept a login session.
+pub fn required_scope(route: &str) -> Option<Scope> {
+    match route {
+        "list_all_todos" | "list_projects" | "list_tags" => Some(Scope::TodosRead),
+        "list_members" | "list_invitations" | "list_comments" => Some(Scope::TodosRead),
+        "todo_history" | "list_activity" | "list_trash" => Some(Scope::TodosRead),
+        "add_todo" | "update_todo" | "delete_todo" | "reorder_todos" => Some(Scope::TodosWrite),
+        "create_project" | "update_project" | "delete_project" => Some(Scope::TodosWrite),
+        "rename_tag" | "merge_tag" | "delete_tag" => Some(Scope::TodosWrite),
+        "invite_member" | "update_member" | "remove_member" => Some(Scope::TodosWrite),
+        "accept_invitation" | "decline_invitation" => Some(Scope::TodosWrite),
+        "add_comment" | "update_comment" | "delete_comment" => Some(Scope::TodosWrite),
+        "restore_todo" | "purge_todo" | "empty_trash" => Some(Scope::TodosWrite),
+        "list_webhooks" | "list_deliveries" => Some(Scope::WebhooksRead),
+        "create_webhook" | "update_webhook" | "delete_webhook" => Some(Scope::WebhooksWrite),
+        "get_profile" => Some(Scope::ProfileRead),
+        _ => None,
+    }
+}
+
+/// Looks up an unexpired token of an enabled account and records that it was
+/// used. Returns the owning user and the token's scopes.
+pub fn authenticate(
+    conn: &mut SqliteConnection,
+    token: &str,
+    now: NaiveDateTime,
+) -> QueryResult<Option<(i32, Vec<Scope>)>> {
+    let Some(api_token) = schema::api_tokens::table
+        .filter(schema::api_tokens::token_hash.eq(security::hash_token(token)))
+        .first::<DbApiToken>(conn)
+        .optional()?
+    else {
+        return Ok(None);
+    };
+    if api_token
+        .expires_at
+        .is_some_and(|expires_at| expires_at <= now)
+    {
+        return Ok(None);
+    }
+    let disabled_at = schema::users::table
+        .find(api_token.user_id)
+        .select(schema::users::disabled_at)
+        .first::<Option<NaiveDateTime>>(conn)?;
+    if disabled_at.is_some() {
+        return Ok(None);
+    }
+
+    let stale = api_token
+        .last_used_at
+        .is_none_or(|last_used_at| now - last_used_at >= LAST_SEEN_RESOLUTION);
+    if stale {
+        diesel::update(schema::api_tokens::table.filter(schema::api_tokens::id.eq(api_token.id)))
+            .set(schema::api_tokens::last_used_at.eq(now))
+            .execute(conn)?;
+    }
+
+    Ok(Some((api_token.user_id, parse_scopes(&api_token.scopes))))
+}
+
+fn parse_scopes(scopes: &str) -> Vec<Scope> {
+    scopes.split(',').filter_map(Scope::parse).collect()
+}
+
+fn format_scopes(scopes: &[Scope]) -> String {
+    let scopes: Vec<&str> = scopes.iter().map(Scope::as_str).collect();
+    scopes.join(",")
+}
+
+fn to_api_token(api_token: DbApiToken, token: Option<String>) -> ApiApiToken {
+    ApiApiToken {
+        id: api_token.id,
+        name: api_token.name,
+        scopes: parse_scopes(&api_token.scopes),
+        created_at: DateTime::<Utc>::from_naive_utc_and_offset(api_token.created_at, Utc),
+        expires_at: api_token
+            .expires_at
+            .map(|at| DateTime::<Utc>::from_naive_utc_and_offset(at, Utc)),
+        last_used_at: api_token
+            .last_used_at
+            .map(|at| DateTime::<Utc>::from_naive_utc_and_offset(at, Utc)),
+        token,
+    }
+}
+
+// --- Routes ---
+
+#[get("/tokens")]
+pub fn list_tokens(
+    pool: &State<DbPool>,
+    auth_user: AuthenticatedUser,
+) -> Result<Json<Vec<ApiApiToken>>, CustomError> {
+    let mut conn = pool.get().expect("Failed to get DB connection from pool");
+
+    let api_tokens = schema::api_tokens::table
+        .filter(schema::api_tokens::user_id.eq(auth_user.user_id))
+        .order(schema::api_tokens::id.asc())
+        .load::<DbApiToken>(&mut conn)?;
+
+    Ok(Json(
+        api_tokens
+            .into_iter()
+            .map(|api_token| to_api_token(api_token, None))
+            .collect(),
+    ))
+}
+
+/// Creates a token. The token itself is only part of this response; we keep
+/// nothing but its hash.
+#[post("/tokens", data = "<token_json>")]
+pub fn create_token(
+    pool: &State<DbPool>,
+    auth_user: AuthenticatedUser,
+    token_json: Json<NewApiToken>,
+) -> Result<Json<ApiApiToken>, CustomError> {
+    let new_token = token_json.into_inner();
+    let name = new_token.name.trim();
+    if name.is_empty() || name.chars().count() > MAX_NAME_LEN {
+        return Err(CustomError::InvalidInput(format!(
+            "The token name must be between 1 and {} characters.",
+            MAX_NAME_LEN
+        )));
+    }
+    if new_token.scopes.is_empty() {
+        return Err(CustomError::InvalidInput(String::from(
+            "A token needs at least one scope.",
+        )));
+    }
+    let now = Utc::now();
+    if new_token
+        .expires_at
+        .is_some_and(|expires_at| expires_at <= now)
+    {
+        return Err(CustomError::InvalidInput(String::from(
+            "The expiry must be in the future.",
+        )));
+    }
+    let mut conn = pool.get().expect("Failed to get DB connection from pool");
+
+    let token = format!("{}{}", TOKEN_PREFIX, security::generate_token(32));
+    let db_token = DbInsertableApiToken {
+        user_id: auth_user.user_id,
+        name: name.to_string(),
+        token_hash: security::hash_token(&token),
+        scopes: format_scopes(&new_token.scopes),
+        created_at: now.naive_utc(),
+        expires_at: new_token.expires_at.map(|at| at.naive_utc()),
+    };
+    let inserted = diesel::insert_into(schema::api_tokens::table)
+        .values(&db_token)
+        .get_result::<DbApiToken>(&mut conn)?;
+
+    Ok(Json(to_api_token(inserted, Some(token))))
+}
+
+#[delete("/tokens/<id>")]
+pub fn delete_token(
+    pool: &State<DbPool>,
+    auth_user: AuthenticatedUser,
+    id: i32,
+) -> Result<Status, CustomError> {
+    let mut conn = pool.get().expect("Failed to get DB connection from pool");
+
+    let deleted = diesel::delete(
+        schema::api_tokens::table
+            .filter(schema::api_tokens::user_id.eq(auth_user.user_id))
+            .filter(schema::api_tokens::id.eq(id)),
+    )
+    .execute(&mut conn)?;
+
+    if deleted == 0 {
+        return Err(CustomError::NotFound);
+    }
+    Ok(Status::NoContent)
+}
@@ -0,0 +1,279 @@
+//! The trash. Deleting a todo only moves it, with its subtasks, to the
+//! trash, where it can be restored until it's deleted for good, by hand or
+//! by the purger once it has been there for the retention period.
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use diesel::prelude::*;
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::tokio;
+use rocket::{Orbit, Rocket, Shutdown, State};
+use std::time::Duration;
+
+use crate::access::{self, Action};
+use crate::scheduler::{ReminderWakeup, with_conn};
+use crate::{
+    AuthenticatedUser, CustomError, DbPool, DbTodo, EventKind, Events, TodoEvent, api, comments,
+    history, load_api_todo, load_api_todos, schema, subtasks,
+};
+
+type ApiTodo = api::Todo;
+
+/// How often the purger looks for todos that have been in the trash too long.
+const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// How many expired todos the purger deletes per transaction.
+const PURGE_BATCH_SIZE: i64 = 200;
+
+/// Deletes todos for good, with everything attached to them.
+pub fn purge(conn: &mut SqliteConnection, ids: &[i32]) -> QueryResult<()> {
+    diesel::delete(schema::reminders::table.filter(schema::reminders::todo_id.eq_any(ids)))
+        .execute(conn)?;
+    diesel::delete(schema::completions::table.filter(schema::completions::todo_id.eq_any(ids)))
+        .execute(conn)?;
+    diesel::delete(schema::todo_tags::table.filter(schema::todo_tags::todo_id.eq_any(ids)))
+        .execute(conn)?;
+    diesel::delete(
+        schema::todo_assignees::table.filter(schema::todo_assignees::todo_id.eq_any(ids)),
+    )
+    .execute(conn)?;
+    diesel::delete(schema::todo_history::table.filter(schema::todo_history::todo_id.eq_any(ids)))
+        .execute(conn)?;
+    comments::delete_for_todos(conn, ids)?;
+    diesel::delete(schema::todos::table.filter(schema::todos::id.eq_any(ids))).execute(conn)?;
+    Ok(())
+}
+
+/// The given todos in the trash together with their subtasks in the trash
+/// the user may edit.
+fn with_subtasks(
+    conn: &mut SqliteConnection,
+    user_id: i32,
+    ids: Vec<i32>,
+) -> QueryResult<Vec<i32>> {
+    let mut all = ids.clone();
+    for id in ids {
+        all.extend(subtasks::editable_descendants(conn, user_id, id)?);
+    }
+    schema::todos::table
+        .filter(schema::todos::id.eq_any(&all))
+        .filter(schema::todos::deleted_at.is_not_null())
+        .select(schema::todos::id)
+        .load::<i32>(conn)
+}
+
+/// Purges the todos that went into the trash before `cutoff`, a batch at a
+/// time so a long-neglected trash doesn't hold the database in one
+/// transaction.
+fn purge_expired(conn: &mut SqliteConnection, cutoff: NaiveDateTime) -> QueryResult<()> {
+    loop {
+        let purged = conn.transaction(|conn| {
+            let expired = schema::todos::table
+                .filter(schema::todos::deleted_at.lt(cutoff))
+                .select(schema::todos::id)
+                .limit(PURGE_BATCH_SIZE)
+                .load::<i32>(conn)?;
+            let mut ids = expired.clone();
+            for id in expired {
+                ids.extend(subtasks::descendants(conn, id)?);
+            }
+            purge(conn, &ids)?;
+            Ok::<_, diesel::result::Error>(ids.len())
+        })?;
+        if purged == 0 {
+            return Ok(());
+        }
+    }
+}
+
+// --- Purger ---
+
+/// Fairing that purges todos once they've been in the trash for
+/// `retention`.
+pub struct TrashPurger {
+    retention: chrono::Duration,
+}
+
+impl TrashPurger {
+    pub fn new(retention: chrono::Duration) -> Self {
+        TrashPurger { retention }
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for TrashPurger {
+    fn info(&self) -> Info {
+        Info {
+            name: "Trash Purger",
+            kind: Kind::Liftoff,
+        }
+    }
+
+    async fn on_liftoff(&self, rocket: &Rocket<Orbit>) {
+        let Some(pool) = rocket.state::<DbPool>().cloned() else {
+            eprintln!("Missing database pool in Rocket state, the trash will not be purged!");
+            return;
+        };
+
+        tokio::spawn(run(pool, self.retention, rocket.shutdown()));
+    }
+}
+
+async fn run(pool: DbPool, retention: chrono::Duration, shutdown: Shutdown) {
+    loop {
+        let cutoff = Utc::now().naive_utc() - retention;
+        with_conn(&pool, move |conn| purge_expired(conn, cutoff)).await;
+
+        tokio::select! {
+            _ = shutdown.clone() => break,
+            _ = tokio::time::sleep(PURGE_INTERVAL) => {}
+        }
+    }
+}
+
+// --- Routes ---
+
+/// Lists the todos in the trash the user can see, most recently deleted
+/// first.
+#[get("/trash")]
+pub fn list_trash(
+    pool: &State<DbPool>,
+    auth_user: AuthenticatedUser,
+) -> Result<Json<Vec<ApiTodo>>, CustomError> {
+    let mut conn = pool.get().expect("Failed to get DB connection from pool");
+
+    let todos = access::trashed_todos(&mut conn, auth_user.user_id)?
+        .order((schema::todos::deleted_at.desc(), schema::todos::id.asc()))
+        .load::<DbTodo>(&mut conn)?;
+    Ok(Json(load_api_todos(&mut conn, todos)?))
+}
+
+/// Takes a todo out of the trash, along with the subtasks that were deleted
+/// with it. A todo whose parent is still in the trash comes back at the top
+/// level.
+#[post("/todos/<id>/restore")]
+pub fn restore_todo(
+    pool: &State<DbPool>,
+    reminder_wakeup: &State<ReminderWakeup>,
+    events: &State<Events>,
+    auth_user: AuthenticatedUser,
+    id: i32,
+) -> Result<Json<ApiTodo>, CustomError> {
+    let mut conn = pool.get().expect("Failed to get DB connection from pool");
+
+    let (todo, restored) = conn.transaction(|conn| {
+        let todo = access::authorize_trashed(conn, auth_user.user_id, id, Action::Edit)?;
+
+        let mut ids = schema::todos::table
+            .filter(schema::todos::id.eq_any(subtasks::editable_descendants(
+                conn,
+                auth_user.user_id,
+                id,
+            )?))
+            .filter(schema::todos::deleted_at.eq(todo.deleted_at))
+            .select(schema::todos::id)
+            .load::<i32>(conn)?;
+        ids.push(id);
+        let restored = diesel::update(schema::todos::table.filter(schema::todos::id.eq_any(&ids)))
+            .set(schema::todos::deleted_at.eq(None::<NaiveDateTime>))
+            .get_results::<DbTodo>(conn)?;
+        let deleted_at = todo
+            .deleted_at
+            .map(|d| DateTime::<Utc>::from_naive_utc_and_offset(d, Utc));
+        for todo in &restored {
+            history::record(
+                conn,
+                todo.id,
+                auth_user.user_id,
+                "deleted_at",
+                &deleted_at,
+                &None,
+            )?;
+        }
+
+        let mut todo = schema::todos::table.find(id).first::<DbTodo>(conn)?;
+        if let Some(parent_id) = todo.parent_id {
+            let parent_there = schema::todos::table
+                .find(parent_id)
+                .filter(schema::todos::deleted_at.is_null())
+                .count()
+                .get_result::<i64>(conn)?
+                > 0;
+            if !parent_there {
+                todo = diesel::update(schema::todos::table.find(id))
+                    .set(schema::todos::parent_id.eq(None::<i32>))
+                    .get_result::<DbTodo>(conn)?;
+                history::record(
+                    conn,
+                    id,
+                    auth_user.user_id,
+                    "parent_id",
+                    &Some(parent_id),
+                    &None,
+                )?;
+            }
+        }
+
+        let restored = restored.into_iter().filter(|todo| todo.id != id).collect();
+        Ok::<_, CustomError>((load_api_todo(conn, todo)?, load_api_todos(conn, restored)?))
+    })?;
+
+    reminder_wakeup.wake();
+    // They were announced as deleted, so they're announced as created again.
+    events.emit(TodoEvent::new(
+        EventKind::TodoCreated,
+        auth_user.user_id,
+        todo.clone(),
+    ));
+    for subtask in restored {
+        events.emit(TodoEvent::new(
+            EventKind::TodoCreated,
+            auth_user.user_id,
+            subtask,
+        ));
+    }
+    Ok(Json(todo))
+}
+
+/// Deletes a todo in the trash for good, with its subtasks.
+#[delete("/trash/<id>")]
+pub fn purge_todo(
+    pool: &State<DbPool>,
+    auth_user: AuthenticatedUser,
+    id: i32,
+) -> Result<Status, CustomError> {
+    let mut conn = pool.get().expect("Failed to get DB connection from pool");
+
+    conn.transaction(|conn| {
+        access::authorize_trashed(conn, auth_user.user_id, id, Action::Edit)?;
+        let ids = with_subtasks(conn, auth_user.user_id, vec![id])?;
+        purge(conn, &ids)?;
+        Ok(Status::NoContent)
+    })
+}
+
+/// Deletes for good every todo in the trash the user may delete.
+#[delete("/trash")]
+pub fn empty_trash(
+    pool: &State<DbPool>,
+    auth_user: AuthenticatedUser,
+) -> Result<Status, CustomError> {
+    let mut conn = pool.get().expect("Failed to get DB connection from pool");
+
+    conn.transaction(|conn| {
+        let trashed = access::trashed_todos(conn, auth_user.user_id)?
+            .select(schema::todos::id)
+            .load::<i32>(conn)?;
+        let mut ids = Vec::new();
+        for id in trashed {
+            match access::authorize_trashed(conn, auth_user.user_id, id, Action::Edit) {
+                Ok(_) => ids.push(id),
+                Err(CustomError::Forbidden) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        let ids = with_subtasks(conn, auth_user.user_id, ids)?;
+        purge(conn, &ids)?;
+        Ok(Status::NoContent)
+    })
+}
@@ -0,0 +1,384 @@
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use hmac::{Hmac, Mac};
+use rocket::State;
+use rocket::http::Status;
+use rocket::serde::json::{self, Json};
+use rocket::tokio;
+use sha2::Sha256;
+use std::time::Duration;
+
+use crate::api::{self, EventKind};
+use crate::notifier::{DueReminder, Notifier, NotifyError, TodoEvent};
+use crate::scheduler::with_conn;
+use crate::{AuthenticatedUser, CustomError, DbPool, models, schema, security};
+
+type DbWebhook = models::Webhook;
+type DbInsertableWebhook = models::InsertableWebhook;
+type DbWebhookDelivery = models::WebhookDelivery;
+type DbInsertableWebhookDelivery = models::InsertableWebhookDelivery;
+type ApiWebhook = api::Webhook;
+type NewWebhook = api::NewWebhook;
+type ApiWebhookDelivery = api::WebhookDelivery;
+
+/// Attempts per delivery, including the first one.
+const MAX_ATTEMPTS: i32 = 5;
+/// Wait before the first retry; doubled after every further failure.
+const INITIAL_BACKOFF: Duration = Duration::from_secs(2);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// How much of the delivery log `list_deliveries` returns.
+const DELIVERY_LOG_LIMIT: i64 = 50;
+
+// --- Delivery ---
+
+/// Notifier that POSTs todo events and due reminders to the user's webhook
+/// subscriptions. Each request carries an `X-TooDoo-Signature` header with the
+/// hex HMAC-SHA256 of the body, keyed with the webhook's secret. Delivery runs
+/// in the background, so it never holds up a request or the reminder
+/// dispatcher, and every attempt is written to `webhook_deliveries`.
+pub struct WebhookNotifier {
+    pool: DbPool,
+    client: reqwest::Client,
+}
+
+impl WebhookNotifier {
+    pub fn new(pool: DbPool) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .expect("Failed to create webhook HTTP client.");
+        WebhookNotifier { pool, client }
+    }
+
+    fn publish(&self, user_id: i32, payload: api::WebhookPayload) {
+        let pool = self.pool.clone();
+        let client = self.client.clone();
+
+        tokio::spawn(async move {
+            let subscribed = with_conn(&pool, move |conn| {
+                schema::webhooks::table
+                    .filter(schema::webhooks::user_id.eq(user_id))
+                    .filter(schema::webhooks::active.eq(true))
+                    .load::<DbWebhook>(conn)
+            })
+            .await
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|webhook| {
+                let events = parse_events(&webhook.events);
+                events.is_empty() || events.contains(&payload.event)
+            });
+
+            let body = match json::to_string(&payload) {
+                Ok(body) => body,
+                Err(e) => {
+                    eprintln!("Failed to serialize webhook payload: {}", e);
+                    return;
+                }
+            };
+            for webhook in subscribed {
+                tokio::spawn(deliver(
+                    pool.clone(),
+                    client.clone(),
+                    webhook,
+                    payload.event,
+                    body.clone(),
+                ));
+            }
+        });
+    }
+}
+
+#[rocket::async_trait]
+impl Notifier for WebhookNotifier {
+    async fn reminder_due(&self, reminder: &DueReminder) -> Result<(), NotifyError> {
+        self.publish(
+            reminder.user_id,
+            api::WebhookPayload {
+                event: EventKind::ReminderDue,
+                occurred_at: Utc::now(),
+                todo: reminder.todo.clone(),
+                remind_at: Some(reminder.remind_at),
+            },
+        );
+        Ok(())
+    }
+
+    async fn todo_event(&self, event: &TodoEvent) -> Result<(), NotifyError> {
+        self.publish(
+            event.user_id,
+            api::WebhookPayload {
+                event: event.kind,
+                occurred_at: event.occurred_at,
+                todo: event.todo.clone(),
+                remind_at: None,
+            },
+        );
+        Ok(())
+    }
+}
+
+async fn deliver(
+    pool: DbPool,
+    client: reqwest::Client,
+    webhook: DbWebhook,
+    event: EventKind,
+    body: String,
+) {
+    let delivery_id = security::generate_token(16);
+    let signature = sign(&webhook.secret, &body);
+    let mut backoff = INITIAL_BACKOFF;
+
+    for attempt in 1..=MAX_ATTEMPTS {
+        let result = client
+            .post(&webhook.url)
+            .header("Content-Type", "application/json")
+            .header("User-Agent", "TooDoo-Webhooks")
+            .header("X-TooDoo-Event", event.as_str())
+            .header("X-TooDoo-Delivery", &delivery_id)
+            .header("X-TooDoo-Signature", format!("sha256={}", signature))
+            .body(body.clone())
+            .send()
+            .await;
+
+        let (status_code, error) = match result {
+            Ok(response) if response.status().is_success() => {
+                (Some(response.status().as_u16()), None)
+            }
+            Ok(response) => (
+                Some(response.status().as_u16()),
+                Some(format!("Unexpected status {}", response.status())),
+            ),
+            Err(e) => (None, Some(e.to_string())),
+        };
+        let succeeded = error.is_none();
+
+        let log = DbInsertableWebhookDelivery {
+            webhook_id: webhook.id,
+            delivery_id: delivery_id.clone(),
+            event: event.as_str().to_string(),
+            attempt,
+            status_code: status_code.map(i32::from),
+            error,
+            succeeded,
+            attempted_at: Utc::now().naive_utc(),
+        };
+        with_conn(&pool, move |conn| {
+            diesel::insert_into(schema::webhook_deliveries::table)
+                .values(&log)
+                .execute(conn)
+        })
+        .await;
+
+        if succeeded {
+            return;
+        }
+        if attempt < MAX_ATTEMPTS {
+            tokio::time::sleep(backoff).await;
+            backoff *= 2;
+        }
+    }
+    eprintln!(
+        "Giving up on webhook {} delivery {} after {} attempts",
+        webhook.id, delivery_id, MAX_ATTEMPTS
+    );
+}
+
+/// Hex HMAC-SHA256 of `body`, which receivers recompute with their copy of the
+/// secret to check a delivery really came from us.
+fn sign(secret: &str, body: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(body.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+fn parse_events(events: &str) -> Vec<EventKind> {
+    events.split(',').filter_map(EventKind::parse).collect()
+}
+
+fn format_events(events: &[EventKind]) -> String {
+    let events: Vec<&str> = events.iter().map(EventKind::as_str).collect();
+    events.join(",")
+}
+
+fn to_api_webhook(webhook: DbWebhook, include_secret: bool) -> ApiWebhook {
+    ApiWebhook {
+        id: webhook.id,
+        url: webhook.url,
+        events: parse_events(&webhook.events),
+        active: webhook.active,
+        created_at: DateTime::<Utc>::from_naive_utc_and_offset(webhook.created_at, Utc),
+        secret: include_secret.then_some(webhook.secret),
+    }
+}
+
+fn validate_url(url: &str) -> Result<(), CustomError> {
+    match reqwest::Url::parse(url) {
+        Ok(parsed) if matches!(parsed.scheme(), "http" | "https") => Ok(()),
+        _ => Err(CustomError::InvalidInput(String::from(
+            "The webhook URL must be an absolute http(s) URL.",
+        ))),
+    }
+}
+
+// --- Routes ---
+
+#[get("/webhooks")]
+pub fn list_webhooks(
+    pool: &State<DbPool>,
+    auth_user: AuthenticatedUser,
+) -> Result<Json<Vec<ApiWebhook>>, CustomError> {
+    let mut conn = pool.get().expect("Failed to get DB connection from pool");
+
+    let webhooks = schema::webhooks::table
+        .filter(schema::webhooks::user_id.eq(auth_user.user_id))
+        .order(schema::webhooks::id.asc())
+        .load::<DbWebhook>(&mut conn)?;
+
+    Ok(Json(
+        webhooks
+            .into_iter()
+            .map(|webhook| to_api_webhook(webhook, false))
+            .collect(),
+    ))
+}
+
+#[post("/webhooks", data = "<webhook_json>")]
+pub fn create_webhook(
+    pool: &State<DbPool>,
+    auth_user: AuthenticatedUser,
+    webhook_json: Json<NewWebhook>,
+) -> Result<Json<ApiWebhook>, CustomError> {
+    let new_webhook = webhook_json.into_inner();
+    validate_url(&new_webhook.url)?;
+    let mut conn = pool.get().expect("Failed to get DB connection from pool");
+
+    let db_webhook = DbInsertableWebhook {
+        user_id: auth_user.user_id,
+        url: new_webhook.url,
+        secret: security::generate_token(32),
+        events: format_events(&new_webhook.events),
+        active: new_webhook.active.unwrap_or(true),
+        created_at: Utc::now().naive_utc(),
+    };
+    let inserted = diesel::insert_into(schema::webhooks::table)
+        .values(&db_webhook)
+        .get_result::<DbWebhook>(&mut conn)?;
+
+    Ok(Json(to_api_webhook(inserted, true)))
+}
+
+#[put("/webhooks/<id>", data = "<webhook_json>")]
+pub fn update_webhook(
+    pool: &State<DbPool>,
+    auth_user: AuthenticatedUser,
+    id: i32,
+    webhook_json: Json<NewWebhook>,
+) -> Result<Json<ApiWebhook>, CustomError> {
+    let webhook = webhook_json.into_inner();
+    validate_url(&webhook.url)?;
+    let mut conn = pool.get().expect("Failed to get DB connection from pool");
+
+    let target = schema::webhooks::table
+        .filter(schema::webhooks::user_id.eq(auth_user.user_id))
+        .filter(schema::webhooks::id.eq(id));
+    let updated = diesel::update(target)
+        .set((
+            schema::webhooks::url.eq(&webhook.url),
+            schema::webhooks::events.eq(format_events(&webhook.events)),
+            schema::webhooks::active.eq(webhook.active.unwrap_or(true)),
+        ))
+        .get_result::<DbWebhook>(&mut conn)
+        .optional()?
+        .ok_or(CustomError::NotFound)?;
+
+    Ok(Json(to_api_webhook(updated, false)))
+}
+
+#[delete("/webhooks/<id>")]
+pub fn delete_webhook(
+    pool: &State<DbPool>,
+    auth_user: AuthenticatedUser,
+    id: i32,
+) -> Result<Status, CustomError> {
+    let mut conn = pool.get().expect("Failed to get DB connection from pool");
+
+    conn.transaction(|conn| {
+        let owned = schema::webhooks::table
+            .filter(schema::webhooks::user_id.eq(auth_user.user_id))
+            .filter(schema::webhooks::id.eq(id))
+            .select(schema::webhooks::id)
+            .first::<i32>(conn)
+            .optional()?;
+        if owned.is_none() {
+            return Err(CustomError::NotFound);
+        }
+
+        diesel::delete(
+            schema::webhook_deliveries::table.filter(schema::webhook_deliveries::webhook_id.eq(id)),
+        )
+        .execute(conn)?;
+        diesel::delete(schema::webhooks::table.filter(schema::webhooks::id.eq(id)))
+            .execute(conn)?;
+
+        Ok(Status::NoContent)
+    })
+}
+
+#[get("/webhooks/<id>/deliveries")]
+pub fn list_deliveries(
+    pool: &State<DbPool>,
+    auth_user: AuthenticatedUser,
+    id: i32,
+) -> Result<Json<Vec<ApiWebhookDelivery>>, CustomError> {
+    let mut conn = pool.get().expect("Failed to get DB connection from pool");
+
+    let webhook = schema::webhooks::table
+        .filter(schema::webhooks::user_id.eq(auth_user.user_id))
+        .filter(schema::webhooks::id.eq(id))
+        .first::<DbWebhook>(&mut conn)?;
+
+    let deliveries = DbWebhookDelivery::belonging_to(&webhook)
+        .order(schema::webhook_deliveries::id.desc())
+        .limit(DELIVERY_LOG_LIMIT)
+        .load::<DbWebhookDelivery>(&mut conn)?;
+
+    Ok(Json(
+        deliveries
+            .into_iter()
+            .map(|d| ApiWebhookDelivery {
+                id: d.id,
+                delivery_id: d.delivery_id,
+                event: d.event,
+                attempt: d.attempt,
+                status_code: d.status_code,
+                error: d.error,
+                succeeded: d.succeeded,
+                attempted_at: DateTime::<Utc>::from_naive_utc_and_offset(d.attempted_at, Utc),
+            })
+            .collect(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signs_with_hmac_sha256() {
+        // RFC 4231, test case 2.
+        assert_eq!(
+            sign("Jefe", "what do ya want for nothing?"),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn signature_depends_on_secret_and_body() {
+        let signature = sign("secret", r#"{"event":"todo.created"}"#);
+        assert_eq!(signature.len(), 64);
+        assert_ne!(signature, sign("other", r#"{"event":"todo.created"}"#));
+        assert_ne!(signature, sign("secret", r#"{"event":"todo.deleted"}"#));
+    }
+}
@@ -0,0 +1,276 @@
+//! Priority, status and the user's own order of todos.
+//!
+//! The order is kept as a rank string on each todo. Moving a todo only
+//! rewrites its own rank, picked to sort between its new neighbours, so
+//! clients reordering different todos at the same time don't undo each
+//! other's moves.
+
+use diesel::prelude::*;
+use rocket::State;
+use rocket::serde::json::Json;
+
+use crate::access::{self, Action};
+use crate::{
+    AuthenticatedUser, CustomError, DbPool, DbTodo, EventKind, Events, TodoEvent, api, history,
+    load_api_todo, schema,
+};
+
+type ApiTodo = api::Todo;
+type Reorder = api::Reorder;
+type TodoStatus = api::TodoStatus;
+
+const DIGITS: &[u8] = b"0123456789abcdefghijklmnopqrstuvwxyz";
+/// How many leading digits count when appending. New todos get ranks this
+/// long, which leaves room for 36^8 of them.
+const WIDTH: usize = 8;
+
+fn digit_value(c: u8) -> usize {
+    DIGITS.iter().position(|&d| d == c).unwrap_or(0)
+}
+
+/// A rank that sorts after `low` and before `high`. `""` sorts before every
+/// rank and `None` after. Ranks never end in '0', so there's always room
+/// between two of them.
+pub fn rank_between(low: &str, high: Option<&str>) -> String {
+    let mut rank = String::new();
+    midpoint(low.as_bytes(), high.map(str::as_bytes), &mut rank);
+    rank
+}
+
+fn midpoint(low: &[u8], high: Option<&[u8]>, rank: &mut String) {
+    if let Some(high) = high {
+        // Keep the prefix the two share, reading a shorter `low` as if it
+        // went on with zeros.
+        let shared = high
+            .iter()
+            .enumerate()
+            .take_while(|&(i, &c)| low.get(i).copied().unwrap_or(b'0') == c)
+            .count();
+        if shared > 0 {
+            rank.extend(high[..shared].iter().map(|&c| c as char));
+            midpoint(
+                low.get(shared..).unwrap_or_default(),
+                Some(&high[shared..]),
+                rank,
+            );
+            return;
+        }
+    }
+
+    let l = low.first().map_or(0, |&c| digit_value(c));
+    let h = high
+        .and_then(|high| high.first())
+        .map_or(DIGITS.len(), |&c| digit_value(c));
+    if h > l + 1 {
+        rank.push(DIGITS[(l + h) / 2] as char);
+        return;
+    }
+    match high {
+        // The first digits are next to each other, but `high` goes on, so
+        // its first digit alone sorts in between.
+        Some(high) if h == l + 1 && high.len() > 1 => rank.push(high[0] as char),
+        _ => {
+            rank.push(DIGITS[l] as char);
+            midpoint(low.get(1..).unwrap_or_default(), None, rank);
+        }
+    }
+}
+
+/// A rank after `last` for a todo added at the end. Counts up in the first
+/// `WIDTH` digits instead of halving the gap, so ranks stay short however
+/// many todos are added one after another.
+pub fn rank_after(last: Option<&str>) -> String {
+    let last = last.unwrap_or_default().as_bytes();
+    let mut digits: Vec<usize> = (0..WIDTH)
+        .map(|i| last.get(i).map_or(0, |&c| digit_value(c)))
+        .collect();
+
+    for i in (0..WIDTH).rev() {
+        if digits[i] + 1 < DIGITS.len() {
+            digits[i] += 1;
+            let mut rank: String = digits.iter().map(|&d| DIGITS[d] as char).collect();
+            if rank.ends_with('0') {
+                rank.push(DIGITS[DIGITS.len() / 2] as char);
+            }
+            return rank;
+        }
+        digits[i] = 0;
+    }
+    // Every leading digit is already the last one.
+    rank_between(std::str::from_utf8(last).unwrap_or_default(), None)
+}
+
+/// The rank for a new todo of the user, after all the others they can see.
+pub fn next_rank(conn: &mut SqliteConnection, user_id: i32) -> QueryResult<String> {
+    let last = access::visible_todos(conn, user_id)?
+        .select(diesel::dsl::max(schema::todos::rank))
+        .first::<Option<String>>(conn)?;
+    Ok(rank_after(last.as_deref()))
+}
+
+/// Works out a todo's status after an update that may have changed its
+/// status, `completed` or both. A new status wins; otherwise ticking
+/// `completed` makes the todo done and unticking it makes it a todo again.
+pub fn resolve_status(
+    current: TodoStatus,
+    was_completed: bool,
+    status: TodoStatus,
+    completed: bool,
+) -> TodoStatus {
+    if status != current {
+        status
+    } else if completed != was_completed {
+        if completed {
+            TodoStatus::Done
+        } else {
+            TodoStatus::Todo
+        }
+    } else {
+        current
+    }
+}
+
+// --- Routes ---
+
+/// Moves a todo to right after another one, or to the top.
+#[post("/todos/reorder", data = "<reorder_json>")]
+pub fn reorder_todos(
+    pool: &State<DbPool>,
+    events: &State<Events>,
+    auth_user: AuthenticatedUser,
+    reorder_json: Json<Reorder>,
+) -> Result<Json<ApiTodo>, CustomError> {
+    let reorder = reorder_json.into_inner();
+    if reorder.after == Some(reorder.id) {
+        return Err(CustomError::InvalidInput(String::from(
+            "A todo can't be put after itself.",
+        )));
+    }
+    let mut conn = pool.get().expect("Failed to get DB connection from pool");
+
+    let moved = conn.transaction(|conn| {
+        let todo = access::authorize(conn, auth_user.user_id, reorder.id, Action::Edit)?;
+
+        let low = match reorder.after {
+            Some(after) => match access::authorize(conn, auth_user.user_id, after, Action::View) {
+                Ok(after) => after.rank,
+                Err(CustomError::NotFound) => {
+                    return Err(CustomError::InvalidInput(String::from(
+                        "The todo to put it after doesn't exist.",
+                    )));
+                }
+                Err(e) => return Err(e),
+            },
+            None => String::new(),
+        };
+        // Whatever sorts next now, so todos moved by someone else since the
+        // client last looked stay where they were put.
+        let high = access::visible_todos(conn, auth_user.user_id)?
+            .filter(schema::todos::id.ne(todo.id))
+            .filter(schema::todos::rank.gt(&low))
+            .select(diesel::dsl::min(schema::todos::rank))
+            .first::<Option<String>>(conn)?;
+
+        let before = todo.rank;
+        let todo = diesel::update(schema::todos::table.find(todo.id))
+            .set(schema::todos::rank.eq(rank_between(&low, high.as_deref())))
+            .get_result::<DbTodo>(conn)?;
+        history::record(
+            conn,
+            todo.id,
+            auth_user.user_id,
+            "rank",
+            &before,
+            &todo.rank,
+        )?;
+        Ok::<_, CustomError>(load_api_todo(conn, todo)?)
+    })?;
+
+    events.emit(TodoEvent::new(
+        EventKind::TodoUpdated,
+        auth_user.user_id,
+        moved.clone(),
+    ));
+    Ok(Json(moved))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_between(low: &str, high: Option<&str>) {
+        let rank = rank_between(low, high);
+        assert!(
+            low < rank.as_str(),
+            "{:?} should sort after {:?}",
+            rank,
+            low
+        );
+        if let Some(high) = high {
+            assert!(
+                rank.as_str() < high,
+                "{:?} should sort before {:?}",
+                rank,
+                high
+            );
+        }
+        assert!(!rank.ends_with('0'), "{:?} ends in '0'", rank);
+    }
+
+    #[test]
+    fn ranks_between_neighbours() {
+        assert_between("", None);
+        assert_between("", Some("1"));
+        assert_between("a", Some("b"));
+        assert_between("a", Some("c"));
+        assert_between("a", Some("a1"));
+        assert_between("a5", Some("b"));
+        assert_between("az", Some("b"));
+        assert_between("z", None);
+        assert_between("zzzz", None);
+        assert_between("0000001", Some("0000002"));
+    }
+
+    #[test]
+    fn keeps_finding_room_between_the_same_neighbours() {
+        let (low, mut high) = (String::from("a"), String::from("b"));
+        for _ in 0..200 {
+            let rank = rank_between(&low, Some(&high));
+            assert!(low < rank && rank < high);
+            high = rank;
+        }
+        let (mut low, high) = (String::from("a"), String::from("b"));
+        for _ in 0..200 {
+            let rank = rank_between(&low, Some(&high));
+            assert!(low < rank && rank < high);
+            low = rank;
+        }
+    }
+
+    #[test]
+    fn appends_in_order_with_short_ranks() {
+        let mut last: Option<String> = None;
+        for _ in 0..1000 {
+            let rank = rank_after(last.as_deref());
+            if let Some(last) = &last {
+                assert!(last < &rank, "{:?} should sort after {:?}", rank, last);
+            }
+            assert!(rank.len() <= WIDTH + 1);
+            assert!(!rank.ends_with('0'));
+            last = Some(rank);
+        }
+    }
+
+    #[test]
+    fn appends_after_ranks_of_any_length() {
+        for last in ["5", "a5", "00000001x", "zzzzzzzz", "zzzzzzzzz5"] {
+            let rank = rank_after(Some(last));
+            assert!(
+                last < rank.as_str(),
+                "{:?} should sort after {:?}",
+                rank,
+                last
+            );
+        }
+    }
+}
@@ -21,18 +21,57 @@ const apiClient = axios.create({
   },
 });
 
+// Shared between concurrent requests so a refresh token is only spent once
+let refreshInFlight: Promise<string | null> | null = null;
+
+// Trade the refresh token for a new token pair. Resolves to null if the
+// session is gone, in which case the user has to log in again.
+function refreshAccessToken(): Promise<string | null> {
+  const authStore = useAuthStore();
+  if (!authStore.refreshToken) {
+    return Promise.resolve(null);
+  }
+
+  if (!refreshInFlight) {
+    refreshInFlight = axios
+      .post('/api/users/refresh', { refresh_token: authStore.refreshToken })
+      .then((response) => {
+        authStore.setToken(response.data.token, response.data.refresh_token);
+        return response.data.token as string;
+      })
+      .catch(() => null)
+      .finally(() => {
+        refreshInFlight = null;
+      });
+  }
+  return refreshInFlight;
+}
+
+function endSession() {
+  const authStore = useAuthStore();
+  authStore.clearToken();
+  router.push('/login');
+}
+
 // --- Request Interceptor ---
 apiClient.interceptors.request.use(
-  (config) => {
+  async (config) => {
     const authStore = useAuthStore();
-    const token = authStore.token;
+    let token = authStore.token;
+
+    // Check if token is expired before using it, and renew it if we can
+    if ((!token || isTokenExpired(token)) && authStore.refreshToken) {
+      token = await refreshAccessToken();
+      if (!token) {
+        endSession();
+        return Promise.reject(new Error('Session expired'));
+      }
+    }
 
     if (token) {
-      // Check if token is expired before using it
       if (isTokenExpired(token)) {
-        // Token is expired, clear it and redirect to login
-        authStore.clearToken();
-        router.push('/login');
+        // Token is expired and can't be refreshed, redirect to login
+        endSession();
         return Promise.reject(new Error('Token expired'));
       }
       
@@ -54,13 +93,17 @@ apiClient.interceptors.response.use(
     // If response is successful, just return it
     return response;
   },
-  (error) => {
+  async (error) => {
     // Handle response errors
-    if (error.response?.status === 401) {
-      // Token is expired or invalid
-      const authStore = useAuthStore();
-      authStore.clearToken(); // Clear the expired token
-      router.push('/login'); // Redirect to login page
+    if (error.response?.status === 401 && useAuthStore().token) {
+      // The token was rejected; retry once with a fresh one before giving up
+      const original = error.config;
+      if (original && !original._retried && (await refreshAccessToken())) {
+        original._retried = true;
+        return apiClient(original);
+      }
+
+      endSession(); // Clear the session and redirect to login page
     }
     
     return Promise.reject(error);
@@ -2,6 +2,8 @@ import { createRouter, createWebHistory } from 'vue-router';
 import TodoListView from '../views/TodoListView.vue';
 import LoginView from '../views/LoginView.vue';
 import RegisterView from '../views/RegisterView.vue';
+import ResetPasswordView from '../views/ResetPasswordView.vue';
+import OidcCallbackView from '../views/OidcCallbackView.vue';
 import { useAuthStore } from '../stores/auth';
 
 declare module 'vue-router' {
@@ -30,6 +32,18 @@ const routes = [
     component: RegisterView,
     meta: { requiresGuest: true },
   },
+  {
+    path: '/reset-password',
+    name: 'ResetPassword',
+    component: ResetPasswordView,
+    meta: { requiresGuest: true },
+  },
+  {
+    path: '/login/oidc',
+    name: 'OidcCallback',
+    component: OidcCallbackView,
+    meta: { requiresGuest: true },
+  },
 ];
 
 const router = createRouter({
@@ -25,19 +25,26 @@ export const useAuthStore = defineStore('auth', () => {
     const token = ref<string | null>(
         storedToken && !isTokenExpired(storedToken) ? storedToken : null
     );
+
+    // The refresh token outlives the access token and is used to get a new one
+    const refreshToken = ref<string | null>(localStorage.getItem('refresh_token'));
     
     // If token was expired, clear it from localStorage
     if (storedToken && isTokenExpired(storedToken)) {
         localStorage.removeItem('jwt_token');
         localStorage.removeItem('jwt_expires_at');
-        localStorage.removeItem('user');
+        if (!refreshToken.value) {
+            localStorage.removeItem('user');
+        }
     }
     
     const user = ref<User | null>(
-        token.value ? JSON.parse(localStorage.getItem('user') || 'null') : null
+        token.value || refreshToken.value ? JSON.parse(localStorage.getItem('user') || 'null') : null
     );
 
     const isAuthenticated = computed(() => {
+        // An expired access token is fine as long as it can be refreshed
+        if (refreshToken.value) return true;
         if (!token.value) return false;
         
         // Double-check token expiration in real-time
@@ -49,7 +56,17 @@ export const useAuthStore = defineStore('auth', () => {
         return true;
     });
 
-    function setToken(newToken: string) {
+    function setRefreshToken(newRefreshToken: string) {
+        refreshToken.value = newRefreshToken;
+        localStorage.setItem('refresh_token', newRefreshToken);
+    }
+
+    function setToken(newToken: string, newRefreshToken?: string) {
+        if (newRefreshToken) {
+            setRefreshToken(newRefreshToken);
+        }
+
+
         // Verify the new token is not expired before setting it
         if (isTokenExpired(newToken)) {
             console.warn('Attempted to set an expired token');
@@ -81,14 +98,16 @@ export const useAuthStore = defineStore('auth', () => {
 
     function clearToken() {
         token.value = null;
+        refreshToken.value = null;
         user.value = null;
         localStorage.removeItem('jwt_token');
+        localStorage.removeItem('refresh_token');
         localStorage.removeItem('jwt_expires_at');
         localStorage.removeItem('user');
     }
 
     function checkTokenExpiration() {
-        if (token.value && isTokenExpired(token.value)) {
+        if (token.value && isTokenExpired(token.value) && !refreshToken.value) {
             clearToken();
             return false;
         }
@@ -97,6 +116,7 @@ export const useAuthStore = defineStore('auth', () => {
 
     return {
         token,
+        refreshToken,
         user,
         isAuthenticated,
         setToken,